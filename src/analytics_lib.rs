@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+// Ethereum mainnet produces a block every 12 seconds
+pub const DEFAULT_BLOCKS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0 / 12.0;
+
+/// Portfolio value of an account at the end of a block
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EquityPoint {
+    pub block: u64,
+    pub price: f64,  // Reference price the account was valued at
+    pub value: f64,  // In quote, net of the gas spent so far
+    pub volume: f64, // Traded since the start, in quote
+}
+
+/// How returns are sampled and annualised
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalyticsConfig {
+    pub sample_every: usize,  // Number of points between two samples
+    pub blocks_per_year: f64, // Points per year, the simulator records one per block
+    pub risk_free_rate: f64,  // Annual
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            sample_every: 1,
+            blocks_per_year: DEFAULT_BLOCKS_PER_YEAR,
+            risk_free_rate: 0.0,
+        }
+    }
+}
+
+impl AnalyticsConfig {
+    pub fn periods_per_year(&self) -> f64 {
+        self.blocks_per_year / self.sample_every.max(1) as f64
+    }
+}
+
+/// Risk and return statistics of an equity curve
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RiskReport {
+    pub total_return: f64,
+    pub annualized_return: f64, // Mean sampled return times the periods per year
+    pub volatility: f64,        // Annualised
+    pub sharpe: f64,
+    pub sortino: f64,
+    pub max_drawdown: f64,          // Fraction of the peak value
+    pub max_drawdown_duration: u64, // In blocks spent below the previous peak
+    pub turnover: f64,              // Traded volume over the average value
+}
+
+/// Per-block valuations of an account.
+///
+/// Points are pushed as the simulation goes, the drawdown is tracked on every
+/// push and the other statistics are computed on demand, so a curve can be
+/// inspected during a run as well as after it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EquityCurve {
+    pub points: Vec<EquityPoint>,
+    peak: Option<EquityPoint>,
+    max_drawdown: f64,
+    max_drawdown_duration: u64,
+}
+
+impl EquityCurve {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, point: EquityPoint) {
+        match self.peak {
+            Some(peak) if point.value < peak.value => {
+                if peak.value > 0.0 {
+                    self.max_drawdown = self.max_drawdown.max(1.0 - point.value / peak.value);
+                }
+                self.max_drawdown_duration = self.max_drawdown_duration.max(point.block - peak.block);
+            }
+            _ => self.peak = Some(point),
+        }
+        self.points.push(point);
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn max_drawdown(&self) -> f64 {
+        self.max_drawdown
+    }
+
+    /// Longest time spent below a previous peak, in blocks. A drawdown not
+    /// recovered by the last point lasts until that point.
+    pub fn max_drawdown_duration(&self) -> u64 {
+        self.max_drawdown_duration
+    }
+
+    /// Simple returns between points `sample_every` apart
+    pub fn returns(&self, sample_every: usize) -> Vec<f64> {
+        let samples: Vec<f64> = self.points
+            .iter()
+            .step_by(sample_every.max(1))
+            .map(|point| point.value)
+            .collect();
+        samples
+            .windows(2)
+            .filter(|pair| pair[0] != 0.0)
+            .map(|pair| pair[1] / pair[0] - 1.0)
+            .collect()
+    }
+
+    pub fn total_return(&self) -> f64 {
+        match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) if first.value != 0.0 => last.value / first.value - 1.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Traded volume over the average portfolio value
+    pub fn turnover(&self) -> f64 {
+        let Some(last) = self.points.last() else {
+            return 0.0;
+        };
+        let average_value = self.points.iter().map(|point| point.value).sum::<f64>() / self.points.len() as f64;
+        if average_value == 0.0 {
+            return 0.0;
+        }
+        last.volume / average_value
+    }
+
+    /// Share of the points whose reference price is within `[low, high]`,
+    /// e.g. the price grid of a Kandel
+    pub fn time_in_range(&self, low: f64, high: f64) -> f64 {
+        if self.points.is_empty() {
+            return 0.0;
+        }
+        let in_range = self.points.iter().filter(|point| point.price >= low && point.price <= high).count();
+        in_range as f64 / self.points.len() as f64
+    }
+
+    pub fn report(&self, config: &AnalyticsConfig) -> RiskReport {
+        let returns = self.returns(config.sample_every);
+        let periods_per_year = config.periods_per_year();
+        let (mean, volatility, downside) = if returns.is_empty() {
+            (0.0, 0.0, 0.0)
+        } else {
+            let n = returns.len() as f64;
+            let mean = returns.iter().sum::<f64>() / n;
+            let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n;
+            let downside = returns.iter().map(|r| r.min(0.0).powi(2)).sum::<f64>() / n;
+            (mean, variance.sqrt(), downside.sqrt())
+        };
+
+        let annualized_return = mean * periods_per_year;
+        let volatility = volatility * periods_per_year.sqrt();
+        let downside = downside * periods_per_year.sqrt();
+        let excess_return = annualized_return - config.risk_free_rate;
+        RiskReport {
+            total_return: self.total_return(),
+            annualized_return,
+            volatility,
+            sharpe: ratio(excess_return, volatility),
+            sortino: ratio(excess_return, downside),
+            max_drawdown: self.max_drawdown,
+            max_drawdown_duration: self.max_drawdown_duration,
+            turnover: self.turnover(),
+        }
+    }
+}
+
+// Ratio of a return to a risk measure, 0 when there is no risk
+fn ratio(excess_return: f64, risk: f64) -> f64 {
+    if risk > 0.0 {
+        excess_return / risk
+    } else {
+        0.0
+    }
+}
+
+/// Sorts reports from the best to the worst according to `key`
+pub fn rank_by<F>(mut reports: Vec<(String, RiskReport)>, key: F) -> Vec<(String, RiskReport)>
+where
+    F: Fn(&RiskReport) -> f64,
+{
+    reports.sort_by(|a, b| key(&b.1).total_cmp(&key(&a.1)).then_with(|| a.0.cmp(&b.0)));
+    reports
+}
+
+/// Where the PnL of an account comes from, in quote.
+///
+/// Fills are valued against the reference price of the block they happen in:
+/// what the account gained on that price is its edge, the rest of the change
+/// in value of its tokens is inventory drift.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PnlAttribution {
+    pub spread_capture: f64,  // Edge of its offers taken
+    pub taker_edge: f64,      // Edge of its market orders, net of fees
+    pub inventory_drift: f64, // Revaluation of the tokens held
+    pub gas_costs: f64,       // Counted negatively
+    pub bounties: f64,        // Received as a taker minus paid for failing offers
+}
+
+impl PnlAttribution {
+    pub fn total(&self) -> f64 {
+        self.spread_capture + self.taker_edge + self.inventory_drift + self.gas_costs + self.bounties
+    }
+}
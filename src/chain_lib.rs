@@ -1,12 +1,28 @@
 use std::fmt;
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::error_lib::LedgerError;
+
+pub const WEI_PER_GWEI: f64 = 1e9;
+pub const WEI_PER_NATIVE: f64 = 1e18;
+
+/// Id of the sub-account `vault_name` of `user_id`. Vault names cannot
+/// contain `.`, so the user is whatever comes before the last one.
+pub fn vault_id(user_id: &str, vault_name: &str) -> String {
+    format!("{}.{}", user_id, vault_name)
+}
 
 /// Represents a user/wallet in the blockchain with an ID and token native
-#[derive(Debug, Clone)]
+///
+/// A vault is a `User` too, holding funds segregated from its parent's.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
-    pub native: f64,  // Using u128 for large token amounts
-    pub balances: HashMap<String, f64>
+    pub native: f64,  // In wei
+    pub balances: HashMap<String, f64>,
+    pub gas_spent: f64, // In wei
+    pub parent: Option<String>, // Set for vaults
+    pub vaults: Vec<String>,    // Ids of the user's vaults
 }
 
 impl User {
@@ -16,9 +32,23 @@ impl User {
             id,
             native: initial_native,
             balances: HashMap::new(),
+            gas_spent: 0.0,
+            parent: None,
+            vaults: Vec::new(),
         }
     }
 
+    /// Creates an empty vault belonging to `parent`
+    pub fn new_vault(parent: &User, vault_name: &str) -> Self {
+        let mut vault = User::new(vault_id(&parent.id, vault_name), 0.0);
+        vault.parent = Some(parent.id.clone());
+        vault
+    }
+
+    pub fn is_vault(&self) -> bool {
+        self.parent.is_some()
+    }
+
     /// Returns the user's current native
     pub fn get_native_balance(&self) -> f64 {
         self.native
@@ -28,23 +58,31 @@ impl User {
         *self.balances.get(token).unwrap_or(&0.0)
     }
 
+    /// Native then token balances sorted by token, see `get_labeled_balances`
     pub fn get_balance_list(&self) -> Vec<f64> {
-        let mut balance_list = vec![self.native];
-        for (_, balance) in &self.balances {
-            balance_list.push(*balance);
+        self.get_labeled_balances().into_iter().map(|(_, balance)| balance).collect()
+    }
+
+    /// `("native", native)` followed by `(token, balance)` sorted by token
+    pub fn get_labeled_balances(&self) -> Vec<(String, f64)> {
+        let mut tokens: Vec<&String> = self.balances.keys().collect();
+        tokens.sort();
+        let mut balance_list = vec![("native".to_string(), self.native)];
+        for token in tokens {
+            balance_list.push((token.clone(), self.balances[token]));
         }
         balance_list
     }
 
 
-    pub fn add_token_balance(&mut self, token: &str, amount: f64) -> Result<(), &'static str> {
+    pub fn add_token_balance(&mut self, token: &str, amount: f64) -> Result<(), LedgerError> {
         let balance = self.balances.entry(token.to_string()).or_insert(0.0);
         let new_balance = *balance + amount;
         if new_balance.is_finite() {
             *balance = new_balance;
             Ok(())
         } else {
-            Err("Token balance overflow")
+            Err(LedgerError::BalanceOverflow { account_id: self.id.clone(), token: token.to_string() })
         }
     }
 
@@ -59,35 +97,48 @@ impl User {
     }
 
     /// Removes tokens from the user's native if sufficient funds exist
-    pub fn spend_native(&mut self, amount: f64) -> Result<(), &'static str> {
+    pub fn spend_native(&mut self, amount: f64) -> Result<(), LedgerError> {
         if self.native >= amount {
             let new_balance = self.native - amount;
             if new_balance.is_finite() {
                 self.native = new_balance;
                 Ok(())
             } else {
-                Err("Invalid native balance after subtraction")
+                Err(LedgerError::BalanceOverflow { account_id: self.id.clone(), token: "native".to_string() })
             }
         } else {
-            Err("Insufficient Gas Funds")
+            Err(LedgerError::InsufficientNative {
+                account_id: self.id.clone(),
+                needed: amount,
+                available: self.native,
+            })
         }
     }
 
-    pub fn spend_token_balance(&mut self, token: &str, amount: f64) -> Result<(), &'static str> {
-        let balance = self.balances.get(token).unwrap_or(&0.0);
-        // println!("Debug: User {} attempting to spend {} {}", self.id, amount, token);
-        // println!("Debug: Current balance: {}", balance);
-        if *balance >= amount {
-            let new_balance = *balance - amount;
+    /// Pays `amount` wei of gas out of the user's native and keeps track of it
+    pub fn pay_gas(&mut self, amount: f64) -> Result<(), LedgerError> {
+        self.spend_native(amount)?;
+        self.gas_spent += amount;
+        Ok(())
+    }
+
+    pub fn spend_token_balance(&mut self, token: &str, amount: f64) -> Result<(), LedgerError> {
+        let balance = *self.balances.get(token).unwrap_or(&0.0);
+        if balance >= amount {
+            let new_balance = balance - amount;
             if new_balance.is_finite() {
                 self.balances.insert(token.to_string(), new_balance);
                 Ok(())
             } else {
-                Err("Invalid token balance after subtraction")
+                Err(LedgerError::BalanceOverflow { account_id: self.id.clone(), token: token.to_string() })
             }
         } else {
-
-            Err("Insufficient token balance for user")
+            Err(LedgerError::InsufficientBalance {
+                account_id: self.id.clone(),
+                token: token.to_string(),
+                needed: amount,
+                available: balance,
+            })
         }
     }
 }
@@ -0,0 +1,445 @@
+use crate::analytics_lib::{EquityCurve, PnlAttribution};
+use crate::chain_lib::User;
+use crate::error_lib::{ConfigError, Error, IoError, StrategyError};
+use crate::gas_lib::GasSchedule;
+use crate::mgv_lib::{Market, Offer, OfferSide, OrderSide};
+use crate::simu_lib::{
+    FailedAction, GasExhaustionEvent, GasPricePoint, PendingAction, PerformanceMetrics, PriceFeed, PricePoint, ScheduledStrategy,
+    Simulator, TriggerState, DEFAULT_BLOCK_TIME,
+};
+use crate::strats_lib::{Event, Intent, Strategy, StrategyFactory};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Bumped whenever the checkpoint layout changes
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+/// A strategy's state, rebuilt from the factory then loaded back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyState {
+    pub factory_name: String,
+    pub state: serde_json::Value,
+}
+
+impl StrategyState {
+    fn save(strategy: &dyn Strategy) -> Result<Self, Error> {
+        let factory_name = strategy
+            .factory_name()
+            .ok_or_else(|| StrategyError::NotCheckpointable(strategy.name().to_string()))?;
+        Ok(Self {
+            factory_name: factory_name.to_string(),
+            state: strategy.save_state()?,
+        })
+    }
+
+    fn restore(&self, factory: &StrategyFactory) -> Result<Box<dyn Strategy>, Error> {
+        factory.restore_strategy(&self.factory_name, &self.state)
+    }
+}
+
+/// An offer of the book, its strategy indexes `Checkpoint::book_strategies`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfferState {
+    pub id: u64,
+    pub maker_id: String,
+    pub side: OfferSide,
+    pub price: f64,
+    pub volume: f64,
+    pub gasreq: u128,
+    pub strategy: usize,
+    #[serde(default)]
+    pub strategy_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingActionState {
+    pub strategy_id: String,
+    pub account_id: String,
+    pub decision_point: PricePoint,
+    pub decided_at: u64,
+    pub execute_at: u64,
+    pub events: Vec<Event>,
+    pub intents: Vec<IntentState>,
+}
+
+/// An intent of a pending action, the strategy of a post indexes
+/// `Checkpoint::book_strategies`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum IntentState {
+    Post {
+        offer_id: u64,
+        side: OfferSide,
+        price: f64,
+        volume: f64,
+        gasreq: u128,
+        strategy: usize,
+    },
+    Update { offer_id: u64, price: f64, volume: f64 },
+    Retract { offer_id: u64 },
+    Take {
+        side: OrderSide,
+        volume: f64,
+        limit_price: f64,
+        #[serde(default)]
+        hedge_price: Option<f64>,
+    },
+}
+
+/// Position of the simulation RNG in its stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RngState {
+    pub seed: [u8; 32],
+    pub stream: u64,
+    pub word_pos: u128,
+}
+
+/// Everything needed to carry on a simulation from `current_block`.
+///
+/// The price feed is not saved: it is given back on restore, which also
+/// allows branching a run on another feed. Outputs are not saved either, the
+/// restored simulator writes nothing until it is given sinks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub current_block: u64,
+    pub base: String,
+    pub quote: String,
+    pub gas_schedule: GasSchedule,
+    pub gas_price: f64,
+    pub fee_bps: f64,
+    pub next_offer_id: u64,
+    pub offers: Vec<OfferState>,           // Bids then asks, in book order
+    pub book_strategies: Vec<StrategyState>, // One per strategy shared by the offers
+    pub users: Vec<User>,
+    pub strategies: BTreeMap<String, StrategyState>,
+    pub user_strategies: BTreeMap<String, Vec<String>>,
+    pub schedule: Vec<ScheduledStrategy>,
+    pub trigger_states: BTreeMap<String, TriggerState>,
+    pub strategy_latency: BTreeMap<String, u64>,
+    pub pending_actions: Vec<PendingActionState>,
+    pub failed_actions: Vec<FailedAction>,
+    pub gas_price_feed: Vec<GasPricePoint>,
+    pub gas_price_index: usize,
+    pub native_price: Option<f64>,
+    pub halted_strategies: Vec<String>,
+    pub gas_exhaustion_events: Vec<GasExhaustionEvent>,
+    pub seed: u64,
+    #[serde(default)]
+    pub start_timestamp: u64,
+    #[serde(default = "default_block_time")]
+    pub block_time: u64,
+    pub rng: RngState,
+    pub performance_metrics: BTreeMap<String, PerformanceMetrics>,
+    pub equity_curves: BTreeMap<String, EquityCurve>,
+    #[serde(default = "default_record_equity_curves")]
+    pub record_equity_curves: bool,
+    pub pnl_attribution: BTreeMap<String, PnlAttribution>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |error: io::Error| IoError::new(format!("writing checkpoint {}", path.display()), &error);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let mut file = BufWriter::new(File::create(path).map_err(io_error)?);
+        serde_json::to_writer(&mut file, self).map_err(|error| io_error(error.into()))?;
+        Ok(file.flush().map_err(io_error)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|error| IoError::new(format!("reading checkpoint {}", path.display()), &error))?;
+        let checkpoint: Self = serde_json::from_reader(BufReader::new(file))
+            .map_err(|error| ConfigError::InvalidCheckpoint(error.to_string()))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(ConfigError::UnsupportedCheckpointVersion {
+                found: checkpoint.version,
+                supported: CHECKPOINT_VERSION,
+            }
+            .into());
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn default_block_time() -> u64 {
+    DEFAULT_BLOCK_TIME
+}
+
+fn default_record_equity_curves() -> bool {
+    true
+}
+
+fn sorted<V: Clone>(map: &HashMap<String, V>) -> BTreeMap<String, V> {
+    map.iter().map(|(key, value)| (key.clone(), value.clone())).collect()
+}
+
+impl Simulator {
+    /// Snapshot of the simulation at the current block. Fails if a strategy,
+    /// registered or behind an offer, does not support checkpoints.
+    pub fn checkpoint(&self) -> Result<Checkpoint, Error> {
+        let mut strategies = BTreeMap::new();
+        for (strategy_id, strategy) in &self.strategies {
+            strategies.insert(strategy_id.clone(), StrategyState::save(strategy.as_ref())?);
+        }
+
+        // Offers of a Kandel share one strategy, save it once
+        let mut book_strategies = Vec::new();
+        let mut strategy_indexes: HashMap<*const Mutex<Box<dyn Strategy>>, usize> = HashMap::new();
+        let mut save_book_strategy = |strategy: &Arc<Mutex<Box<dyn Strategy>>>| -> Result<usize, Error> {
+            let key = Arc::as_ptr(strategy);
+            if let Some(index) = strategy_indexes.get(&key) {
+                return Ok(*index);
+            }
+            book_strategies.push(StrategyState::save(strategy.lock().unwrap().as_ref())?);
+            strategy_indexes.insert(key, book_strategies.len() - 1);
+            Ok(book_strategies.len() - 1)
+        };
+        let mut offers = Vec::new();
+        for offer in self.market.bids.iter().chain(self.market.asks.iter()) {
+            let strategy = save_book_strategy(&offer.strategy)?;
+            offers.push(OfferState {
+                id: offer.id,
+                maker_id: offer.maker.lock().unwrap().id.clone(),
+                side: offer.side,
+                price: offer.price,
+                volume: offer.volume,
+                gasreq: offer.gasreq,
+                strategy,
+                strategy_id: offer.strategy_id.clone(),
+            });
+        }
+        // The offers a pending action will post are saved with their strategy too
+        let mut pending_actions = Vec::new();
+        for action in &self.pending_actions {
+            let mut intents = Vec::new();
+            for intent in &action.intents {
+                intents.push(match intent {
+                    Intent::Post { offer_id, side, price, volume, gasreq, strategy } => IntentState::Post {
+                        offer_id: *offer_id,
+                        side: *side,
+                        price: *price,
+                        volume: *volume,
+                        gasreq: *gasreq,
+                        strategy: save_book_strategy(strategy)?,
+                    },
+                    Intent::Update { offer_id, price, volume } => IntentState::Update { offer_id: *offer_id, price: *price, volume: *volume },
+                    Intent::Retract { offer_id } => IntentState::Retract { offer_id: *offer_id },
+                    Intent::Take { side, volume, limit_price, hedge_price } => IntentState::Take {
+                        side: *side,
+                        volume: *volume,
+                        limit_price: *limit_price,
+                        hedge_price: *hedge_price,
+                    },
+                });
+            }
+            pending_actions.push(PendingActionState {
+                strategy_id: action.strategy_id.clone(),
+                account_id: action.user.lock().unwrap().id.clone(),
+                decision_point: action.decision_point,
+                decided_at: action.decided_at,
+                execute_at: action.execute_at,
+                events: action.events.clone(),
+                intents,
+            });
+        }
+
+        let mut users: Vec<User> = self.users.values().map(|user| user.lock().unwrap().clone()).collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut halted_strategies: Vec<String> = self.halted_strategies.iter().cloned().collect();
+        halted_strategies.sort();
+
+        Ok(Checkpoint {
+            version: CHECKPOINT_VERSION,
+            current_block: self.current_block,
+            base: self.market.base.clone(),
+            quote: self.market.quote.clone(),
+            gas_schedule: self.market.gas_schedule.clone(),
+            gas_price: self.market.gas_price,
+            fee_bps: self.market.fee_bps,
+            next_offer_id: self.market.next_offer_id(),
+            offers,
+            book_strategies,
+            users,
+            strategies,
+            user_strategies: sorted(&self.user_strategies),
+            schedule: self.schedule.clone(),
+            trigger_states: sorted(&self.trigger_states),
+            strategy_latency: sorted(&self.strategy_latency),
+            pending_actions,
+            failed_actions: self.failed_actions.clone(),
+            gas_price_feed: self.gas_price_feed.clone(),
+            gas_price_index: self.gas_price_index,
+            native_price: self.native_price,
+            halted_strategies,
+            gas_exhaustion_events: self.gas_exhaustion_events.clone(),
+            seed: self.seed,
+            start_timestamp: self.start_timestamp,
+            block_time: self.block_time,
+            rng: RngState {
+                seed: self.market.rng.get_seed(),
+                stream: self.market.rng.get_stream(),
+                word_pos: self.market.rng.get_word_pos(),
+            },
+            performance_metrics: sorted(&self.performance_metrics),
+            equity_curves: sorted(&self.equity_curves),
+            record_equity_curves: self.record_equity_curves,
+            pnl_attribution: sorted(&self.pnl_attribution),
+        })
+    }
+
+    pub fn save_checkpoint(&self, path: &Path) -> Result<(), Error> {
+        self.checkpoint()?.save(path)
+    }
+
+    /// Rebuilds a simulator from a checkpoint, its strategies are created by
+    /// `factory` then loaded with their saved state.
+    ///
+    /// `price_feed` must cover the checkpointed blocks, the blocks after them
+    /// may differ from the original run. A streamed feed is read up to the
+    /// checkpoint.
+    ///
+    /// Outputs are not part of the checkpoint: the restored simulator writes
+    /// nothing until it is given sinks, see `set_output_dir` and `add_sink`.
+    pub fn from_checkpoint(
+        checkpoint: Checkpoint,
+        price_feed: impl Into<PriceFeed>,
+        factory: &StrategyFactory,
+    ) -> Result<Self, Error> {
+        let mut price_feed: PriceFeed = price_feed.into();
+        let price_point = match checkpoint.current_block.checked_sub(1) {
+            Some(last_block) => Some(price_feed.point(last_block)?.ok_or_else(|| ConfigError::PriceFeedTooShort {
+                needed: checkpoint.current_block,
+                available: price_feed.len().unwrap_or(0),
+            })?),
+            None => None,
+        };
+
+        let mut market = Market::with_gas_schedule(checkpoint.base, checkpoint.quote, checkpoint.gas_schedule);
+        market.gas_price = checkpoint.gas_price;
+        market.fee_bps = checkpoint.fee_bps;
+        let mut simulator = Simulator::new(market, price_feed);
+        simulator.clear_sinks();
+        simulator.current_block = checkpoint.current_block;
+        simulator.price_point = price_point;
+
+        for user in checkpoint.users {
+            simulator.users.insert(user.id.clone(), Arc::new(Mutex::new(user)));
+        }
+        let account = |account_id: &str| simulator.users.get(account_id).map(Arc::clone).ok_or_else(|| {
+            ConfigError::InvalidCheckpoint(format!("unknown account {}", account_id))
+        });
+
+        let book_strategies = checkpoint
+            .book_strategies
+            .iter()
+            .map(|state| state.restore(factory).map(|strategy| Arc::new(Mutex::new(strategy))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let book_strategy = |index: usize, offer_id: u64| book_strategies.get(index).map(Arc::clone).ok_or_else(|| {
+            ConfigError::InvalidCheckpoint(format!("unknown strategy of offer {}", offer_id))
+        });
+        let mut offers = Vec::new();
+        for offer in checkpoint.offers {
+            offers.push(Offer {
+                id: offer.id,
+                maker: account(&offer.maker_id)?,
+                side: offer.side,
+                price: offer.price,
+                volume: offer.volume,
+                gasreq: offer.gasreq,
+                strategy: book_strategy(offer.strategy, offer.id)?,
+                strategy_id: offer.strategy_id,
+            });
+        }
+        let mut pending_actions = Vec::new();
+        for action in checkpoint.pending_actions {
+            let mut intents = Vec::new();
+            for intent in action.intents {
+                intents.push(match intent {
+                    IntentState::Post { offer_id, side, price, volume, gasreq, strategy } => Intent::Post {
+                        offer_id,
+                        side,
+                        price,
+                        volume,
+                        gasreq,
+                        strategy: book_strategy(strategy, offer_id)?,
+                    },
+                    IntentState::Update { offer_id, price, volume } => Intent::Update { offer_id, price, volume },
+                    IntentState::Retract { offer_id } => Intent::Retract { offer_id },
+                    IntentState::Take { side, volume, limit_price, hedge_price } => {
+                        Intent::Take { side, volume, limit_price, hedge_price }
+                    }
+                });
+            }
+            pending_actions.push(PendingAction {
+                user: account(&action.account_id)?,
+                strategy_id: action.strategy_id,
+                decision_point: action.decision_point,
+                decided_at: action.decided_at,
+                execute_at: action.execute_at,
+                events: action.events,
+                intents,
+            });
+        }
+        for offer in offers {
+            simulator.market.restore_offer(offer, checkpoint.next_offer_id);
+        }
+        simulator.pending_actions = pending_actions.into();
+
+        for (strategy_id, state) in &checkpoint.strategies {
+            simulator.strategies.insert(strategy_id.clone(), state.restore(factory)?);
+        }
+        simulator.user_strategies = checkpoint.user_strategies.into_iter().collect();
+        simulator.schedule = checkpoint.schedule;
+        simulator.trigger_states = checkpoint.trigger_states.into_iter().collect();
+        simulator.strategy_latency = checkpoint.strategy_latency.into_iter().collect();
+        simulator.failed_actions = checkpoint.failed_actions;
+        simulator.gas_price_feed = checkpoint.gas_price_feed;
+        simulator.gas_price_index = checkpoint.gas_price_index;
+        simulator.native_price = checkpoint.native_price;
+        simulator.halted_strategies = checkpoint.halted_strategies.into_iter().collect();
+        simulator.gas_exhaustion_events = checkpoint.gas_exhaustion_events;
+
+        simulator.seed = checkpoint.seed;
+        simulator.set_block_time(checkpoint.start_timestamp, checkpoint.block_time);
+        if let Some(last_block) = checkpoint.current_block.checked_sub(1) {
+            simulator.set_market_block(last_block);
+        }
+        simulator.market.rng = ChaCha8Rng::from_seed(checkpoint.rng.seed);
+        simulator.market.rng.set_stream(checkpoint.rng.stream);
+        simulator.market.rng.set_word_pos(checkpoint.rng.word_pos);
+
+        simulator.performance_metrics = checkpoint.performance_metrics.into_iter().collect();
+        simulator.equity_curves = checkpoint.equity_curves.into_iter().collect();
+        simulator.record_equity_curves = checkpoint.record_equity_curves;
+        simulator.pnl_attribution = checkpoint.pnl_attribution.into_iter().collect();
+        Ok(simulator)
+    }
+
+    pub fn load_checkpoint(
+        path: &Path,
+        price_feed: impl Into<PriceFeed>,
+        factory: &StrategyFactory,
+    ) -> Result<Self, Error> {
+        Self::from_checkpoint(Checkpoint::load(path)?, price_feed, factory)
+    }
+
+    /// Saves a checkpoint to `path` every `every` blocks while running,
+    /// overwriting the previous one
+    pub fn set_checkpointing(&mut self, every: u64, path: impl Into<PathBuf>) {
+        self.checkpointing = Some((every.max(1), path.into()));
+    }
+
+    pub(crate) fn auto_checkpoint(&self) -> Result<(), Error> {
+        match &self.checkpointing {
+            Some((every, path)) if self.current_block.is_multiple_of(*every) => self.save_checkpoint(path),
+            _ => Ok(()),
+        }
+    }
+}
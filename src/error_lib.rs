@@ -0,0 +1,333 @@
+use crate::mgv_lib::OrderSide;
+use std::fmt;
+use std::io;
+
+////////////////////////
+// Ledger
+///////////////////////
+
+/// Failures of the balances of an account
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerError {
+    InsufficientBalance {
+        account_id: String,
+        token: String,
+        needed: f64,
+        available: f64,
+    },
+    /// Not enough native to pay for gas or a bounty, in wei
+    InsufficientNative {
+        account_id: String,
+        needed: f64,
+        available: f64,
+    },
+    BalanceOverflow {
+        account_id: String,
+        token: String, // "native" for the native balance
+    },
+    NegativeAmount(f64),
+    UnknownAccount(String),
+    VaultExists(String),
+    NestedVault(String), // Id of the vault that was asked for a vault
+    InvalidVaultName(String),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientBalance { account_id, token, needed, available } => write!(
+                f,
+                "{} needs {} {} but holds {}",
+                account_id, needed, token, available
+            ),
+            Self::InsufficientNative { account_id, needed, available } => write!(
+                f,
+                "{} needs {} wei of native but holds {}",
+                account_id, needed, available
+            ),
+            Self::BalanceOverflow { account_id, token } => write!(f, "{} balance of {} overflowed", token, account_id),
+            Self::NegativeAmount(amount) => write!(f, "Amount must be positive, got {}", amount),
+            Self::UnknownAccount(account_id) => write!(f, "Account {} not found", account_id),
+            Self::VaultExists(vault_id) => write!(f, "Vault {} already exists", vault_id),
+            Self::NestedVault(vault_id) => write!(f, "Vault {} cannot have vaults", vault_id),
+            Self::InvalidVaultName(vault_name) => write!(f, "Vault name {} cannot be empty nor contain '.'", vault_name),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+////////////////////////
+// Market
+///////////////////////
+
+/// Failures of the order book
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketError {
+    InsufficientLiquidity {
+        side: OrderSide,
+        requested: f64,
+        available: f64, // Base volume of the whole side
+    },
+    /// A delayed order would fill beyond the worst price seen when it was decided
+    PriceMoved {
+        side: OrderSide,
+        limit_price: f64,
+        price: f64,
+    },
+    OfferNotFound(u64),
+    NotOfferOwner {
+        offer_id: u64,
+        account_id: String,
+    },
+    Ledger(LedgerError),
+    /// The post hook of a taken offer failed, aborting the market order
+    PostHook {
+        offer_id: u64,
+        source: Box<Error>,
+    },
+}
+
+impl fmt::Display for MarketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientLiquidity { side, requested, available } => write!(
+                f,
+                "Insufficient liquidity: {:?} order of {} but only {} on the book",
+                side, requested, available
+            ),
+            Self::PriceMoved { side, limit_price, price } => write!(
+                f,
+                "Price moved: {:?} order limited to {} would fill at {}",
+                side, limit_price, price
+            ),
+            Self::OfferNotFound(offer_id) => write!(f, "Offer {} not found", offer_id),
+            Self::NotOfferOwner { offer_id, account_id } => {
+                write!(f, "Offer {} does not belong to {}", offer_id, account_id)
+            }
+            Self::Ledger(error) => write!(f, "{}", error),
+            Self::PostHook { offer_id, source } => write!(f, "Post hook of offer {} failed: {}", offer_id, source),
+        }
+    }
+}
+
+impl std::error::Error for MarketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Ledger(error) => error.source(), // Displayed as is
+            Self::PostHook { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<LedgerError> for MarketError {
+    fn from(error: LedgerError) -> Self {
+        Self::Ledger(error)
+    }
+}
+
+////////////////////////
+// Strategy
+///////////////////////
+
+/// Failures of the strategies and of their registration
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrategyError {
+    UnknownStrategy(String),
+    UnknownParameter(String),
+    AlreadyAssigned(String),
+    NotCheckpointable(String), // Name of the strategy
+    InvalidState(String),      // Why the saved state could not be read
+}
+
+impl fmt::Display for StrategyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownStrategy(strategy_id) => write!(f, "Strategy {} not found", strategy_id),
+            Self::UnknownParameter(name) => write!(f, "Unknown parameter {}", name),
+            Self::AlreadyAssigned(strategy_id) => write!(f, "Strategy {} already assigned to an account", strategy_id),
+            Self::NotCheckpointable(name) => write!(f, "{} cannot be checkpointed", name),
+            Self::InvalidState(reason) => write!(f, "Invalid strategy state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StrategyError {}
+
+////////////////////////
+// Config
+///////////////////////
+
+/// Invalid settings of a strategy, a model, a scenario or a saved file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    InvalidParameter {
+        name: String,
+        reason: String,
+    },
+    InvalidModel(String),
+    InvalidScenario(String),
+    InvalidFeed(String),
+    InvalidCheckpoint(String),
+    InvalidSummary(String),
+    UnsupportedCheckpointVersion {
+        found: u32,
+        supported: u32,
+    },
+    PriceFeedTooShort {
+        needed: u64,
+        available: usize,
+    },
+    ThreadPool(String),
+}
+
+impl ConfigError {
+    pub fn invalid_parameter(name: &str, reason: &str) -> Self {
+        Self::InvalidParameter { name: name.to_string(), reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParameter { name, reason } => write!(f, "Invalid {}: {}", name, reason),
+            Self::InvalidModel(reason) => write!(f, "Invalid model: {}", reason),
+            Self::InvalidScenario(reason) => write!(f, "Invalid scenario: {}", reason),
+            Self::InvalidFeed(reason) => write!(f, "Invalid feed: {}", reason),
+            Self::InvalidCheckpoint(reason) => write!(f, "Invalid checkpoint: {}", reason),
+            Self::InvalidSummary(reason) => write!(f, "Invalid run summary: {}", reason),
+            Self::UnsupportedCheckpointVersion { found, supported } => write!(
+                f,
+                "Unsupported checkpoint version {}, expected {}",
+                found, supported
+            ),
+            Self::PriceFeedTooShort { needed, available } => write!(
+                f,
+                "Price feed has {} points, the checkpoint needs {}",
+                available, needed
+            ),
+            Self::ThreadPool(reason) => write!(f, "Failed to build the thread pool: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+////////////////////////
+// I/O
+///////////////////////
+
+/// A failed read or write, `io::Error` is neither `Clone` nor `PartialEq`
+/// so its kind and message are kept instead
+#[derive(Debug, Clone, PartialEq)]
+pub struct IoError {
+    pub context: String, // What was being done, e.g. "writing outputs"
+    pub kind: io::ErrorKind,
+    pub message: String,
+}
+
+impl IoError {
+    pub fn new(context: impl Into<String>, error: &io::Error) -> Self {
+        Self {
+            context: context.into(),
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "I/O error while {}: {}", self.context, self.message)
+    }
+}
+
+impl std::error::Error for IoError {}
+
+////////////////////////
+// Error
+///////////////////////
+
+/// Any error of the simulator
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Ledger(LedgerError),
+    Market(MarketError),
+    Strategy(StrategyError),
+    Config(ConfigError),
+    Io(IoError),
+}
+
+impl Error {
+    /// Whether an account could not pay for gas, the simulator halts its
+    /// strategies instead of failing
+    pub fn is_out_of_gas(&self) -> bool {
+        self.out_of_gas_account().is_some()
+    }
+
+    /// The account that could not pay for gas
+    pub fn out_of_gas_account(&self) -> Option<&str> {
+        match self {
+            Self::Ledger(LedgerError::InsufficientNative { account_id, .. })
+            | Self::Market(MarketError::Ledger(LedgerError::InsufficientNative { account_id, .. })) => Some(account_id),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ledger(error) => write!(f, "{}", error),
+            Self::Market(error) => write!(f, "{}", error),
+            Self::Strategy(error) => write!(f, "{}", error),
+            Self::Config(error) => write!(f, "{}", error),
+            Self::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// Transparent: the wrapped error is displayed as is, so the chain goes on
+// with its own source
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Ledger(error) => error.source(),
+            Self::Market(error) => error.source(),
+            Self::Strategy(error) => error.source(),
+            Self::Config(error) => error.source(),
+            Self::Io(error) => error.source(),
+        }
+    }
+}
+
+impl From<LedgerError> for Error {
+    fn from(error: LedgerError) -> Self {
+        Self::Ledger(error)
+    }
+}
+
+impl From<MarketError> for Error {
+    fn from(error: MarketError) -> Self {
+        Self::Market(error)
+    }
+}
+
+impl From<StrategyError> for Error {
+    fn from(error: StrategyError) -> Self {
+        Self::Strategy(error)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        Self::Config(error)
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(error: IoError) -> Self {
+        Self::Io(error)
+    }
+}
@@ -0,0 +1,249 @@
+use crate::error_lib::Error;
+use crate::simu_lib::{PricePoint, Simulator};
+use crate::sweep_lib::run_parallel;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+// Two-sided 95% quantile of the normal distribution
+pub const Z_95: f64 = 1.96;
+
+/// Summary statistics of a metric over the runs of an experiment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub p05: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub max: f64,
+    pub ci_low: f64,  // 95% confidence interval of the mean
+    pub ci_high: f64,
+}
+
+impl Distribution {
+    pub fn from_samples(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        // Sample standard deviation, 0 for a single run
+        let std_dev = if count > 1 {
+            (sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        let half_width = Z_95 * std_dev / (count as f64).sqrt();
+        Some(Self {
+            count,
+            mean,
+            std_dev,
+            min: sorted[0],
+            p05: quantile(&sorted, 0.05),
+            p25: quantile(&sorted, 0.25),
+            median: quantile(&sorted, 0.5),
+            p75: quantile(&sorted, 0.75),
+            p95: quantile(&sorted, 0.95),
+            max: sorted[count - 1],
+            ci_low: mean - half_width,
+            ci_high: mean + half_width,
+        })
+    }
+}
+
+/// Quantile `q` of sorted samples, interpolated linearly between two samples
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+/// Outcome of one account on one path
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunRow {
+    pub run: usize,
+    pub seed: u64,
+    pub account_id: String,
+    pub pnl: f64,
+    pub final_value: f64,
+    pub max_drawdown: f64,
+    pub fills: u64,
+    pub gas_spent_quote: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunFailure {
+    pub run: usize,
+    pub seed: u64,
+    pub reason: Error,
+}
+
+type RunMetric = fn(&RunRow) -> f64;
+
+/// Distribution of a metric of an account over all the runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateRow {
+    pub account_id: String,
+    pub metric: &'static str,
+    pub distribution: Distribution,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExperimentResults {
+    pub runs: Vec<RunRow>,
+    pub failures: Vec<RunFailure>,
+}
+
+impl ExperimentResults {
+    /// Distributions of the PnL, max drawdown and fills of every account
+    pub fn aggregate(&self) -> Vec<AggregateRow> {
+        let mut account_ids: Vec<&String> = self.runs.iter().map(|row| &row.account_id).collect();
+        account_ids.sort();
+        account_ids.dedup();
+
+        let metrics: [(&'static str, RunMetric); 3] = [
+            ("pnl", |row| row.pnl),
+            ("max_drawdown", |row| row.max_drawdown),
+            ("fills", |row| row.fills as f64),
+        ];
+        let mut rows = Vec::new();
+        for account_id in account_ids {
+            let runs: Vec<&RunRow> = self.runs.iter().filter(|row| &row.account_id == account_id).collect();
+            for (metric, value) in metrics {
+                let samples: Vec<f64> = runs.iter().map(|row| value(row)).collect();
+                if let Some(distribution) = Distribution::from_samples(&samples) {
+                    rows.push(AggregateRow { account_id: account_id.clone(), metric, distribution });
+                }
+            }
+        }
+        rows
+    }
+
+    pub fn write_runs_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = create_csv(path)?;
+        writeln!(file, "run,seed,account,pnl,final_value,max_drawdown,fills,gas_quote")?;
+        for row in &self.runs {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{}",
+                row.run, row.seed, row.account_id, row.pnl, row.final_value, row.max_drawdown, row.fills, row.gas_spent_quote
+            )?;
+        }
+        file.flush()
+    }
+
+    pub fn write_aggregate_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = create_csv(path)?;
+        writeln!(file, "account,metric,count,mean,std_dev,min,p05,p25,median,p75,p95,max,ci_low,ci_high")?;
+        for row in self.aggregate() {
+            let d = row.distribution;
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                row.account_id, row.metric, d.count, d.mean, d.std_dev, d.min, d.p05, d.p25, d.median, d.p75, d.p95, d.max,
+                d.ci_low, d.ci_high
+            )?;
+        }
+        file.flush()
+    }
+}
+
+fn create_csv(path: &Path) -> io::Result<BufWriter<File>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+/// Runs one strategy configuration over `n_paths` seeded price paths.
+///
+/// The i-th path is generated with the seed `seed + i`, which also seeds the
+/// simulator of that run. Runs are executed in parallel, their outputs are
+/// removed unless `keep_outputs` is set.
+pub struct MonteCarlo {
+    pub n_paths: usize,
+    pub seed: u64,
+    pub threads: Option<usize>, // Defaults to one per core
+    pub keep_outputs: bool,
+}
+
+impl MonteCarlo {
+    pub fn new(n_paths: usize, seed: u64) -> Self {
+        Self {
+            n_paths,
+            seed,
+            threads: None,
+            keep_outputs: false,
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub fn run<P, B>(&self, path: P, build: B) -> Result<ExperimentResults, Error>
+    where
+        P: Fn(u64) -> Vec<PricePoint> + Sync,
+        B: Fn(Arc<[PricePoint]>) -> Result<Simulator, Error> + Sync,
+    {
+        let results = run_parallel(self.threads, self.n_paths, |run| {
+            let seed = self.seed.wrapping_add(run as u64);
+            self.run_one(run, seed, path(seed).into(), &build)
+                .map_err(|reason| RunFailure { run, seed, reason })
+        })?;
+
+        let mut experiment = ExperimentResults::default();
+        for result in results {
+            match result {
+                Ok(rows) => experiment.runs.extend(rows),
+                Err(failure) => experiment.failures.push(failure),
+            }
+        }
+        Ok(experiment)
+    }
+
+    fn run_one<B>(&self, run: usize, seed: u64, price_feed: Arc<[PricePoint]>, build: &B) -> Result<Vec<RunRow>, Error>
+    where
+        B: Fn(Arc<[PricePoint]>) -> Result<Simulator, Error>,
+    {
+        let mut simulator = build(price_feed)?;
+        simulator.set_seed(seed);
+        if !self.keep_outputs {
+            simulator.clear_sinks();
+            simulator.disable_book_snapshots();
+        }
+        simulator.run_simulation(false, false)?;
+
+        let mut account_ids: Vec<&String> = simulator.performance_metrics.keys().collect();
+        account_ids.sort();
+        Ok(account_ids
+            .into_iter()
+            .map(|account_id| {
+                let metrics = &simulator.performance_metrics[account_id];
+                RunRow {
+                    run,
+                    seed,
+                    account_id: account_id.clone(),
+                    pnl: metrics.total_profit_loss,
+                    final_value: metrics.current_balance,
+                    max_drawdown: simulator.equity_curves.get(account_id).map_or(0.0, |curve| curve.max_drawdown()),
+                    fills: metrics.total_trades,
+                    gas_spent_quote: metrics.gas_spent_quote,
+                }
+            })
+            .collect())
+    }
+}
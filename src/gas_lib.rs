@@ -0,0 +1,148 @@
+use crate::chain_lib::WEI_PER_GWEI;
+use serde::{Deserialize, Serialize};
+
+// Gas used by one byte of non-zero calldata on L1
+const L1_GAS_PER_BYTE: f64 = 16.0;
+
+/// Fee an L2 pays to post its transaction data on L1
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct L1DataFee {
+    pub bytes_per_write: u64, // Calldata of an offer write/update/retract
+    pub bytes_per_take: u64,  // Calldata of a market order
+    pub l1_gas_price: f64,    // In gwei
+    pub scalar: f64,          // Compression and fee scalar of the rollup
+}
+
+impl L1DataFee {
+    /// Cost in wei of posting `bytes` of calldata on L1
+    pub fn cost(&self, bytes: u64) -> f64 {
+        bytes as f64 * L1_GAS_PER_BYTE * self.l1_gas_price * WEI_PER_GWEI * self.scalar
+    }
+}
+
+/// Gas costs of Mangrove operations on a given deployment
+///
+/// All costs are in gas units, the market gas price converts them to wei.
+/// Chain profiles are indicative values and should be checked against the
+/// deployed contracts. Other chains can be simulated by building a schedule
+/// from their measured costs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GasSchedule {
+    pub name: String,
+    pub offer_write: u128,
+    pub offer_update: u128,
+    pub offer_retract: u128,
+    pub taker_gasbase: u128,  // Fixed cost of a market order
+    pub offer_gasbase: u128,  // Overhead of executing one offer in a market order
+    pub posthook: u128,       // Repost of an offer from its posthook, paid by the maker
+    pub default_gasreq: u128, // Gas requirement strategies give to their offers
+    pub default_gas_price: f64, // In gwei
+    pub l1_data_fee: Option<L1DataFee>,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            offer_write: 200_000,
+            offer_update: 200_000,
+            offer_retract: 100_000,
+            taker_gasbase: 0,
+            offer_gasbase: 0,
+            posthook: 200_000,
+            default_gasreq: 100_000,
+            default_gas_price: 1.0,
+            l1_data_fee: None,
+        }
+    }
+}
+
+impl GasSchedule {
+    /// Indicative values, see `GasSchedule`
+    pub fn arbitrum() -> Self {
+        Self {
+            name: "arbitrum".to_string(),
+            offer_write: 180_000,
+            offer_update: 60_000,
+            offer_retract: 40_000,
+            taker_gasbase: 80_000,
+            offer_gasbase: 30_000,
+            posthook: 80_000,
+            default_gasreq: 150_000,
+            default_gas_price: 0.01,
+            l1_data_fee: Some(L1DataFee {
+                bytes_per_write: 100,
+                bytes_per_take: 140,
+                l1_gas_price: 20.0,
+                scalar: 0.6,
+            }),
+        }
+    }
+
+    /// Indicative values, see `GasSchedule`
+    pub fn blast() -> Self {
+        Self {
+            name: "blast".to_string(),
+            offer_write: 180_000,
+            offer_update: 60_000,
+            offer_retract: 40_000,
+            taker_gasbase: 80_000,
+            offer_gasbase: 30_000,
+            posthook: 80_000,
+            default_gasreq: 150_000,
+            default_gas_price: 0.005,
+            l1_data_fee: Some(L1DataFee {
+                bytes_per_write: 100,
+                bytes_per_take: 140,
+                l1_gas_price: 20.0,
+                scalar: 0.7,
+            }),
+        }
+    }
+
+    /// Indicative values, see `GasSchedule`. Gas is paid in POL on Polygon:
+    /// set the simulator native price accordingly
+    pub fn polygon() -> Self {
+        Self {
+            name: "polygon".to_string(),
+            offer_write: 180_000,
+            offer_update: 60_000,
+            offer_retract: 40_000,
+            taker_gasbase: 80_000,
+            offer_gasbase: 30_000,
+            posthook: 80_000,
+            default_gasreq: 150_000,
+            default_gas_price: 30.0,
+            l1_data_fee: None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "default" => Some(Self::default()),
+            "arbitrum" => Some(Self::arbitrum()),
+            "blast" => Some(Self::blast()),
+            "polygon" => Some(Self::polygon()),
+            _ => None,
+        }
+    }
+
+    pub fn list_profiles() -> Vec<&'static str> {
+        vec!["default", "arbitrum", "blast", "polygon"]
+    }
+
+    /// L1 data fee in wei of an offer write, update or retract
+    pub fn l1_write_fee(&self) -> f64 {
+        self.l1_data_fee.map_or(0.0, |fee| fee.cost(fee.bytes_per_write))
+    }
+
+    /// L1 data fee in wei of a market order
+    pub fn l1_take_fee(&self) -> f64 {
+        self.l1_data_fee.map_or(0.0, |fee| fee.cost(fee.bytes_per_take))
+    }
+
+    /// Gas of a market order executing offers with the given gas requirements
+    pub fn market_order_gas(&self, gasreqs: &[u128]) -> u128 {
+        self.taker_gasbase + gasreqs.iter().map(|gasreq| self.offer_gasbase + gasreq).sum::<u128>()
+    }
+}
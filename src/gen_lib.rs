@@ -0,0 +1,274 @@
+use crate::error_lib::ConfigError;
+use crate::simu_lib::PricePoint;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Poisson, StandardNormal};
+
+// Ethereum mainnet produces a block every 12 seconds
+pub const DEFAULT_BLOCK_DT: f64 = 12.0 / (365.0 * 24.0 * 3600.0);
+
+/// A stochastic price process, simulated one block at a time.
+///
+/// Rates and volatilities are annual, `dt` is the length of a block in years.
+pub trait PriceModel {
+    /// Price at the next block
+    fn step(&mut self, price: f64, dt: f64, rng: &mut ChaCha8Rng) -> f64;
+}
+
+fn normal(rng: &mut ChaCha8Rng) -> f64 {
+    rng.sample(StandardNormal)
+}
+
+/// Geometric Brownian motion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gbm {
+    pub mu: f64,
+    pub sigma: f64,
+}
+
+impl PriceModel for Gbm {
+    fn step(&mut self, price: f64, dt: f64, rng: &mut ChaCha8Rng) -> f64 {
+        let drift = (self.mu - 0.5 * self.sigma * self.sigma) * dt;
+        price * (drift + self.sigma * dt.sqrt() * normal(rng)).exp()
+    }
+}
+
+/// GBM with log-normal jumps arriving as a Poisson process
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MertonJumpDiffusion {
+    pub mu: f64,
+    pub sigma: f64,
+    pub jump_intensity: f64, // Expected jumps per year
+    pub jump_mean: f64,      // Mean of the log jump size
+    pub jump_std: f64,       // Standard deviation of the log jump size
+}
+
+impl PriceModel for MertonJumpDiffusion {
+    fn step(&mut self, price: f64, dt: f64, rng: &mut ChaCha8Rng) -> f64 {
+        // Compensated so that `mu` stays the expected return
+        let expected_jump = (self.jump_mean + 0.5 * self.jump_std * self.jump_std).exp() - 1.0;
+        let drift = (self.mu - 0.5 * self.sigma * self.sigma - self.jump_intensity * expected_jump) * dt;
+        let mut log_return = drift + self.sigma * dt.sqrt() * normal(rng);
+
+        let rate = self.jump_intensity * dt;
+        let jumps = match Poisson::new(rate) {
+            Ok(poisson) => poisson.sample(rng) as u64,
+            Err(_) => 0, // No jumps at a zero intensity
+        };
+        for _ in 0..jumps {
+            log_return += self.jump_mean + self.jump_std * normal(rng);
+        }
+        price * log_return.exp()
+    }
+}
+
+/// Mean reversion of the log price towards `ln(mean_price)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrnsteinUhlenbeck {
+    pub theta: f64, // Speed of reversion
+    pub mean_price: f64,
+    pub sigma: f64,
+}
+
+impl PriceModel for OrnsteinUhlenbeck {
+    fn step(&mut self, price: f64, dt: f64, rng: &mut ChaCha8Rng) -> f64 {
+        let log_price = price.ln();
+        let reversion = self.theta * (self.mean_price.ln() - log_price) * dt;
+        (log_price + reversion + self.sigma * dt.sqrt() * normal(rng)).exp()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Regime {
+    pub mu: f64,
+    pub sigma: f64,
+}
+
+/// GBM whose drift and volatility follow a Markov chain of regimes.
+///
+/// `transitions[i][j]` is the probability of moving from regime `i` to
+/// regime `j` at each block, every row should sum to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegimeSwitching {
+    pub regimes: Vec<Regime>,
+    pub transitions: Vec<Vec<f64>>,
+    pub current: usize,
+}
+
+impl RegimeSwitching {
+    pub fn new(regimes: Vec<Regime>, transitions: Vec<Vec<f64>>) -> Result<Self, ConfigError> {
+        if regimes.is_empty() {
+            return Err(ConfigError::InvalidModel("At least one regime is required".to_string()));
+        }
+        if transitions.len() != regimes.len() || transitions.iter().any(|row| row.len() != regimes.len()) {
+            return Err(ConfigError::InvalidModel("Transition matrix must be square, with a row per regime".to_string()));
+        }
+        if transitions.iter().any(|row| (row.iter().sum::<f64>() - 1.0).abs() > 1e-9) {
+            return Err(ConfigError::InvalidModel("Transition probabilities must sum to 1".to_string()));
+        }
+        Ok(Self { regimes, transitions, current: 0 })
+    }
+
+    fn switch(&mut self, rng: &mut ChaCha8Rng) {
+        let draw: f64 = rng.gen();
+        let mut cumulative = 0.0;
+        for (regime, probability) in self.transitions[self.current].iter().enumerate() {
+            cumulative += probability;
+            if draw < cumulative {
+                self.current = regime;
+                return;
+            }
+        }
+    }
+}
+
+impl PriceModel for RegimeSwitching {
+    fn step(&mut self, price: f64, dt: f64, rng: &mut ChaCha8Rng) -> f64 {
+        self.switch(rng);
+        let regime = self.regimes[self.current];
+        Gbm { mu: regime.mu, sigma: regime.sigma }.step(price, dt, rng)
+    }
+}
+
+/// Where a generated path starts, how long it is and its seed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathConfig {
+    pub initial_price: f64,
+    pub start_block: u64,
+    pub n_blocks: usize,
+    pub dt: f64, // Length of a block, in years
+    pub seed: u64,
+}
+
+impl PathConfig {
+    pub fn new(initial_price: f64, n_blocks: usize, seed: u64) -> Self {
+        Self {
+            initial_price,
+            start_block: 0,
+            n_blocks,
+            dt: DEFAULT_BLOCK_DT,
+            seed,
+        }
+    }
+}
+
+/// A generated price path, one `PricePoint` per block starting at the
+/// initial price
+pub struct PricePath<M: PriceModel> {
+    model: M,
+    config: PathConfig,
+    rng: ChaCha8Rng,
+    price: f64,
+    index: usize,
+}
+
+impl<M: PriceModel> PricePath<M> {
+    pub fn new(model: M, config: PathConfig) -> Self {
+        Self {
+            model,
+            config,
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            price: config.initial_price,
+            index: 0,
+        }
+    }
+}
+
+impl<M: PriceModel> Iterator for PricePath<M> {
+    type Item = PricePoint;
+
+    fn next(&mut self) -> Option<PricePoint> {
+        if self.index >= self.config.n_blocks {
+            return None;
+        }
+        if self.index > 0 {
+            self.price = self.model.step(self.price, self.config.dt, &mut self.rng);
+        }
+        let point = PricePoint::new(self.config.start_block + self.index as u64, self.price);
+        self.index += 1;
+        Some(point)
+    }
+}
+
+pub fn generate_path<M: PriceModel>(model: M, config: PathConfig) -> Vec<PricePoint> {
+    PricePath::new(model, config).collect()
+}
+
+/// `n_paths` independent paths, the i-th one seeded with `config.seed + i`
+pub fn generate_paths<M: PriceModel + Clone>(model: &M, config: PathConfig, n_paths: usize) -> Vec<Vec<PricePoint>> {
+    (0..n_paths)
+        .map(|i| {
+            let config = PathConfig { seed: config.seed.wrapping_add(i as u64), ..config };
+            generate_path(model.clone(), config)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapMethod {
+    Moving,     // Blocks of exactly `block_length` returns
+    Stationary, // Blocks of geometric length with mean `block_length`, wrapping around the sample
+}
+
+/// Resamples the log-returns of a recorded feed by blocks, which keeps the
+/// volatility clustering of the sample within each block.
+///
+/// The block length of the model is in returns, `dt` is ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockBootstrap {
+    pub log_returns: Vec<f64>,
+    pub block_length: usize,
+    pub method: BootstrapMethod,
+    position: usize,
+    remaining: usize, // Returns left in the current block
+}
+
+impl BlockBootstrap {
+    pub fn new(log_returns: Vec<f64>, block_length: usize, method: BootstrapMethod) -> Result<Self, ConfigError> {
+        if block_length == 0 {
+            return Err(ConfigError::InvalidModel("Block length must be positive".to_string()));
+        }
+        if log_returns.len() < block_length {
+            return Err(ConfigError::InvalidModel("Not enough returns for the block length".to_string()));
+        }
+        Ok(Self { log_returns, block_length, method, position: 0, remaining: 0 })
+    }
+
+    /// Bootstraps the log-returns between consecutive points of `price_feed`
+    pub fn from_feed(price_feed: &[PricePoint], block_length: usize, method: BootstrapMethod) -> Result<Self, ConfigError> {
+        if price_feed.iter().any(|point| point.price <= 0.0) {
+            return Err(ConfigError::InvalidModel("Prices must be positive".to_string()));
+        }
+        let log_returns = price_feed.windows(2).map(|pair| (pair[1].price / pair[0].price).ln()).collect();
+        Self::new(log_returns, block_length, method)
+    }
+
+    fn next_return(&mut self, rng: &mut ChaCha8Rng) -> f64 {
+        let n = self.log_returns.len();
+        match self.method {
+            BootstrapMethod::Moving => {
+                if self.remaining == 0 {
+                    self.position = rng.gen_range(0..=n - self.block_length);
+                    self.remaining = self.block_length;
+                }
+            }
+            BootstrapMethod::Stationary => {
+                if self.remaining == 0 || rng.gen::<f64>() < 1.0 / self.block_length as f64 {
+                    self.position = rng.gen_range(0..n);
+                    self.remaining = usize::MAX;
+                }
+            }
+        }
+        let log_return = self.log_returns[self.position % n];
+        self.position = (self.position + 1) % n;
+        self.remaining -= 1;
+        log_return
+    }
+}
+
+impl PriceModel for BlockBootstrap {
+    fn step(&mut self, price: f64, _dt: f64, rng: &mut ChaCha8Rng) -> f64 {
+        price * self.next_return(rng).exp()
+    }
+}
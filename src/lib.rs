@@ -1,8 +1,19 @@
+pub mod analytics_lib;
 pub mod chain_lib;
+pub mod checkpoint_lib;
+pub mod error_lib;
+pub mod experiment_lib;
+pub mod gas_lib;
+pub mod gen_lib;
 pub mod mgv_lib;
+pub mod observer_lib;
+pub mod output_lib;
+pub mod params_lib;
 pub mod read_utils;
+pub mod scenario_lib;
 pub mod strats_lib;
 pub mod simu_lib;
+pub mod sweep_lib;
 pub mod macros;
 pub mod strats;
 pub mod utils;
\ No newline at end of file
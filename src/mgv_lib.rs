@@ -1,12 +1,18 @@
 
-use crate::chain_lib::User;
+use crate::chain_lib::{User, WEI_PER_GWEI};
+use crate::error_lib::{LedgerError, MarketError};
+use crate::gas_lib::GasSchedule;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::sync::{Arc, Mutex};
-use crate::strats_lib::Strategy;
+use crate::strats_lib::{Strategy, StrategyContext};
+use crate::simu_lib::DEFAULT_SEED;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 
-const OFFER_WRITE_COST: u128 = 200_000; // TO CHECK
-//const OFFER_DELETE_COST: u128 = 100_000; // TO CHECK
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OfferSide {
     Ask,
     Bid,
@@ -21,34 +27,51 @@ impl OfferSide {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
+/// An offer taken, fully or partially, by a market order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fill {
+    pub offer_id: u64,
+    pub maker_id: String,
+    pub taker_id: String,
+    pub side: OfferSide, // Side of the offer, an ask fill is a taker buy
+    pub price: f64,
+    pub base_volume: f64,
+    pub quote_volume: f64,
+    pub fee: f64, // Paid by the taker, in quote
+}
+
 ////////////////////////
 // Offer
 ///////////////////////
 
 pub struct Offer {
+    pub id: u64, // Assigned by the market when the offer is written
     pub maker:  Arc<Mutex<User>>,
     pub side: OfferSide,
     pub price: f64,
     pub volume: f64,
     pub gasreq: u128,
     pub strategy: Arc<Mutex<Box<dyn Strategy>>>, // Replace post_hook with strategy
+    pub strategy_id: Option<String>, // Registered strategy that wrote the offer, see `StrategyContext::own_offers`
 }
 
 impl std::fmt::Debug for Offer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Offer")
+            .field("id", &self.id)
             .field("maker", &self.maker)
             .field("side", &self.side)
             .field("price", &self.price)
             .field("volume", &self.volume)
             .field("gasreq", &self.gasreq)
             .field("strategy", &if true { "Some(Strategy)" } else { "None" })
+            .field("strategy_id", &self.strategy_id)
             .finish()
     }
 }
@@ -56,12 +79,14 @@ impl std::fmt::Debug for Offer {
 impl Clone for Offer {
     fn clone(&self) -> Self {
         Self {
+            id: self.id,
             maker: Arc::clone(&self.maker),
             side: self.side,
             price: self.price,
             volume: self.volume,
             gasreq: self.gasreq,
             strategy: Arc::clone(&self.strategy), // Clone the Arc<Mutex<...>> instead of setting to None
+            strategy_id: self.strategy_id.clone(),
         }
     }
 }
@@ -76,12 +101,14 @@ impl Offer {
         strategy: Arc<Mutex<Box<dyn Strategy>>>
     ) -> Self {
         Self {
+            id: 0,
             maker,
             side,
             price,
             volume,
             gasreq,
             strategy,
+            strategy_id: None,
         }
     }
 }
@@ -89,16 +116,16 @@ impl Offer {
 
 impl PartialOrd for Offer {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self.side {
-            OfferSide::Ask => self.price.partial_cmp(&other.price),
-            OfferSide::Bid => other.price.partial_cmp(&self.price),
-        }
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Offer {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        match self.side {
+            OfferSide::Ask => self.price.partial_cmp(&other.price),
+            OfferSide::Bid => other.price.partial_cmp(&self.price),
+        }.expect("price compare error")
     }
 }
 
@@ -115,6 +142,37 @@ impl Eq for Offer {}
 
 
 
+/// Plain view of an offer, detached from its maker and strategy
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookEntry {
+    pub offer_id: u64,
+    pub side: OfferSide,
+    pub price: f64,
+    pub volume: f64,
+    pub maker: String,
+}
+
+impl BookEntry {
+    pub fn new(offer: &Offer) -> Self {
+        Self {
+            offer_id: offer.id,
+            side: offer.side,
+            price: offer.price,
+            volume: offer.volume,
+            maker: offer.maker.lock().map(|user| user.id.clone()).unwrap_or_else(|_| "locked".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for OfferSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ask => write!(f, "ask"),
+            Self::Bid => write!(f, "bid"),
+        }
+    }
+}
+
 ////////////////////////
 // Market
 ///////////////////////
@@ -123,45 +181,280 @@ pub struct Market {
     pub quote: String,
     pub bids: Vec<Offer>,
     pub asks: Vec<Offer>,
-    pub offer_write_cost: u128,
+    pub gas_schedule: GasSchedule,
+    pub gas_price: f64, // In gwei
+    next_offer_id: u64,
+    in_posthook: bool,
+    pub out_of_gas_reposts: Vec<OutOfGasRepost>,
+    pub fee_bps: f64, // Taker fee, taken on what the taker receives
+    pub events: Vec<MarketEvent>, // Offer writes and fills, in execution order
+    pub offer_failures: Vec<OfferFailure>,
+    pub block: u64,        // Block being simulated, set by the simulator
+    pub timestamp: u64,    // Unix time of the block, in seconds, set by the simulator
+    pub rng: ChaCha8Rng,   // Randomness of the strategies, seeded by the simulator
+    sandboxed: bool,       // Set on the copies made by `sandbox`
+}
+
+/// An offer whose maker could not deliver when it was taken.
+///
+/// As on Mangrove the offer is removed without being executed nor reposted,
+/// and its maker pays the taker a bounty, in wei, out of its native.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfferFailure {
+    pub offer_id: u64,
+    pub maker_id: String,
+    pub taker_id: String,
+    pub bounty: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfferWriteKind {
+    Post,
+    Update,
+    Retract,
+}
+
+/// An offer written to the book by its maker, with the price and volume it
+/// was written at, or had when retracted
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfferWrite {
+    pub kind: OfferWriteKind,
+    pub offer_id: u64,
+    pub maker_id: String,
+    pub side: OfferSide,
+    pub price: f64,
+    pub volume: f64,
+}
+
+/// A write or a fill of the book, a post hook's writes come after the fill
+/// that called it
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketEvent {
+    OfferWrite(OfferWrite),
+    Fill(Fill),
+}
+
+/// A filled offer whose posthook could not repost it because the maker ran
+/// out of native: the offer is dropped from the book instead.
+#[derive(Debug, Clone)]
+pub struct OutOfGasRepost {
+    pub maker_id: String,
+    pub side: OfferSide,
+    pub price: f64,
 }
 
 impl Market {
     pub fn new(base: String, quote: String) -> Self {
+        Self::with_gas_schedule(base, quote, GasSchedule::default())
+    }
+
+    pub fn with_gas_schedule(base: String, quote: String, gas_schedule: GasSchedule) -> Self {
         Self {
             base,
             quote,
             bids: Vec::new(),
             asks: Vec::new(),
-            offer_write_cost: OFFER_WRITE_COST,
+            gas_price: gas_schedule.default_gas_price,
+            gas_schedule,
+            next_offer_id: 1,
+            in_posthook: false,
+            out_of_gas_reposts: Vec::new(),
+            fee_bps: 0.0,
+            events: Vec::new(),
+            offer_failures: Vec::new(),
+            block: 0,
+            timestamp: 0,
+            rng: ChaCha8Rng::seed_from_u64(DEFAULT_SEED),
+            sandboxed: false,
         }
     }
 
+    /// Copy of the market to try orders and writes on, returned with its copy
+    /// of `account`. The offers and their makers are copies too, so nothing
+    /// done on the sandbox reaches the real balances, and the post hooks of
+    /// the offers taken there do not run. The RNG is a fresh one.
+    pub fn sandbox(&self, account: &Arc<Mutex<User>>) -> (Market, Arc<Mutex<User>>) {
+        let mut accounts: HashMap<*const Mutex<User>, Arc<Mutex<User>>> = HashMap::new();
+        let mut copy = |user: &Arc<Mutex<User>>| {
+            Arc::clone(accounts
+                .entry(Arc::as_ptr(user))
+                .or_insert_with(|| Arc::new(Mutex::new(user.lock().unwrap().clone()))))
+        };
+        let account = copy(account);
+        let bids = self.bids.iter().map(|offer| Offer { maker: copy(&offer.maker), ..offer.clone() }).collect();
+        let asks = self.asks.iter().map(|offer| Offer { maker: copy(&offer.maker), ..offer.clone() }).collect();
+        let mut sandbox = Market::with_gas_schedule(self.base.clone(), self.quote.clone(), self.gas_schedule.clone());
+        sandbox.bids = bids;
+        sandbox.asks = asks;
+        sandbox.gas_price = self.gas_price;
+        sandbox.next_offer_id = self.next_offer_id;
+        sandbox.fee_bps = self.fee_bps;
+        sandbox.block = self.block;
+        sandbox.timestamp = self.timestamp;
+        sandbox.sandboxed = true;
+        (sandbox, account)
+    }
+
+    /// Switches to another deployment's gas costs and default gas price
+    pub fn set_gas_schedule(&mut self, gas_schedule: GasSchedule) {
+        self.gas_price = gas_schedule.default_gas_price;
+        self.gas_schedule = gas_schedule;
+    }
+
+    /// Cost in wei of `gas` gas units at the current gas price
+    pub fn gas_cost(&self, gas: u128) -> f64 {
+        gas as f64 * self.gas_price * WEI_PER_GWEI
+    }
+
     fn insert(&mut self, offer: Offer) {
-        match offer.side {
+        self.sort_side_after(offer.side, |side| side.push(offer));
+    }
+
+    /// Id the next offer written will get
+    pub fn next_offer_id(&self) -> u64 {
+        self.next_offer_id
+    }
+
+    /// Sets aside the ids before `next_offer_id`, for offers written later
+    /// with `place_reserved_offer`
+    pub fn reserve_offer_ids(&mut self, next_offer_id: u64) {
+        self.next_offer_id = self.next_offer_id.max(next_offer_id);
+    }
+
+    /// Hash of the offers of both sides, changes whenever an offer is
+    /// written, taken or retracted
+    pub fn book_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for offer in self.bids.iter().chain(self.asks.iter()) {
+            offer.id.hash(&mut hasher);
+            offer.price.to_bits().hash(&mut hasher);
+            offer.volume.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Puts back an offer read from a checkpoint, keeping its id and without
+    /// charging gas
+    pub fn restore_offer(&mut self, offer: Offer, next_offer_id: u64) {
+        self.reserve_offer_ids(next_offer_id.max(offer.id + 1));
+        self.insert(offer);
+    }
+
+    fn sort_side_after(&mut self, side: OfferSide, change: impl FnOnce(&mut Vec<Offer>)) {
+        match side {
             OfferSide::Bid => {
-                self.bids.push(offer);
+                change(&mut self.bids);
                 self.bids.sort_by(|a, b| b.price.partial_cmp(&a.price).expect("price compare error"));
             }
             OfferSide::Ask => {
-                self.asks.push(offer);
+                change(&mut self.asks);
                 self.asks.sort_by(|a, b| a.price.partial_cmp(&b.price).expect("price compare error"));
             }
         }
     }
 
     // Add a new method that requires a User to insert an offer
-    pub fn place_offer(&mut self, offer: Offer) -> Result<(), &'static str> {
-        // Calculate required gas cost
-        let gas_cost = self.offer_write_cost;
+    pub fn place_offer(&mut self, offer: Offer) -> Result<u64, MarketError> {
+        let offer_id = self.next_offer_id;
+        self.write_offer(offer, offer_id)?;
+        self.next_offer_id += 1;
+        Ok(offer_id)
+    }
+
+    /// Writes an offer under the id it carries, set aside with `reserve_offer_ids`
+    pub fn place_reserved_offer(&mut self, offer: Offer) -> Result<u64, MarketError> {
+        let offer_id = offer.id;
+        self.write_offer(offer, offer_id)?;
+        Ok(offer_id)
+    }
+
+    fn write_offer(&mut self, mut offer: Offer, offer_id: u64) -> Result<(), MarketError> {
+        // Calculate required gas cost, a repost from a posthook rides in the taker's transaction
+        let gas_cost = if self.in_posthook {
+            self.gas_cost(self.gas_schedule.posthook)
+        } else {
+            self.gas_cost(self.gas_schedule.offer_write) + self.gas_schedule.l1_write_fee()
+        };
         
         // Check if user can pay for gas
-        offer.maker.lock().unwrap().spend_native(gas_cost as f64)?;
+        offer.maker.lock().unwrap().pay_gas(gas_cost)?;
         
+        offer.id = offer_id;
+        self.record_write(OfferWriteKind::Post, &offer);
         self.insert(offer);
         Ok(())
     }
 
+    pub fn get_offer(&self, offer_id: u64) -> Option<&Offer> {
+        self.bids.iter().chain(self.asks.iter()).find(|offer| offer.id == offer_id)
+    }
+
+    /// Moves an offer to a new price and volume, keeping its id
+    pub fn update_offer(&mut self, offer_id: u64, price: f64, volume: f64) -> Result<(), MarketError> {
+        let (maker, side) = match self.get_offer(offer_id) {
+            Some(offer) => (Arc::clone(&offer.maker), offer.side),
+            None => return Err(MarketError::OfferNotFound(offer_id)),
+        };
+        let gas_cost = self.gas_cost(self.gas_schedule.offer_update) + self.gas_schedule.l1_write_fee();
+        maker.lock().unwrap().pay_gas(gas_cost)?;
+
+        self.sort_side_after(side, |offers| {
+            if let Some(offer) = offers.iter_mut().find(|offer| offer.id == offer_id) {
+                offer.price = price;
+                offer.volume = volume;
+            }
+        });
+        let maker_id = maker.lock().unwrap().id.clone();
+        self.events.push(MarketEvent::OfferWrite(OfferWrite { kind: OfferWriteKind::Update, offer_id, maker_id, side, price, volume }));
+        Ok(())
+    }
+
+    /// Removes an offer from the book, the maker pays the retract gas
+    pub fn retract_offer(&mut self, offer_id: u64) -> Result<Offer, MarketError> {
+        let gas_cost = self.gas_cost(self.gas_schedule.offer_retract) + self.gas_schedule.l1_write_fee();
+        let offers = if self.bids.iter().any(|offer| offer.id == offer_id) {
+            &mut self.bids
+        } else {
+            &mut self.asks
+        };
+        let index = offers.iter().position(|offer| offer.id == offer_id).ok_or(MarketError::OfferNotFound(offer_id))?;
+        offers[index].maker.lock().unwrap().pay_gas(gas_cost)?;
+        let offer = offers.remove(index);
+        self.record_write(OfferWriteKind::Retract, &offer);
+        Ok(offer)
+    }
+
+    fn record_write(&mut self, kind: OfferWriteKind, offer: &Offer) {
+        self.events.push(MarketEvent::OfferWrite(OfferWrite {
+            kind,
+            offer_id: offer.id,
+            maker_id: offer.maker.lock().unwrap().id.clone(),
+            side: offer.side,
+            price: offer.price,
+            volume: offer.volume,
+        }));
+    }
+
+
+    /// Offers posted, updated, retracted or filled since the last call, in execution order
+    pub fn take_events(&mut self) -> Vec<MarketEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Offer failures since the last call, in execution order
+    pub fn take_offer_failures(&mut self) -> Vec<OfferFailure> {
+        std::mem::take(&mut self.offer_failures)
+    }
+
+    /// Bounty in wei owed by the maker of a failing offer: the gas it made the taker spend
+    pub fn offer_bounty(&self, offer: &Offer) -> f64 {
+        self.gas_cost(self.gas_schedule.offer_gasbase + offer.gasreq)
+    }
+
+    /// Every offer of the book, bids from the best one then asks from the best one
+    pub fn snapshot(&self) -> Vec<BookEntry> {
+        self.bids.iter().chain(self.asks.iter()).map(BookEntry::new).collect()
+    }
 
     pub fn best_bid(&self) -> Option<&Offer> {
         self.bids.first()
@@ -171,8 +464,28 @@ impl Market {
         self.asks.first()
     }
 
+    /// Price of the last offer a market order of `volume` would reach, None
+    /// if the book cannot fill it
+    pub fn worst_fill_price(&self, side: OrderSide, volume: f64) -> Option<f64> {
+        let offers = match side {
+            OrderSide::Buy => &self.asks,
+            OrderSide::Sell => &self.bids,
+        };
+        let mut remaining_volume = volume;
+        for offer in offers {
+            remaining_volume -= offer.volume;
+            if remaining_volume <= 0.0 {
+                return Some(offer.price);
+            }
+        }
+        None
+    }
+
  
-    pub fn market_order(&mut self, taker: &Arc<Mutex<User>>, side: OrderSide, volume: f64) -> Result<(), &'static str> {
+    /// Takes `volume` base from the best offers and returns the base volume
+    /// filled. As on Mangrove, a failing offer is skipped and its maker pays
+    /// the taker a bounty instead: the order then fills less than `volume`.
+    pub fn market_order(&mut self, taker: &Arc<Mutex<User>>, side: OrderSide, volume: f64) -> Result<f64, MarketError> {
         let offers = match side {
             OrderSide::Buy => &self.asks,  // If user wants to buy (bid), look at asks
             OrderSide::Sell => &self.bids,  // If user wants to sell (ask), look at bids
@@ -180,25 +493,49 @@ impl Market {
         
         // Calculate total volume and gas requirements
         let mut remaining_volume = volume;
-        let mut total_gas = 0u128;
-        let mut offers_to_execute = 0;
+        let mut gasreqs = Vec::new();
+        let mut quote_cost = 0.0; // Of a buy, if no offer fails
         
         for offer in offers {
             if remaining_volume <= 0.0 {
                 break;
             }
-            total_gas += offer.gasreq;
+            gasreqs.push(offer.gasreq);
+            quote_cost += remaining_volume.min(offer.volume) * offer.price;
             remaining_volume -= offer.volume;
-            offers_to_execute += 1;
         }
+        let offers_to_execute = gasreqs.len();
         
         // Check if we can fill the order
         if remaining_volume > 0.0 {
-            return Err("Insufficient liquidity");
+            return Err(MarketError::InsufficientLiquidity {
+                side,
+                requested: volume,
+                available: volume - remaining_volume,
+            });
         }
     
+        // The taker must be able to pay before the book or any balance changes
+        {
+            let taker = taker.lock().unwrap();
+            let (token, needed) = match side {
+                OrderSide::Buy => (&self.quote, quote_cost),
+                OrderSide::Sell => (&self.base, volume),
+            };
+            let available = taker.get_token_balance(token);
+            if available < needed {
+                return Err(MarketError::Ledger(LedgerError::InsufficientBalance {
+                    account_id: taker.id.clone(),
+                    token: token.clone(),
+                    needed,
+                    available,
+                }));
+            }
+        }
+
         // Charge gas fees
-        taker.lock().unwrap().spend_native(total_gas as f64)?;
+        let gas_cost = self.gas_cost(self.gas_schedule.market_order_gas(&gasreqs)) + self.gas_schedule.l1_take_fee();
+        taker.lock().unwrap().pay_gas(gas_cost)?;
     
         let mut remaining_volume = volume;
         let offers_to_remove = offers_to_execute;  // Store how many offers we'll process
@@ -211,73 +548,93 @@ impl Market {
             
             let base_volume = remaining_volume.min(offer.volume);
             let quote_volume = base_volume * offer.price;
+            let fee_rate = self.fee_bps / 10_000.0;
 
     
             let strategy = offer.strategy.clone();
             let maker_ref = offer.maker.clone();
+            let bounty = self.offer_bounty(&offer);
             {
                 let mut maker = offer.maker.lock().unwrap();
                 let mut taker_guard = taker.lock().unwrap();
+                let (delivered_token, delivered_volume) = match side {
+                    OrderSide::Buy => (&self.base, base_volume),
+                    OrderSide::Sell => (&self.quote, quote_volume),
+                };
+                if maker.get_token_balance(delivered_token) < delivered_volume {
+                    // The offer fails, the maker pays for the gas it wasted
+                    let bounty = bounty.min(maker.get_native_balance());
+                    maker.spend_native(bounty)?;
+                    taker_guard.add_native(bounty);
+                    self.offer_failures.push(OfferFailure {
+                        offer_id: offer.id,
+                        maker_id: maker.id.clone(),
+                        taker_id: taker_guard.id.clone(),
+                        bounty,
+                    });
+                    continue;
+                }
                 // Transfer tokens
                 match side {
                     OrderSide::Buy => {
                         // Taker sends quote tokens, receives base tokens
-                        if let Err(e) = taker_guard.spend_token_balance(&self.quote, quote_volume) {
-                            let user_id = taker.lock().unwrap().id.clone();
-                            println!("Error: User {} failed to spend {} {}: {}", user_id, quote_volume, self.quote, e);
-                            return Err("Insufficient token balance for taker");
-                        }
-                        if let Err(e) = maker.add_token_balance(&self.quote, quote_volume) {
-                            let user_id = maker.id.clone();
-                            println!("Error: User {} failed to receive {} {}: {}", user_id, quote_volume, self.quote, e);
-                            return Err("Failed to add token balance to maker");
-                        }
-                        if let Err(e) = taker_guard.add_token_balance(&self.base, base_volume) {
-                            let user_id = taker_guard.id.clone();
-                            println!("Error: User {} failed to receive {} {}: {}", user_id, base_volume, self.base, e);
-                            return Err("Failed to add token balance to taker");
-                        }
-                        if let Err(e) = maker.spend_token_balance(&self.base, base_volume) {
-                            let user_id = maker.id.clone();
-                            println!("Error: User {} failed to spend {} {}: {}", user_id, base_volume, self.base, e);
-                            return Err("Insufficient token balance for maker");
-                        }
+                        taker_guard.spend_token_balance(&self.quote, quote_volume)?;
+                        maker.add_token_balance(&self.quote, quote_volume)?;
+                        taker_guard.add_token_balance(&self.base, base_volume * (1.0 - fee_rate))?;
+                        maker.spend_token_balance(&self.base, base_volume)?;
                     }
                     OrderSide::Sell => {
                         // Taker sends base tokens, receives quote tokens
-                        if let Err(e) = taker_guard.spend_token_balance(&self.base, base_volume) {
-                            let user_id = taker_guard.id.clone();
-                            println!("Error: User {} failed to spend {} {}: {}", user_id, base_volume, self.base, e);
-                            return Err("Insufficient token balance for taker");
-                        }
-                        if let Err(e) = maker.add_token_balance(&self.base, base_volume) {
-                            let user_id = maker.id.clone();
-                            println!("Error: User {} failed to receive {} {}: {}", user_id, base_volume, self.base, e);
-                            return Err("Failed to add token balance to maker");
-                        }
-                        if let Err(e) = taker_guard.add_token_balance(&self.quote, quote_volume) {
-                            let user_id = taker_guard.id.clone();
-                            println!("Error: User {} failed to receive {} {}: {}", user_id, quote_volume, self.quote, e);
-                            return Err("Failed to add token balance to taker");
-                        }
-                        if let Err(e) = maker.spend_token_balance(&self.quote, quote_volume) {
-                            let user_id = maker.id.clone();
-                            println!("Error: User {} failed to spend {} {}: {}", user_id, quote_volume, self.quote, e);
-                            return Err("Insufficient token balance for maker");
-                        }
+                        taker_guard.spend_token_balance(&self.base, base_volume)?;
+                        maker.add_token_balance(&self.base, base_volume)?;
+                        taker_guard.add_token_balance(&self.quote, quote_volume * (1.0 - fee_rate))?;
+                        maker.spend_token_balance(&self.quote, quote_volume)?;
                     }
                 }
+                self.events.push(MarketEvent::Fill(Fill {
+                    offer_id: offer.id,
+                    maker_id: maker.id.clone(),
+                    taker_id: taker_guard.id.clone(),
+                    side: offer.side,
+                    price: offer.price,
+                    base_volume,
+                    quote_volume,
+                    fee: quote_volume * fee_rate,
+                }));
             }
             
-            // Execute strategy's post_trade
+            // Execute strategy's post_trade, a sandbox leaves the strategies alone
+            if self.sandboxed {
+                remaining_volume -= base_volume;
+                continue;
+            }
             if let Ok(mut strategy) = strategy.lock() {
-                strategy.post_hook(self, maker_ref, &offer)?;
+                self.in_posthook = true;
+                // The repost belongs to the strategy that wrote the offer
+                let mut context = StrategyContext::new(self, Arc::clone(&maker_ref)).with_strategy(offer.strategy_id.clone());
+                let result = strategy.post_hook(&mut context, &offer);
+                self.in_posthook = false;
+                match result {
+                    // The taker's order still goes through, only the repost is lost
+                    Err(error) if error.is_out_of_gas() => {
+                        let maker_id = error.out_of_gas_account().unwrap_or_default().to_string();
+                        self.out_of_gas_reposts.push(OutOfGasRepost {
+                            maker_id,
+                            side: offer.side,
+                            price: offer.price,
+                        });
+                    }
+                    result => result.map_err(|error| MarketError::PostHook {
+                        offer_id: offer.id,
+                        source: Box::new(error),
+                    })?,
+                }
             }
             
             remaining_volume -= base_volume;
         }
     
-        Ok(())
+        Ok(volume - remaining_volume)
     }
      
     
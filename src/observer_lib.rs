@@ -0,0 +1,84 @@
+use crate::error_lib::Error;
+use crate::mgv_lib::{Fill, OfferWrite};
+use crate::simu_lib::{PricePoint, Simulator};
+use crate::strats_lib::Event;
+
+/// A strategy method run by the simulator, and how it went
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategyAction {
+    pub block: u64,
+    pub strategy_id: String,
+    pub account_id: String,
+    pub hook: &'static str, // "execute", "on_start", "on_block" or "on_end"
+    pub events: Vec<Event>, // What woke the strategy up, empty for the lifecycle hooks
+    pub result: Result<(), Error>,
+}
+
+/// An error raised during the run, whether the simulation carries on or not
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorEvent {
+    pub block: u64,
+    pub strategy_id: Option<String>, // None for errors of the simulator itself
+    pub context: &'static str,
+    pub error: Error,
+}
+
+/// Notified of what happens during a run, to collect metrics, log or check
+/// invariants without touching the simulation loop.
+///
+/// Every method gets the simulator in read-only. Observers are not part of
+/// checkpoints, add them again to a restored simulator.
+pub trait Observer: Send {
+    /// Before anything runs in the block
+    fn on_step_start(&mut self, _block: u64, _price_point: &PricePoint, _simulator: &Simulator) {}
+
+    /// Once the block is marked to market and written to the sinks
+    fn on_step_end(&mut self, _block: u64, _price_point: &PricePoint, _simulator: &Simulator) {}
+
+    /// After a strategy ran, its offers and fills are notified first
+    fn on_strategy_action(&mut self, _action: &StrategyAction, _simulator: &Simulator) {}
+
+    fn on_fill(&mut self, _fill: &Fill, _simulator: &Simulator) {}
+
+    fn on_offer_write(&mut self, _write: &OfferWrite, _simulator: &Simulator) {}
+
+    fn on_error(&mut self, _error: &ErrorEvent, _simulator: &Simulator) {}
+}
+
+impl Simulator {
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    // Calls `notify` on every observer. They are moved out of the simulator
+    // for the time of the call, so that they can read it.
+    pub(crate) fn notify<F>(&mut self, mut notify: F)
+    where
+        F: FnMut(&mut dyn Observer, &Simulator),
+    {
+        if self.observers.is_empty() {
+            return;
+        }
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in observers.iter_mut() {
+            notify(observer.as_mut(), self);
+        }
+        self.observers = observers;
+    }
+
+    // Notifies an error and hands it back, to be returned or swallowed
+    pub(crate) fn report_error(&mut self, strategy_id: Option<&str>, context: &'static str, error: Error) -> Error {
+        let event = ErrorEvent {
+            block: self.current_block,
+            strategy_id: strategy_id.map(str::to_string),
+            context,
+            error,
+        };
+        self.notify(|observer, simulator| observer.on_error(&event, simulator));
+        event.error
+    }
+}
@@ -0,0 +1,515 @@
+use crate::mgv_lib::{BookEntry, Market};
+use crate::simu_lib::PerformanceMetrics;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_OUTPUT_DIR: &str = "data/output";
+
+/// Summary of the book written at every block
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketState {
+    pub block: u64,
+    pub price: f64,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub bid_offers: usize,
+    pub ask_offers: usize,
+    pub bid_volume: f64,
+    pub ask_volume: f64,
+}
+
+impl MarketState {
+    pub fn new(block: u64, price: f64, market: &Market) -> Self {
+        Self {
+            block,
+            price,
+            best_bid: market.best_bid().map(|offer| offer.price),
+            best_ask: market.best_ask().map(|offer| offer.price),
+            bid_offers: market.bids.len(),
+            ask_offers: market.asks.len(),
+            bid_volume: market.bids.iter().map(|offer| offer.volume).sum(),
+            ask_volume: market.asks.iter().map(|offer| offer.volume).sum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookRowKind {
+    Clear,    // Starts a full snapshot, the book is empty until its offers
+    Snapshot, // Offer present in a full snapshot
+    Add,
+    Update,
+    Remove,
+}
+
+impl std::fmt::Display for BookRowKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Clear => write!(f, "clear"),
+            Self::Snapshot => write!(f, "snapshot"),
+            Self::Add => write!(f, "add"),
+            Self::Update => write!(f, "update"),
+            Self::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// One line of the order book output, `Clear` rows have no offer
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookRow {
+    pub block: u64,
+    pub kind: BookRowKind,
+    pub entry: Option<BookEntry>,
+}
+
+impl BookRow {
+    fn new(block: u64, kind: BookRowKind, entry: BookEntry) -> Self {
+        Self { block, kind, entry: Some(entry) }
+    }
+}
+
+/// Turns the book at each block into full snapshots and deltas.
+///
+/// A full snapshot is written on the first block and every `full_every`
+/// blocks, after a `Clear` row so that an empty book is recorded too. In
+/// between, only the offers added, updated or removed since the previous
+/// block are written if `deltas` is set.
+#[derive(Debug, Clone)]
+pub struct BookRecorder {
+    pub full_every: u64,
+    pub deltas: bool,
+    last_book: Option<BTreeMap<u64, BookEntry>>,
+}
+
+impl BookRecorder {
+    pub fn new(full_every: u64, deltas: bool) -> Self {
+        Self {
+            full_every: full_every.max(1),
+            deltas,
+            last_book: None,
+        }
+    }
+
+    pub fn rows(&mut self, block: u64, market: &Market) -> Vec<BookRow> {
+        let entries = market.snapshot();
+        let book: BTreeMap<u64, BookEntry> = entries
+            .iter()
+            .map(|entry| (entry.offer_id, entry.clone()))
+            .collect();
+
+        let rows = match &self.last_book {
+            Some(last_book) if !block.is_multiple_of(self.full_every) => {
+                if self.deltas {
+                    Self::delta_rows(block, last_book, &book)
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => std::iter::once(BookRow { block, kind: BookRowKind::Clear, entry: None })
+                .chain(entries.into_iter().map(|entry| BookRow::new(block, BookRowKind::Snapshot, entry)))
+                .collect(),
+        };
+        self.last_book = Some(book);
+        rows
+    }
+
+    fn delta_rows(block: u64, last_book: &BTreeMap<u64, BookEntry>, book: &BTreeMap<u64, BookEntry>) -> Vec<BookRow> {
+        let mut rows = Vec::new();
+        for (offer_id, entry) in last_book {
+            if !book.contains_key(offer_id) {
+                rows.push(BookRow::new(block, BookRowKind::Remove, entry.clone()));
+            }
+        }
+        for (offer_id, entry) in book {
+            let kind = match last_book.get(offer_id) {
+                None => BookRowKind::Add,
+                Some(last_entry) if last_entry != entry => BookRowKind::Update,
+                Some(_) => continue,
+            };
+            rows.push(BookRow::new(block, kind, entry.clone()));
+        }
+        rows
+    }
+}
+
+/// Rebuilds the book as of `block` from the rows written up to then
+pub fn replay_book(rows: &[BookRow], block: u64) -> Vec<BookEntry> {
+    let mut book: BTreeMap<u64, BookEntry> = BTreeMap::new();
+    for row in rows.iter().filter(|row| row.block <= block) {
+        match (row.kind, &row.entry) {
+            (BookRowKind::Clear, _) => book.clear(),
+            (BookRowKind::Remove, Some(entry)) => {
+                book.remove(&entry.offer_id);
+            }
+            (_, Some(entry)) => {
+                book.insert(entry.offer_id, entry.clone());
+            }
+            (_, None) => {}
+        }
+    }
+    book.into_values().collect()
+}
+
+/// Destination of the data recorded during a simulation
+///
+/// Balances are given as `(column, amount)` pairs, starting with `native`.
+pub trait OutputSink: Send {
+    fn write_balances(&mut self, block: u64, account_id: &str, balances: &[(String, f64)]) -> io::Result<()>;
+
+    fn write_market_state(&mut self, state: &MarketState) -> io::Result<()>;
+
+    /// Order book rows of a block, see `BookRecorder`
+    fn write_book(&mut self, _rows: &[BookRow]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Metrics of an account at the end of a block
+    fn write_metrics(&mut self, _block: u64, _account_id: &str, _metrics: &PerformanceMetrics) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn create_writer(output_dir: &Path, file_name: &str) -> io::Result<BufWriter<File>> {
+    fs::create_dir_all(output_dir)?;
+    Ok(BufWriter::new(File::create(output_dir.join(file_name))?))
+}
+
+fn format_optional(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+////////////////////////
+// CSV
+///////////////////////
+
+/// One `<account>.csv` file per account, a `market_state.csv`, an
+/// `order_book.csv` and a `metrics.csv` file.
+///
+/// The balance columns are fixed by the first row written for an account,
+/// tokens the account only receives later are not recorded.
+pub struct CsvSink {
+    output_dir: PathBuf,
+    balance_files: HashMap<String, (BufWriter<File>, Vec<String>)>,
+    market_file: Option<BufWriter<File>>,
+    book_file: Option<BufWriter<File>>,
+    metrics_file: Option<BufWriter<File>>,
+}
+
+impl CsvSink {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            balance_files: HashMap::new(),
+            market_file: None,
+            book_file: None,
+            metrics_file: None,
+        }
+    }
+}
+
+impl OutputSink for CsvSink {
+    fn write_balances(&mut self, block: u64, account_id: &str, balances: &[(String, f64)]) -> io::Result<()> {
+        if !self.balance_files.contains_key(account_id) {
+            let mut file = create_writer(&self.output_dir, &format!("{}.csv", account_id))?;
+            let columns: Vec<String> = balances.iter().map(|(column, _)| column.clone()).collect();
+            writeln!(file, "block,{}", columns.join(","))?;
+            self.balance_files.insert(account_id.to_string(), (file, columns));
+        }
+        let (file, columns) = self.balance_files.get_mut(account_id).unwrap();
+        let row: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                let amount = balances.iter().find(|(name, _)| name == column).map_or(0.0, |(_, amount)| *amount);
+                amount.to_string()
+            })
+            .collect();
+        writeln!(file, "{},{}", block, row.join(","))
+    }
+
+    fn write_market_state(&mut self, state: &MarketState) -> io::Result<()> {
+        if self.market_file.is_none() {
+            let mut file = create_writer(&self.output_dir, "market_state.csv")?;
+            writeln!(file, "block,price,best_bid,best_ask,bid_offers,ask_offers,bid_volume,ask_volume")?;
+            self.market_file = Some(file);
+        }
+        let file = self.market_file.as_mut().unwrap();
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{}",
+            state.block,
+            state.price,
+            format_optional(state.best_bid),
+            format_optional(state.best_ask),
+            state.bid_offers,
+            state.ask_offers,
+            state.bid_volume,
+            state.ask_volume,
+        )
+    }
+
+    fn write_book(&mut self, rows: &[BookRow]) -> io::Result<()> {
+        if self.book_file.is_none() {
+            let mut file = create_writer(&self.output_dir, "order_book.csv")?;
+            writeln!(file, "block,kind,side,price,volume,maker,offer_id")?;
+            self.book_file = Some(file);
+        }
+        let file = self.book_file.as_mut().unwrap();
+        for row in rows {
+            match &row.entry {
+                Some(entry) => writeln!(
+                    file,
+                    "{},{},{},{},{},{},{}",
+                    row.block, row.kind, entry.side, entry.price, entry.volume, entry.maker, entry.offer_id
+                )?,
+                None => writeln!(file, "{},{},,,,,", row.block, row.kind)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_metrics(&mut self, block: u64, account_id: &str, metrics: &PerformanceMetrics) -> io::Result<()> {
+        if self.metrics_file.is_none() {
+            let mut file = create_writer(&self.output_dir, "metrics.csv")?;
+            writeln!(
+                file,
+                "block,account,value,pnl,realized_pnl,unrealized_pnl,trades,volume,fees,gas_native,gas_quote,hodl_value,rebalanced_value"
+            )?;
+            self.metrics_file = Some(file);
+        }
+        let file = self.metrics_file.as_mut().unwrap();
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            block,
+            account_id,
+            metrics.current_balance,
+            metrics.total_profit_loss,
+            metrics.realized_pnl,
+            metrics.unrealized_pnl,
+            metrics.total_trades,
+            metrics.total_volume,
+            metrics.fees_paid,
+            metrics.gas_spent_native,
+            metrics.gas_spent_quote,
+            metrics.hodl_value,
+            metrics.rebalanced_value,
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for (file, _) in self.balance_files.values_mut() {
+            file.flush()?;
+        }
+        if let Some(file) = self.market_file.as_mut() {
+            file.flush()?;
+        }
+        if let Some(file) = self.book_file.as_mut() {
+            file.flush()?;
+        }
+        if let Some(file) = self.metrics_file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////
+// JSON Lines
+///////////////////////
+
+/// `balances.jsonl`, `market_state.jsonl`, `order_book.jsonl` and
+/// `metrics.jsonl`, one JSON object per line
+pub struct JsonLinesSink {
+    output_dir: PathBuf,
+    balance_file: Option<BufWriter<File>>,
+    market_file: Option<BufWriter<File>>,
+    book_file: Option<BufWriter<File>>,
+    metrics_file: Option<BufWriter<File>>,
+}
+
+impl JsonLinesSink {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            balance_file: None,
+            market_file: None,
+            book_file: None,
+            metrics_file: None,
+        }
+    }
+}
+
+impl OutputSink for JsonLinesSink {
+    fn write_balances(&mut self, block: u64, account_id: &str, balances: &[(String, f64)]) -> io::Result<()> {
+        if self.balance_file.is_none() {
+            self.balance_file = Some(create_writer(&self.output_dir, "balances.jsonl")?);
+        }
+        let balances: serde_json::Map<String, serde_json::Value> = balances
+            .iter()
+            .map(|(column, amount)| (column.clone(), serde_json::json!(amount)))
+            .collect();
+        let line = serde_json::json!({
+            "block": block,
+            "account": account_id,
+            "balances": balances,
+        });
+        writeln!(self.balance_file.as_mut().unwrap(), "{}", line)
+    }
+
+    fn write_market_state(&mut self, state: &MarketState) -> io::Result<()> {
+        if self.market_file.is_none() {
+            self.market_file = Some(create_writer(&self.output_dir, "market_state.jsonl")?);
+        }
+        let line = serde_json::json!({
+            "block": state.block,
+            "price": state.price,
+            "best_bid": state.best_bid,
+            "best_ask": state.best_ask,
+            "bid_offers": state.bid_offers,
+            "ask_offers": state.ask_offers,
+            "bid_volume": state.bid_volume,
+            "ask_volume": state.ask_volume,
+        });
+        writeln!(self.market_file.as_mut().unwrap(), "{}", line)
+    }
+
+    fn write_book(&mut self, rows: &[BookRow]) -> io::Result<()> {
+        if self.book_file.is_none() {
+            self.book_file = Some(create_writer(&self.output_dir, "order_book.jsonl")?);
+        }
+        let file = self.book_file.as_mut().unwrap();
+        for row in rows {
+            let line = match &row.entry {
+                Some(entry) => serde_json::json!({
+                    "block": row.block,
+                    "kind": row.kind.to_string(),
+                    "side": entry.side.to_string(),
+                    "price": entry.price,
+                    "volume": entry.volume,
+                    "maker": entry.maker,
+                    "offer_id": entry.offer_id,
+                }),
+                None => serde_json::json!({ "block": row.block, "kind": row.kind.to_string() }),
+            };
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn write_metrics(&mut self, block: u64, account_id: &str, metrics: &PerformanceMetrics) -> io::Result<()> {
+        if self.metrics_file.is_none() {
+            self.metrics_file = Some(create_writer(&self.output_dir, "metrics.jsonl")?);
+        }
+        let line = serde_json::json!({
+            "block": block,
+            "account": account_id,
+            "value": metrics.current_balance,
+            "pnl": metrics.total_profit_loss,
+            "realized_pnl": metrics.realized_pnl,
+            "unrealized_pnl": metrics.unrealized_pnl,
+            "trades": metrics.total_trades,
+            "volume": metrics.total_volume,
+            "fees": metrics.fees_paid,
+            "gas_native": metrics.gas_spent_native,
+            "gas_quote": metrics.gas_spent_quote,
+            "hodl_value": metrics.hodl_value,
+            "rebalanced_value": metrics.rebalanced_value,
+        });
+        writeln!(self.metrics_file.as_mut().unwrap(), "{}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = self.balance_file.as_mut() {
+            file.flush()?;
+        }
+        if let Some(file) = self.market_file.as_mut() {
+            file.flush()?;
+        }
+        if let Some(file) = self.book_file.as_mut() {
+            file.flush()?;
+        }
+        if let Some(file) = self.metrics_file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////
+// Memory
+///////////////////////
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceRecord {
+    pub block: u64,
+    pub account_id: String,
+    pub balances: Vec<(String, f64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsRecord {
+    pub block: u64,
+    pub account_id: String,
+    pub metrics: PerformanceMetrics,
+}
+
+#[derive(Debug, Default)]
+pub struct Recording {
+    pub balances: Vec<BalanceRecord>,
+    pub market_states: Vec<MarketState>,
+    pub book: Vec<BookRow>,
+    pub metrics: Vec<MetricsRecord>,
+}
+
+/// Keeps everything in memory, read it back through `recording()`
+#[derive(Clone, Default)]
+pub struct MemoryRecorder {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl MemoryRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn recording(&self) -> Arc<Mutex<Recording>> {
+        Arc::clone(&self.recording)
+    }
+}
+
+impl OutputSink for MemoryRecorder {
+    fn write_balances(&mut self, block: u64, account_id: &str, balances: &[(String, f64)]) -> io::Result<()> {
+        self.recording.lock().unwrap().balances.push(BalanceRecord {
+            block,
+            account_id: account_id.to_string(),
+            balances: balances.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn write_market_state(&mut self, state: &MarketState) -> io::Result<()> {
+        self.recording.lock().unwrap().market_states.push(state.clone());
+        Ok(())
+    }
+
+    fn write_book(&mut self, rows: &[BookRow]) -> io::Result<()> {
+        self.recording.lock().unwrap().book.extend_from_slice(rows);
+        Ok(())
+    }
+
+    fn write_metrics(&mut self, block: u64, account_id: &str, metrics: &PerformanceMetrics) -> io::Result<()> {
+        self.recording.lock().unwrap().metrics.push(MetricsRecord {
+            block,
+            account_id: account_id.to_string(),
+            metrics: metrics.clone(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use crate::error_lib::{ConfigError, Error, StrategyError};
+use crate::mgv_lib::OfferSide;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Type of a strategy parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParameterKind {
+    Float,
+    Int,
+    Side,
+    FloatList,
+}
+
+impl fmt::Display for ParameterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Float => write!(f, "float"),
+            Self::Int => write!(f, "int"),
+            Self::Side => write!(f, "Ask|Bid"),
+            Self::FloatList => write!(f, "[float]"),
+        }
+    }
+}
+
+/// Value of a strategy parameter, written as is in scenario files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    Int(i64),
+    Float(f64),
+    Side(OfferSide),
+    FloatList(Vec<f64>),
+}
+
+impl ParameterValue {
+    pub fn kind(&self) -> ParameterKind {
+        match self {
+            Self::Int(_) => ParameterKind::Int,
+            Self::Float(_) => ParameterKind::Float,
+            Self::Side(_) => ParameterKind::Side,
+            Self::FloatList(_) => ParameterKind::FloatList,
+        }
+    }
+
+    // The value as `kind`, integers and integral floats are interchangeable
+    fn coerce(self, kind: ParameterKind) -> Option<Self> {
+        match (self, kind) {
+            (Self::Int(value), ParameterKind::Float) => Some(Self::Float(value as f64)),
+            (Self::Float(value), ParameterKind::Int) if value.fract() == 0.0 => Some(Self::Int(value as i64)),
+            (value, kind) if value.kind() == kind => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Float(value) => write!(f, "{}", value),
+            Self::Side(side) => write!(f, "{:?}", side),
+            Self::FloatList(values) => write!(f, "{:?}", values),
+        }
+    }
+}
+
+/// Parameters by name, as given to `StrategyFactory::create_strategy`
+pub type ParameterMap = BTreeMap<String, ParameterValue>;
+
+/// Declaration of a strategy parameter.
+///
+/// Parameters without a default are required unless marked optional. Bounds
+/// apply to numbers and to every element of lists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterSpec {
+    pub name: &'static str,
+    pub kind: ParameterKind,
+    pub description: &'static str,
+    pub default: Option<ParameterValue>,
+    pub optional: bool,
+    pub min: Option<f64>,
+    pub min_exclusive: bool,
+    pub max: Option<f64>,
+}
+
+impl ParameterSpec {
+    pub fn new(name: &'static str, kind: ParameterKind, description: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            description,
+            default: None,
+            optional: false,
+            min: None,
+            min_exclusive: false,
+            max: None,
+        }
+    }
+
+    pub fn float(name: &'static str, description: &'static str) -> Self {
+        Self::new(name, ParameterKind::Float, description)
+    }
+
+    pub fn int(name: &'static str, description: &'static str) -> Self {
+        Self::new(name, ParameterKind::Int, description)
+    }
+
+    pub fn side(name: &'static str, description: &'static str) -> Self {
+        Self::new(name, ParameterKind::Side, description)
+    }
+
+    pub fn float_list(name: &'static str, description: &'static str) -> Self {
+        Self::new(name, ParameterKind::FloatList, description)
+    }
+
+    pub fn default_value(mut self, value: ParameterValue) -> Self {
+        self.default = Some(value);
+        self
+    }
+
+    /// Left out of the built parameters when not given
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Inclusive lower bound
+    pub fn min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self.min_exclusive = false;
+        self
+    }
+
+    /// Exclusive lower bound
+    pub fn above(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self.min_exclusive = true;
+        self
+    }
+
+    /// Inclusive upper bound
+    pub fn max(mut self, max: f64) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.default.is_none() && !self.optional
+    }
+
+    /// Checks `value` against the type and bounds of the parameter
+    pub fn check(&self, value: ParameterValue) -> Result<ParameterValue, ConfigError> {
+        let invalid = |reason: String| ConfigError::invalid_parameter(self.name, &reason);
+        let value = value
+            .clone()
+            .coerce(self.kind)
+            .ok_or_else(|| invalid(format!("expected {}, got {}", self.kind, value)))?;
+        let numbers = match &value {
+            ParameterValue::Int(number) => vec![*number as f64],
+            ParameterValue::Float(number) => vec![*number],
+            ParameterValue::FloatList(numbers) => numbers.clone(),
+            ParameterValue::Side(_) => Vec::new(),
+        };
+        for number in numbers {
+            let too_low = self.min.is_some_and(|min| if self.min_exclusive { number <= min } else { number < min });
+            let too_high = self.max.is_some_and(|max| number > max);
+            if number.is_nan() || too_low || too_high {
+                return Err(invalid(format!("must be {}, got {}", self.range(), number)));
+            }
+        }
+        Ok(value)
+    }
+
+    /// A swept number as a value of the parameter's kind, an int parameter
+    /// takes integral numbers only
+    pub fn swept_value(&self, value: f64) -> Result<ParameterValue, ConfigError> {
+        ParameterValue::Float(value)
+            .coerce(self.kind)
+            .ok_or_else(|| ConfigError::invalid_parameter(self.name, &format!("expected {}, got {}", self.kind, value)))
+    }
+
+    /// The bounds of the parameter, such as `> 0` or `in [1, 10]`
+    pub fn range(&self) -> String {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => format!("in {}{}, {}]", if self.min_exclusive { "(" } else { "[" }, min, max),
+            (Some(min), None) => format!("{} {}", if self.min_exclusive { ">" } else { ">=" }, min),
+            (None, Some(max)) => format!("<= {}", max),
+            (None, None) => "any".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParameterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.name, self.kind)?;
+        if self.min.is_some() || self.max.is_some() {
+            write!(f, ", {}", self.range())?;
+        }
+        match &self.default {
+            Some(default) => write!(f, ", default {}", default)?,
+            None if self.optional => write!(f, ", optional")?,
+            None => write!(f, ", required")?,
+        }
+        write!(f, "): {}", self.description)
+    }
+}
+
+/// Checks the new value of a single parameter, for `Strategy::set_parameter`
+pub fn check_parameter(schema: &[ParameterSpec], name: &str, value: ParameterValue) -> Result<ParameterValue, Error> {
+    let spec = schema
+        .iter()
+        .find(|spec| spec.name == name)
+        .ok_or_else(|| StrategyError::UnknownParameter(name.to_string()))?;
+    Ok(spec.check(value)?)
+}
+
+/// Parameters checked against a schema, with the defaults filled in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameters {
+    values: ParameterMap,
+}
+
+impl Parameters {
+    /// Rejects unknown, missing, mistyped and out of range parameters
+    pub fn validate(schema: &[ParameterSpec], mut values: ParameterMap) -> Result<Self, Error> {
+        if let Some(name) = values.keys().find(|name| !schema.iter().any(|spec| spec.name == name.as_str())) {
+            return Err(StrategyError::UnknownParameter(name.clone()).into());
+        }
+        let mut checked = ParameterMap::new();
+        for spec in schema {
+            let value = match values.remove(spec.name).or_else(|| spec.default.clone()) {
+                Some(value) => spec.check(value)?,
+                None if spec.optional => continue,
+                None => return Err(ConfigError::invalid_parameter(spec.name, "missing").into()),
+            };
+            checked.insert(spec.name.to_string(), value);
+        }
+        Ok(Self { values: checked })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ParameterValue> {
+        self.values.get(name)
+    }
+
+    pub fn values(&self) -> &ParameterMap {
+        &self.values
+    }
+
+    pub fn float(&self, name: &str) -> Result<f64, ConfigError> {
+        self.optional_float(name).ok_or_else(|| Self::missing(name))
+    }
+
+    pub fn int(&self, name: &str) -> Result<i64, ConfigError> {
+        self.optional_int(name).ok_or_else(|| Self::missing(name))
+    }
+
+    pub fn side(&self, name: &str) -> Result<OfferSide, ConfigError> {
+        match self.values.get(name) {
+            Some(ParameterValue::Side(side)) => Ok(*side),
+            _ => Err(Self::missing(name)),
+        }
+    }
+
+    pub fn optional_float(&self, name: &str) -> Option<f64> {
+        match self.values.get(name) {
+            Some(ParameterValue::Float(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn optional_int(&self, name: &str) -> Option<i64> {
+        match self.values.get(name) {
+            Some(ParameterValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn optional_float_list(&self, name: &str) -> Option<Vec<f64>> {
+        match self.values.get(name) {
+            Some(ParameterValue::FloatList(values)) => Some(values.clone()),
+            _ => None,
+        }
+    }
+
+    fn missing(name: &str) -> ConfigError {
+        ConfigError::invalid_parameter(name, "missing")
+    }
+}
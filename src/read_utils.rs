@@ -1,38 +1,119 @@
-use std::fs;
-use std::error::Error;
-use crate::simu_lib::PricePoint;
-
-
-pub fn read_price_feed(file_path: &str) -> Result<Vec<PricePoint>, Box<dyn Error>> {
-    let content = fs::read_to_string(file_path)?;
-    
-    let mut price_points = Vec::new();
-    for (line_num, line) in content.lines().enumerate() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines};
+use std::error::Error as StdError;
+use crate::error_lib::{ConfigError, Error, IoError};
+use crate::simu_lib::{GasPricePoint, PriceFeed, PricePoint};
+
+
+pub fn read_price_feed(file_path: &str) -> Result<Vec<PricePoint>, Box<dyn StdError>> {
+    let price_points = PriceFeedReader::open(file_path)?.collect::<Result<Vec<_>, _>>()?;
+    Ok(price_points)
+}
+
+/// Reads a gas price feed, one `block_number;gas_price_in_gwei` per line
+pub fn read_gas_price_feed(file_path: &str) -> Result<Vec<GasPricePoint>, Box<dyn StdError>> {
+    let gas_price_points = BlockSeriesReader::open(file_path, "gas_price")?
+        .map(|point| point.map(|(block, gas_price)| GasPricePoint { block, gas_price }))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(gas_price_points)
+}
+
+/// Streams a price feed file to the simulator, see `PriceFeedReader`
+pub fn stream_price_feed(file_path: &str) -> Result<PriceFeed, Error> {
+    Ok(PriceFeed::try_stream(PriceFeedReader::open(file_path)?))
+}
+
+/// Reads `block_number;value` lines one at a time through a buffer, the file
+/// is never held in memory. Blank lines are skipped.
+pub struct BlockSeriesReader<R> {
+    lines: Lines<R>,
+    line_num: usize,
+    value_name: &'static str,
+    source: String, // Named in the errors
+}
+
+impl BlockSeriesReader<BufReader<File>> {
+    pub fn open(file_path: &str, value_name: &'static str) -> Result<Self, Error> {
+        let file = File::open(file_path).map_err(|error| IoError::new(format!("opening {}", file_path), &error))?;
+        Ok(Self::new(BufReader::new(file), value_name, file_path))
+    }
+}
+
+impl<R: BufRead> BlockSeriesReader<R> {
+    pub fn new(reader: R, value_name: &'static str, source: &str) -> Self {
+        Self { lines: reader.lines(), line_num: 0, value_name, source: source.to_string() }
+    }
+
+    fn parse(&self, line: &str) -> Result<(u64, f64), Error> {
+        let invalid = |reason: String| ConfigError::InvalidFeed(format!("{}: {}", self.source, reason));
 
         // Split by semicolon
         let parts: Vec<&str> = line.split(';').collect();
         if parts.len() != 2 {
-            return Err(format!(
-                "Invalid format at line {}: expected 'block_number;price', got '{}'",
-                line_num + 1, line
-            ).into());
+            return Err(invalid(format!(
+                "Invalid format at line {}: expected 'block_number;{}', got '{}'",
+                self.line_num, self.value_name, line
+            )).into());
         }
 
         // Parse block number - remove any "block_number" prefix if it exists
         let block_str = parts[0].trim().replace("block_number", "").trim().to_string();
         let block = block_str.parse::<u64>()
-            .map_err(|_| format!("Invalid block number at line {}: {}", line_num + 1, parts[0]))?;
+            .map_err(|_| invalid(format!("Invalid block number at line {}: {}", self.line_num, parts[0])))?;
 
-        // Parse price
-        let price = parts[1].trim().parse::<f64>()
-            .map_err(|_| format!("Invalid price at line {}: {}", line_num + 1, parts[1]))?;
+        // Parse value
+        let value = parts[1].trim().parse::<f64>()
+            .map_err(|_| invalid(format!("Invalid {} at line {}: {}", self.value_name, self.line_num, parts[1])))?;
 
-        price_points.push(PricePoint { block, price });
+        Ok((block, value))
     }
+}
 
-    Ok(price_points)
+impl<R: BufRead> Iterator for BlockSeriesReader<R> {
+    type Item = Result<(u64, f64), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => return Some(Err(self.io_error(&error))),
+            };
+            self.line_num += 1;
+            let line = line.trim();
+            if !line.is_empty() {
+                return Some(self.parse(line));
+            }
+        }
+    }
+}
+
+impl<R> BlockSeriesReader<R> {
+    fn io_error(&self, error: &io::Error) -> Error {
+        IoError::new(format!("reading {} at line {}", self.source, self.line_num + 1), error).into()
+    }
+}
+
+/// Price points of a `block_number;price` file, read lazily
+pub struct PriceFeedReader<R> {
+    series: BlockSeriesReader<R>,
+}
+
+impl PriceFeedReader<BufReader<File>> {
+    pub fn open(file_path: &str) -> Result<Self, Error> {
+        Ok(Self { series: BlockSeriesReader::open(file_path, "price")? })
+    }
+}
+
+impl<R: BufRead> PriceFeedReader<R> {
+    pub fn new(reader: R, source: &str) -> Self {
+        Self { series: BlockSeriesReader::new(reader, "price", source) }
+    }
+}
+
+impl<R: BufRead> Iterator for PriceFeedReader<R> {
+    type Item = Result<PricePoint, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.series.next().map(|point| point.map(|(block, price)| PricePoint { block, price }))
+    }
 }
@@ -0,0 +1,403 @@
+use crate::analytics_lib::{AnalyticsConfig, EquityCurve, RiskReport};
+use crate::error_lib::{ConfigError, Error, IoError};
+use crate::gas_lib::GasSchedule;
+use crate::gen_lib::{generate_path, Gbm, PathConfig};
+use crate::mgv_lib::Market;
+use crate::output_lib::{CsvSink, JsonLinesSink, DEFAULT_OUTPUT_DIR};
+use crate::params_lib::{ParameterMap, ParameterValue};
+use crate::read_utils::{read_gas_price_feed, stream_price_feed, PriceFeedReader};
+use crate::simu_lib::{PerformanceMetrics, PriceFeed, PricePoint, Simulator, StrategyPnl, DEFAULT_SEED};
+use crate::strats_lib::StrategyFactory;
+use crate::sweep_lib::{ParameterGrid, ParameterSet, Sweep, SweepTable};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File the summary of a run is written to, in the output directory
+pub const SUMMARY_FILE: &str = "summary.json";
+
+/// A simulation described in a TOML or JSON file: the market, its price
+/// feed, the accounts and their strategies, and where the outputs go.
+///
+/// Paths in the file are relative to the working directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+    pub market: MarketConfig,
+    pub price_feed: PriceFeedConfig,
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    #[serde(default)]
+    pub strategies: Vec<StrategyConfig>,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub sweep: Option<SweepConfig>,
+}
+
+fn default_seed() -> u64 {
+    DEFAULT_SEED
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarketConfig {
+    pub base: String,
+    pub quote: String,
+    #[serde(default)]
+    pub gas_profile: Option<String>, // See `GasSchedule::list_profiles`, Ethereum mainnet by default
+    #[serde(default)]
+    pub fee_bps: f64,
+    #[serde(default)]
+    pub gas_price_feed: Option<String>, // File of `block;gas_price_in_gwei` lines
+    #[serde(default)]
+    pub native_price: Option<f64>, // In quote, defaults to the price feed
+}
+
+/// Where the reference prices come from, one per block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum PriceFeedConfig {
+    /// File of `block;price` lines, streamed by `Scenario::build` when `stream`
+    /// is set instead of being loaded in memory. Streamed feeds cannot be swept.
+    File {
+        path: String,
+        #[serde(default)]
+        stream: bool,
+    },
+    Inline { prices: Vec<f64> },
+    /// Geometric Brownian motion seeded by the scenario's seed
+    Gbm {
+        initial_price: f64,
+        n_blocks: usize,
+        mu: f64,    // Annual drift
+        sigma: f64, // Annual volatility
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserConfig {
+    pub id: String,
+    #[serde(default)]
+    pub native: f64, // In wei
+    #[serde(default)]
+    pub balances: BTreeMap<String, f64>,
+}
+
+/// A strategy built by the `StrategyFactory` from `kind` and the remaining
+/// keys, see `StrategyFactory::schema` for the parameters of each kind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrategyConfig {
+    pub id: String,
+    pub account: String,
+    pub kind: String,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub latency: u64, // In blocks
+    #[serde(flatten)]
+    pub parameters: ParameterMap,
+}
+
+// Keys of a strategy that are not parameters of its kind
+const STRATEGY_KEYS: [&str; 5] = ["id", "account", "kind", "priority", "latency"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    JsonLines,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputConfig {
+    #[serde(default = "default_output_dir")]
+    pub dir: String,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub book_snapshots: Option<u64>, // Full snapshot interval, 0 disables the book output
+    #[serde(default)]
+    pub equity_curves: Option<bool>, // Recorded unless the price feed is streamed
+}
+
+fn default_output_dir() -> String {
+    DEFAULT_OUTPUT_DIR.to_string()
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_output_dir(),
+            format: OutputFormat::default(),
+            book_snapshots: None,
+            equity_curves: None,
+        }
+    }
+}
+
+/// Values to sweep, keyed by `<strategy id>.<parameter>`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SweepConfig {
+    pub parameters: BTreeMap<String, Vec<f64>>,
+    #[serde(default)]
+    pub threads: Option<usize>, // One per core by default
+}
+
+impl Scenario {
+    /// Reads a scenario, TOML or JSON according to the file extension
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| IoError::new(format!("reading scenario {}", path.display()), &error))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&content),
+            Some("json") => Self::from_json(&content),
+            _ => Err(ConfigError::InvalidScenario(format!("{} is neither a .toml nor a .json file", path.display())).into()),
+        }
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, Error> {
+        toml::from_str(content).map_err(|error| ConfigError::InvalidScenario(error.to_string()).into())
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, Error> {
+        serde_json::from_str(content).map_err(|error| ConfigError::InvalidScenario(error.to_string()).into())
+    }
+
+    pub fn price_feed(&self) -> Result<Vec<PricePoint>, Error> {
+        let price_feed = match &self.price_feed {
+            PriceFeedConfig::File { path, .. } => PriceFeedReader::open(path)?.collect::<Result<Vec<_>, _>>()?,
+            PriceFeedConfig::Inline { prices } => prices
+                .iter()
+                .enumerate()
+                .map(|(block, price)| PricePoint::new(block as u64, *price))
+                .collect(),
+            PriceFeedConfig::Gbm { initial_price, n_blocks, mu, sigma } => generate_path(
+                Gbm { mu: *mu, sigma: *sigma },
+                PathConfig::new(*initial_price, *n_blocks, self.seed),
+            ),
+        };
+        if price_feed.is_empty() {
+            return Err(ConfigError::InvalidScenario("the price feed is empty".to_string()).into());
+        }
+        Ok(price_feed)
+    }
+
+    /// A simulator ready to run, on the scenario's own price feed
+    pub fn build(&self) -> Result<Simulator, Error> {
+        match &self.price_feed {
+            PriceFeedConfig::File { path, stream: true } => self.build_with_feed(stream_price_feed(path)?),
+            _ => self.build_with_feed(self.price_feed()?),
+        }
+    }
+
+    /// A simulator ready to run on `price_feed`, shared between the runs of a sweep
+    pub fn build_with_feed(&self, price_feed: impl Into<PriceFeed>) -> Result<Simulator, Error> {
+        self.build_with_factory(price_feed, &StrategyFactory::new())
+    }
+
+    /// Same as `build_with_feed`, with strategies registered beyond the built-in ones
+    pub fn build_with_factory(&self, price_feed: impl Into<PriceFeed>, factory: &StrategyFactory) -> Result<Simulator, Error> {
+        let mut market = Market::new(self.market.base.clone(), self.market.quote.clone());
+        if let Some(profile) = &self.market.gas_profile {
+            let schedule = GasSchedule::from_name(profile).ok_or_else(|| {
+                ConfigError::InvalidScenario(format!(
+                    "unknown gas profile {}, expected one of {:?}",
+                    profile,
+                    GasSchedule::list_profiles()
+                ))
+            })?;
+            market.set_gas_schedule(schedule);
+        }
+        market.fee_bps = self.market.fee_bps;
+
+        let mut simulator = Simulator::new(market, price_feed);
+        simulator.set_seed(self.seed);
+        if let Some(path) = &self.market.gas_price_feed {
+            let gas_price_feed = read_gas_price_feed(path)
+                .map_err(|error| ConfigError::InvalidScenario(format!("gas price feed {}: {}", path, error)))?;
+            simulator.set_gas_price_feed(gas_price_feed);
+        }
+        if let Some(native_price) = self.market.native_price {
+            simulator.set_native_price(native_price);
+        }
+
+        for user in &self.users {
+            let account = simulator.add_user(user.id.clone(), user.native);
+            let mut account = account.lock().unwrap();
+            for (token, amount) in &user.balances {
+                account.add_token_balance(token, *amount)?;
+            }
+        }
+        for strategy in &self.strategies {
+            simulator.add_strategy(strategy.id.clone(), factory.create_strategy(&strategy.kind, &strategy.parameters)?);
+            simulator.assign_strategy_with_priority(&strategy.account, &strategy.id, strategy.priority)?;
+            simulator.set_strategy_latency(&strategy.id, strategy.latency)?;
+        }
+
+        simulator.clear_sinks();
+        match self.output.format {
+            OutputFormat::Csv => simulator.add_sink(Box::new(CsvSink::new(&self.output.dir))),
+            OutputFormat::JsonLines => simulator.add_sink(Box::new(JsonLinesSink::new(&self.output.dir))),
+            OutputFormat::None => {}
+        }
+        match self.output.book_snapshots {
+            Some(0) => simulator.disable_book_snapshots(),
+            Some(full_every) => simulator.set_book_snapshots(full_every, true),
+            None => {}
+        }
+        // A streamed feed is meant for runs too long to keep a point per block
+        let streamed = matches!(self.price_feed, PriceFeedConfig::File { stream: true, .. });
+        if !self.output.equity_curves.unwrap_or(!streamed) {
+            simulator.disable_equity_curves();
+        }
+        Ok(simulator)
+    }
+
+    /// Copy of the scenario with swept values, each named `<strategy id>.<parameter>`.
+    /// Parameters are checked against the strategy schemas when it is built.
+    pub fn with_parameters(&self, parameters: &ParameterSet) -> Result<Self, Error> {
+        let mut scenario = self.clone();
+        for (name, value) in &parameters.values {
+            let invalid = |reason: &str| ConfigError::invalid_parameter(name, reason);
+            let (strategy_id, parameter) = name.split_once('.').ok_or_else(|| invalid("expected <strategy id>.<parameter>"))?;
+            let strategy = scenario
+                .strategies
+                .iter_mut()
+                .find(|strategy| strategy.id == strategy_id)
+                .ok_or_else(|| invalid("unknown strategy"))?;
+
+            if STRATEGY_KEYS.contains(&parameter) {
+                return Err(invalid("not a strategy parameter").into());
+            }
+            strategy.parameters.insert(parameter.to_string(), ParameterValue::Float(*value));
+        }
+        Ok(scenario)
+    }
+
+    /// Runs the scenario, writes its outputs and the summary of the run
+    pub fn run(&self, show_progress: bool, verbose: bool) -> Result<RunSummary, Error> {
+        let mut simulator = self.build()?;
+        simulator.run_simulation(show_progress, verbose)?;
+        let summary = RunSummary::new(&self.name, &simulator, &AnalyticsConfig::default());
+        if self.output.format != OutputFormat::None {
+            summary.save(&Path::new(&self.output.dir).join(SUMMARY_FILE))?;
+        }
+        Ok(summary)
+    }
+
+    /// Runs the `sweep` section of the scenario, every run on the same price
+    /// feed. It is held in memory to be shared, so streamed feeds are refused.
+    pub fn run_sweep(&self) -> Result<SweepTable, Error> {
+        let config = self
+            .sweep
+            .as_ref()
+            .ok_or_else(|| ConfigError::InvalidScenario("the scenario has no sweep section".to_string()))?;
+        if let PriceFeedConfig::File { stream: true, .. } = self.price_feed {
+            return Err(ConfigError::InvalidScenario("a streamed price feed cannot be swept".to_string()).into());
+        }
+        let grid = config
+            .parameters
+            .iter()
+            .fold(ParameterGrid::new(), |grid, (name, values)| grid.with(name, values.clone()));
+        let mut sweep = Sweep::new(grid, self.price_feed()?);
+        if let Some(threads) = config.threads {
+            sweep = sweep.with_threads(threads);
+        }
+        sweep.run(|parameters, price_feed| self.with_parameters(parameters)?.build_with_feed(price_feed))
+    }
+}
+
+////////////////////////
+// Summary
+///////////////////////
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSummary {
+    pub account_id: String,
+    pub metrics: PerformanceMetrics,
+    pub risk: RiskReport,
+}
+
+/// Results of a run, saved next to its outputs and read back by `report`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub scenario: String,
+    pub blocks: u64,
+    pub fingerprint: u64,
+    pub accounts: Vec<AccountSummary>, // Sorted by account id
+    pub strategies: Vec<StrategyPnl>,  // In execution order
+}
+
+impl RunSummary {
+    pub fn new(scenario: &str, simulator: &Simulator, analytics: &AnalyticsConfig) -> Self {
+        let mut account_ids: Vec<&String> = simulator.performance_metrics.keys().collect();
+        account_ids.sort();
+        let accounts = account_ids
+            .into_iter()
+            .map(|account_id| AccountSummary {
+                account_id: account_id.clone(),
+                metrics: simulator.performance_metrics[account_id].clone(),
+                risk: simulator
+                    .risk_report(account_id, analytics)
+                    .unwrap_or_else(|| EquityCurve::new().report(analytics)),
+            })
+            .collect();
+        Self {
+            scenario: scenario.to_string(),
+            blocks: simulator.current_block,
+            fingerprint: simulator.run_fingerprint(),
+            accounts,
+            strategies: simulator.strategy_pnl.clone(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |error: std::io::Error| IoError::new(format!("writing summary {}", path.display()), &error);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(|error| io_error(error.into()))?;
+        Ok(fs::write(path, content).map_err(io_error)?)
+    }
+
+    /// Reads a summary, `path` may also be the output directory holding it
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let path: PathBuf = if path.is_dir() { path.join(SUMMARY_FILE) } else { path.to_path_buf() };
+        let content = fs::read_to_string(&path)
+            .map_err(|error| IoError::new(format!("reading summary {}", path.display()), &error))?;
+        serde_json::from_str(&content).map_err(|error| ConfigError::InvalidSummary(error.to_string()).into())
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "=== {} ({} blocks, fingerprint {:016x}) ===", self.scenario, self.blocks, self.fingerprint)?;
+        for account in &self.accounts {
+            let metrics = &account.metrics;
+            writeln!(f, "\nAccount: {}", account.account_id)?;
+            writeln!(f, "Total Trades: {}", metrics.total_trades)?;
+            writeln!(f, "Total Volume: {:.2}", metrics.total_volume)?;
+            writeln!(f, "Total P&L: {:.2}", metrics.total_profit_loss)?;
+            writeln!(f, "Portfolio Value: {:.2} (initial {:.2})", metrics.current_balance, metrics.initial_balance)?;
+            writeln!(f, "HODL Value: {:.2}", metrics.hodl_value)?;
+            writeln!(f, "Gas Spent: {:.2} quote", metrics.gas_spent_quote)?;
+            writeln!(f, "Max Drawdown: {:.2}%", account.risk.max_drawdown * 100.0)?;
+            writeln!(f, "Sharpe: {:.2}", account.risk.sharpe)?;
+        }
+        for strategy in &self.strategies {
+            writeln!(f, "\nStrategy: {} (account {})", strategy.strategy_id, strategy.account_id)?;
+            writeln!(f, "Attributed P&L: {:.2}", strategy.attribution.total())?;
+            for (name, value) in &strategy.metrics {
+                writeln!(f, "{}: {:.4}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
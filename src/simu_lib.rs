@@ -1,13 +1,24 @@
-use crate::mgv_lib::Market;
-use crate::strats_lib::Strategy;
-use crate::chain_lib::User;
+use crate::mgv_lib::{Fill, Market, MarketEvent, OfferSide};
+use crate::strats_lib::{Event, Intent, Strategy, StrategyContext, Trigger};
+use crate::chain_lib::{vault_id, User, WEI_PER_NATIVE};
+use crate::error_lib::{ConfigError, Error, IoError, LedgerError, StrategyError};
+use crate::gas_lib::GasSchedule;
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
-use std::io::Write;
-use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use crate::analytics_lib::{AnalyticsConfig, EquityCurve, EquityPoint, PnlAttribution, RiskReport};
+use crate::observer_lib::{Observer, StrategyAction};
+use crate::output_lib::{BookRecorder, CsvSink, MarketState, OutputSink};
+use crate::params_lib::{ParameterMap, ParameterValue};
+use crate::sweep_lib::ParameterSet;
 
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct PricePoint {
     pub block: u64,
     pub price: f64,
@@ -29,37 +40,339 @@ impl std::fmt::Display for PricePoint {
     }
 }
 
+type PriceStream = Box<dyn Iterator<Item = Result<PricePoint, Error>> + Send>;
+
+/// The prices a simulator runs on, one per block.
+///
+/// Points held in memory can be shared between simulators and read in any
+/// order. Streamed points are pulled one block ahead of the simulation and
+/// dropped once it has moved past them, so the feed takes the same memory
+/// whatever its length. The equity curves still grow by a point per block,
+/// see `disable_equity_curves` for long runs.
+pub enum PriceFeed {
+    Points(Arc<[PricePoint]>),
+    Stream {
+        points: PriceStream,
+        next: Option<PricePoint>, // Point of block `position`, once pulled
+        position: u64,
+    },
+}
+
+impl PriceFeed {
+    pub fn stream<I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = PricePoint>,
+        I::IntoIter: Send + 'static,
+    {
+        Self::try_stream(points.into_iter().map(Ok))
+    }
+
+    /// A stream that can fail while it is read, such as `read_utils::PriceFeedReader`
+    pub fn try_stream<I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = Result<PricePoint, Error>>,
+        I::IntoIter: Send + 'static,
+    {
+        Self::Stream { points: Box::new(points.into_iter()), next: None, position: 0 }
+    }
+
+    /// Number of blocks, when it is known without reading the whole feed
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Self::Points(points) => Some(points.len()),
+            Self::Stream { points, next, position } => match points.size_hint() {
+                (lower, Some(upper)) if lower == upper => Some(*position as usize + next.is_some() as usize + upper),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Point of the `block`-th block, None past the end of the feed. A stream
+    /// skips the blocks before it and cannot go back to them.
+    pub fn point(&mut self, block: u64) -> Result<Option<PricePoint>, Error> {
+        match self {
+            Self::Points(points) => Ok(points.get(block as usize).copied()),
+            Self::Stream { points, next, position } => {
+                if block < *position {
+                    return Err(ConfigError::InvalidFeed(format!(
+                        "block {} was already streamed, the feed is at block {}",
+                        block, position
+                    ))
+                    .into());
+                }
+                while *position < block {
+                    if next.take().is_none() && points.next().transpose()?.is_none() {
+                        break;
+                    }
+                    *position += 1;
+                }
+                if next.is_none() && *position == block {
+                    *next = points.next().transpose()?;
+                }
+                if next.is_none() {
+                    // Exhausted, its length is now known
+                    *points = Box::new(std::iter::empty());
+                }
+                Ok(*next)
+            }
+        }
+    }
+}
+
+impl From<Arc<[PricePoint]>> for PriceFeed {
+    fn from(points: Arc<[PricePoint]>) -> Self {
+        Self::Points(points)
+    }
+}
+
+impl From<Vec<PricePoint>> for PriceFeed {
+    fn from(points: Vec<PricePoint>) -> Self {
+        Self::Points(points.into())
+    }
+}
+
+impl From<&[PricePoint]> for PriceFeed {
+    fn from(points: &[PricePoint]) -> Self {
+        Self::Points(points.into())
+    }
+}
+
+/// Gas price, in gwei, from `block` onwards
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GasPricePoint {
+    pub block: u64,
+    pub gas_price: f64,
+}
+
+impl GasPricePoint {
+    pub fn new(block: u64, gas_price: f64) -> Self {
+        Self { block, gas_price }
+    }
+}
+
 pub struct Simulator {
     pub market: Market,
-    pub price_feed: Vec<PricePoint>,
+    pub price_feed: PriceFeed,
+    pub price_point: Option<PricePoint>, // Of the block being run, or of the last one run
     pub current_block: u64,
     pub users: HashMap<String, Arc<Mutex<User>>>,
     pub performance_metrics: HashMap<String, PerformanceMetrics>,
+    pub equity_curves: HashMap<String, EquityCurve>,
+    pub record_equity_curves: bool,
+    pub pnl_attribution: HashMap<String, PnlAttribution>,
+    pub strategy_pnl: Vec<StrategyPnl>,
     pub strategies: HashMap<String, Box<dyn Strategy>>,              // Added
     pub user_strategies: HashMap<String, Vec<String>>,              // Added
+    pub strategy_latency: HashMap<String, u64>,
+    pub pending_actions: VecDeque<PendingAction>,
+    pub failed_actions: Vec<FailedAction>,
+    pub gas_price_feed: Vec<GasPricePoint>,
+    pub(crate) gas_price_index: usize,
+    pub native_price: Option<f64>,
+    pub halted_strategies: HashSet<String>,
+    pub gas_exhaustion_events: Vec<GasExhaustionEvent>,
+    pub schedule: Vec<ScheduledStrategy>,
+    pub trigger_states: HashMap<String, TriggerState>,
+    pub seed: u64, // Seeds the market RNG the strategies draw from
+    pub start_timestamp: u64, // Unix time of block 0, in seconds
+    pub block_time: u64,      // Seconds between two blocks
+    pub sinks: Vec<Box<dyn OutputSink>>,
+    pub observers: Vec<Box<dyn Observer>>,
+    pub book_recorder: Option<BookRecorder>,
+    pub checkpointing: Option<(u64, PathBuf)>, // Saves a checkpoint every N blocks to the path
 }
 
-#[derive(Debug, Default)]
+pub const DEFAULT_BOOK_SNAPSHOT_INTERVAL: u64 = 100;
+
+pub const DEFAULT_SEED: u64 = 0;
+
+// Ethereum mainnet produces a block every 12 seconds
+pub const DEFAULT_BLOCK_TIME: u64 = 12;
+
+/// A strategy bound to an account, in the order the simulator runs it.
+///
+/// Strategies run by increasing `priority`, ties broken by registration order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledStrategy {
+    pub strategy_id: String,
+    pub account_id: String,
+    pub priority: i32,
+    pub registration: usize,
+}
+
+/// What a strategy saw when it last ran, its triggers are matched against it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TriggerState {
+    pub last_price: Option<f64>,
+    pub last_book: Option<u64>,
+    pub fills: Vec<Fill>, // Fills of the account's offers since the strategy last ran
+}
+
+/// PnL attribution of a strategy, reported at the end of a run.
+///
+/// Fills are booked per account: strategies sharing an account share its
+/// attribution, run them from separate vaults to tell them apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyPnl {
+    pub strategy_id: String,
+    pub account_id: String,
+    pub attribution: PnlAttribution,
+    pub metrics: Vec<(String, f64)>, // From `Strategy::metrics`
+}
+
+/// A strategy decision waiting to land on chain.
+///
+/// The strategy decided on `intents` at block `decided_at`, observing
+/// `decision_point` and the book of that block, but its transactions only
+/// execute at `execute_at`, against the book as it is then.
+#[derive(Debug, Clone)]
+pub struct PendingAction {
+    pub strategy_id: String,
+    pub user: Arc<Mutex<User>>,
+    pub decision_point: PricePoint,
+    pub decided_at: u64,
+    pub execute_at: u64,
+    pub events: Vec<Event>, // Events the decision was made on
+    pub intents: Vec<Intent>,
+}
+
+/// A delayed action that reverted (or never landed) because the market moved
+/// on while it was in flight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedAction {
+    pub strategy_id: String,
+    pub decided_at: u64,
+    pub executed_at: u64,
+    pub reason: String,
+}
+
+/// A user that could no longer pay for gas, and the strategies halted as a result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GasExhaustionEvent {
+    pub block: u64,
+    pub user_id: String,
+    pub halted_strategies: Vec<String>,
+    pub context: String,
+}
+
+/// Metrics of an account, in quote at the price feed reference price.
+///
+/// The portfolio is the base and quote balances of the account, the native
+/// paying for gas and bounties is accounted for through `gas_spent_quote`
+/// and `bounties_quote`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct PerformanceMetrics {
     pub total_trades: u64,
-    pub total_volume: f64,
-    pub total_profit_loss: f64,
-    pub initial_balance: f64,
-    pub current_balance: f64,
+    pub total_volume: f64,      // Traded, in quote
+    pub total_profit_loss: f64, // Portfolio value change net of gas and bounties
+    pub initial_balance: f64,   // Portfolio value at the first block
+    pub current_balance: f64,   // Portfolio value at the last block
+    pub gas_spent_native: f64,
+    pub gas_spent_quote: f64,
+    #[serde(default)]
+    pub bounties_quote: f64, // Received as a taker minus paid for failing offers
+    pub realized_pnl: f64,   // Of the base sold, against its average cost
+    pub unrealized_pnl: f64, // Of the base held, against its average cost
+    pub fees_paid: f64,
+    pub hodl_value: f64,       // Initial balances held untouched
+    pub rebalanced_value: f64, // Initial value kept 50/50 in base and quote every block
+    pub average_cost: f64,     // Of the base held
+    position: f64,
+    initial_base: f64,
+    initial_quote: f64,
+    last_price: f64,
+    initialized: bool,
+}
+
+impl PerformanceMetrics {
+    /// Books a trade of the account and returns its realized PnL
+    pub fn record_trade(&mut self, is_buy: bool, price: f64, base_volume: f64, fee: f64) -> f64 {
+        self.total_trades += 1;
+        self.total_volume += base_volume * price;
+        self.fees_paid += fee;
+        let realized = if is_buy {
+            let held = self.position.max(0.0);
+            if held + base_volume > 0.0 {
+                self.average_cost = (held * self.average_cost + base_volume * price) / (held + base_volume);
+            }
+            self.position += base_volume;
+            0.0
+        } else {
+            self.position -= base_volume;
+            base_volume * (price - self.average_cost)
+        };
+        self.realized_pnl += realized;
+        realized
+    }
+
+    /// Values the balances at `price`, the first call sets the initial
+    /// portfolio the benchmarks start from
+    pub fn mark_to_market(&mut self, price: f64, base: f64, quote: f64) {
+        if !self.initialized {
+            self.initial_base = base;
+            self.initial_quote = quote;
+            self.initial_balance = base * price + quote;
+            self.rebalanced_value = self.initial_balance;
+            self.average_cost = price;
+            self.position = base;
+            self.last_price = price;
+            self.initialized = true;
+        }
+        if self.last_price > 0.0 {
+            // Half of the portfolio is in base at the start of each block
+            self.rebalanced_value *= 1.0 + 0.5 * (price / self.last_price - 1.0);
+        }
+        self.last_price = price;
+        self.hodl_value = self.initial_base * price + self.initial_quote;
+        self.current_balance = base * price + quote;
+        self.unrealized_pnl = base * (price - self.average_cost);
+        self.total_profit_loss = self.current_balance - self.initial_balance - self.gas_spent_quote + self.bounties_quote;
+    }
 }
 
 
 impl Simulator {
 
-    pub fn new(market: Market, price_feed: Vec<PricePoint>) -> Self {
+    /// Takes the price feed as a `Vec`, an already shared `Arc<[PricePoint]>`
+    /// or a `PriceFeed` streamed from any iterator.
+    ///
+    /// Nothing is written until an output is set, see `set_output_dir` and
+    /// `add_sink`.
+    pub fn new(market: Market, price_feed: impl Into<PriceFeed>) -> Self {
         Self {
             market,
-            price_feed,
+            price_feed: price_feed.into(),
+            price_point: None,
             current_block: 0,
             users: HashMap::new(),
             performance_metrics: HashMap::new(),
+            equity_curves: HashMap::new(),
+            record_equity_curves: true,
+            pnl_attribution: HashMap::new(),
+            strategy_pnl: Vec::new(),
             strategies: HashMap::new(),              // Added
             user_strategies: HashMap::new(),         // Added
+            strategy_latency: HashMap::new(),
+            pending_actions: VecDeque::new(),
+            failed_actions: Vec::new(),
+            gas_price_feed: Vec::new(),
+            gas_price_index: 0,
+            native_price: None,
+            halted_strategies: HashSet::new(),
+            gas_exhaustion_events: Vec::new(),
+            schedule: Vec::new(),
+            trigger_states: HashMap::new(),
+            seed: DEFAULT_SEED,
+            start_timestamp: 0,
+            block_time: DEFAULT_BLOCK_TIME,
+            sinks: Vec::new(),
+            observers: Vec::new(),
+            book_recorder: Some(BookRecorder::new(DEFAULT_BOOK_SNAPSHOT_INTERVAL, true)),
+            checkpointing: None,
         }
     }
 
@@ -70,39 +383,222 @@ impl Simulator {
         user
     }
 
-    pub fn step(&mut self) -> Option<&PricePoint> {
-        if self.current_block >= self.price_feed.len() as u64 {
-            return None;
+    pub fn step(&mut self) -> Result<Option<PricePoint>, Error> {
+        let price_point = self.price_feed.point(self.current_block)?;
+        if price_point.is_some() {
+            self.price_point = price_point;
+            self.current_block += 1;
         }
-        
-        let price_point = &self.price_feed[self.current_block as usize];
-        self.current_block += 1;
-        
-        Some(price_point)
+        Ok(price_point)
     }
 
-    pub fn update_metrics(&mut self, user_id: &str, trade_volume: f64, profit_loss: f64) {
+    /// Books a trade of `base_volume` at `price` for `user_id`, bought if `is_buy`
+    pub fn update_metrics(&mut self, user_id: &str, is_buy: bool, price: f64, base_volume: f64, fee: f64) {
         if let Some(metrics) = self.performance_metrics.get_mut(user_id) {
-            metrics.total_trades += 1;
-            metrics.total_volume += trade_volume;
-            metrics.total_profit_loss += profit_loss;
-            // Update current balance from user
-            if let Some(user) = self.users.get(user_id) {
-                if let Ok(user) = user.lock() {
-                    metrics.current_balance = user.get_native_balance() as f64;
+            metrics.record_trade(is_buy, price, base_volume, fee);
+        }
+    }
+
+    // Books the fills and failed offers of the last actions for both their
+    // maker and taker, against the reference price of the current block
+    fn process_fills(&mut self) -> Vec<Fill> {
+        // After the last block, the hooks trade at the last price
+        let Some(price_point) = self.price_point else {
+            return Vec::new();
+        };
+        // Bounties move native, valued in quote when they are paid like gas
+        let native_price = self.get_native_price(&price_point);
+        for failure in self.market.take_offer_failures() {
+            let bounty = failure.bounty / WEI_PER_NATIVE * native_price;
+            if let Some(metrics) = self.performance_metrics.get_mut(&failure.maker_id) {
+                metrics.bounties_quote -= bounty;
+            }
+            if let Some(metrics) = self.performance_metrics.get_mut(&failure.taker_id) {
+                metrics.bounties_quote += bounty;
+            }
+        }
+
+        // Observers see the writes and fills in the order they happened
+        let mut fills = Vec::new();
+        for event in self.market.take_events() {
+            let fill = match event {
+                MarketEvent::OfferWrite(write) => {
+                    self.notify(|observer, simulator| observer.on_offer_write(&write, simulator));
+                    continue;
+                }
+                MarketEvent::Fill(fill) => fill,
+            };
+            self.queue_fill(&fill);
+            let taker_buys = fill.side == OfferSide::Ask;
+            self.update_metrics(&fill.maker_id, !taker_buys, fill.price, fill.base_volume, 0.0);
+            self.update_metrics(&fill.taker_id, taker_buys, fill.price, fill.base_volume, fill.fee);
+
+            // What the maker sold above, or bought below, the reference price
+            let maker_edge = match fill.side {
+                OfferSide::Ask => fill.base_volume * (fill.price - price_point.price),
+                OfferSide::Bid => fill.base_volume * (price_point.price - fill.price),
+            };
+            self.pnl_attribution.entry(fill.maker_id.clone()).or_default().spread_capture += maker_edge;
+            self.pnl_attribution.entry(fill.taker_id.clone()).or_default().taker_edge += -maker_edge - fill.fee;
+            self.notify(|observer, simulator| observer.on_fill(&fill, simulator));
+            fills.push(fill);
+        }
+        fills
+    }
+
+    // Hands a fill to the strategies of its maker that wait for their offers
+    // to be taken
+    fn queue_fill(&mut self, fill: &Fill) {
+        for scheduled in &self.schedule {
+            let waits_for_fills = scheduled.account_id == fill.maker_id
+                && self.strategies.get(&scheduled.strategy_id).is_some_and(|strategy| {
+                    strategy.triggers().iter().any(|trigger| matches!(trigger, Trigger::OfferFilled))
+                });
+            if waits_for_fills {
+                self.trigger_states.entry(scheduled.strategy_id.clone()).or_default().fills.push(fill.clone());
+            }
+        }
+    }
+
+    // Events of the current block matching the triggers of `strategy_id`,
+    // empty if the strategy has nothing to do
+    fn due_events(&mut self, strategy_id: &str, price_point: &PricePoint, is_duplicate: bool) -> Vec<Event> {
+        let Some(strategy) = self.strategies.get(strategy_id) else {
+            return Vec::new();
+        };
+        let triggers = strategy.triggers();
+        // Hashing the book is only worth it for the strategies watching it
+        let book_hash = triggers.contains(&Trigger::BookChanged).then(|| self.market.book_hash());
+        let state = self.trigger_states.entry(strategy_id.to_string()).or_default();
+        let mut events = Vec::new();
+        for trigger in triggers {
+            match trigger {
+                Trigger::EveryBlock if !is_duplicate => events.push(Event::NewPrice),
+                Trigger::EveryNBlocks(every) if every > 0 && self.current_block.is_multiple_of(every) => {
+                    events.push(Event::Interval(every))
                 }
+                Trigger::PriceMove(threshold) => match state.last_price {
+                    None => events.push(Event::NewPrice),
+                    Some(from) if from > 0.0 && (price_point.price / from - 1.0).abs() >= threshold => {
+                        events.push(Event::PriceMoved { from, to: price_point.price })
+                    }
+                    Some(_) => {}
+                },
+                Trigger::OfferFilled => events.extend(state.fills.drain(..).map(Event::OfferFilled)),
+                Trigger::Timer(block) if block == self.current_block => events.push(Event::Timer(block)),
+                Trigger::BookChanged if state.last_book != book_hash => events.push(Event::BookChanged),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    // Values every account at the reference price of the block and extends
+    // its equity curve
+    fn mark_to_market(&mut self, price_point: &PricePoint) {
+        self.revalue_accounts(price_point);
+        if !self.record_equity_curves {
+            return;
+        }
+        for (user_id, metrics) in &self.performance_metrics {
+            if self.users.contains_key(user_id) {
+                self.equity_curves.entry(user_id.clone()).or_default().push(EquityPoint {
+                    block: self.current_block,
+                    price: price_point.price,
+                    value: metrics.current_balance - metrics.gas_spent_quote + metrics.bounties_quote,
+                    volume: metrics.total_volume,
+                });
+            }
+        }
+    }
+
+    // Values every account at the reference price of the block, without
+    // extending the equity curves
+    fn revalue_accounts(&mut self, price_point: &PricePoint) {
+        for (user_id, user) in &self.users {
+            if let (Some(metrics), Ok(user)) = (self.performance_metrics.get_mut(user_id), user.lock()) {
+                metrics.mark_to_market(
+                    price_point.price,
+                    user.get_token_balance(&self.market.base),
+                    user.get_token_balance(&self.market.quote),
+                );
+
+                // Whatever the fills do not explain comes from the price moving
+                let attribution = self.pnl_attribution.entry(user_id.clone()).or_default();
+                attribution.inventory_drift = metrics.current_balance - metrics.initial_balance
+                    - attribution.spread_capture
+                    - attribution.taker_edge;
+                attribution.gas_costs = -metrics.gas_spent_quote;
+                attribution.bounties = metrics.bounties_quote;
             }
         }
     }
 
+    /// Risk statistics of the equity curve of `user_id`, as of the last block simulated
+    pub fn risk_report(&self, user_id: &str, config: &AnalyticsConfig) -> Option<RiskReport> {
+        self.equity_curves.get(user_id).map(|curve| curve.report(config))
+    }
+
+    /// PnL attribution of every scheduled strategy, in execution order
+    pub fn strategy_attribution(&self) -> Vec<StrategyPnl> {
+        self.schedule
+            .iter()
+            .map(|scheduled| StrategyPnl {
+                strategy_id: scheduled.strategy_id.clone(),
+                account_id: scheduled.account_id.clone(),
+                attribution: self.pnl_attribution.get(&scheduled.account_id).copied().unwrap_or_default(),
+                metrics: self.strategies.get(&scheduled.strategy_id).map(|strategy| strategy.metrics()).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Risk statistics of every account, by account id
+    pub fn risk_reports(&self, config: &AnalyticsConfig) -> Vec<(String, RiskReport)> {
+        let mut reports: Vec<(String, RiskReport)> = self.equity_curves
+            .iter()
+            .map(|(user_id, curve)| (user_id.clone(), curve.report(config)))
+            .collect();
+        reports.sort_by(|a, b| a.0.cmp(&b.0));
+        reports
+    }
+
     pub fn print_metrics(&self) {
         println!("\n=== Performance Metrics ===");
-        for (user_id, metrics) in &self.performance_metrics {
+        let mut user_ids: Vec<&String> = self.performance_metrics.keys().collect();
+        user_ids.sort();
+        for user_id in user_ids {
+            let metrics = &self.performance_metrics[user_id];
             println!("\nUser: {}", user_id);
             println!("Total Trades: {}", metrics.total_trades);
             println!("Total Volume: {:.2}", metrics.total_volume);
-            println!("Total P&L: {:.2}", metrics.total_profit_loss);
-            println!("Current Balance: {:.2}", metrics.current_balance);
+            println!("Total P&L: {:.2} (realized {:.2}, unrealized {:.2})", metrics.total_profit_loss, metrics.realized_pnl, metrics.unrealized_pnl);
+            println!("Portfolio Value: {:.2} (initial {:.2})", metrics.current_balance, metrics.initial_balance);
+            println!("HODL Value: {:.2}", metrics.hodl_value);
+            println!("50/50 Rebalanced Value: {:.2}", metrics.rebalanced_value);
+            println!("Fees Paid: {:.2}", metrics.fees_paid);
+            println!("Gas Spent: {:.6} native ({:.2} quote)", metrics.gas_spent_native, metrics.gas_spent_quote);
+            println!("Bounties: {:.2} quote", metrics.bounties_quote);
+        }
+        self.print_strategy_pnl();
+    }
+
+    pub fn print_strategy_pnl(&self) {
+        if self.strategy_pnl.is_empty() {
+            return;
+        }
+        println!("\n=== PnL Attribution ===");
+        for strategy_pnl in &self.strategy_pnl {
+            let attribution = &strategy_pnl.attribution;
+            println!("\nStrategy: {} (account {})", strategy_pnl.strategy_id, strategy_pnl.account_id);
+            println!("Spread Capture: {:.2}", attribution.spread_capture);
+            println!("Taker Edge: {:.2}", attribution.taker_edge);
+            println!("Inventory Drift: {:.2}", attribution.inventory_drift);
+            println!("Gas Costs: {:.2}", attribution.gas_costs);
+            println!("Bounties: {:.2}", attribution.bounties);
+            println!("Total: {:.2}", attribution.total());
+            for (name, value) in &strategy_pnl.metrics {
+                println!("{}: {:.4}", name, value);
+            }
         }
     }
 
@@ -111,158 +607,841 @@ impl Simulator {
         self.strategies.insert(strategy_id, strategy);
     }
 
-    pub fn assign_strategy(&mut self, user_id: &str, strategy_id: &str) -> Result<(), &'static str> {
-        if !self.users.contains_key(user_id) || !self.strategies.contains_key(strategy_id) {
-            return Err("User or strategy not found");
+    pub fn assign_strategy(&mut self, user_id: &str, strategy_id: &str) -> Result<(), Error> {
+        self.assign_strategy_with_priority(user_id, strategy_id, 0)
+    }
+
+    /// Binds a strategy to an account, strategies with a lower `priority` act first in each block
+    pub fn assign_strategy_with_priority(&mut self, user_id: &str, strategy_id: &str, priority: i32) -> Result<(), Error> {
+        if !self.users.contains_key(user_id) {
+            return Err(LedgerError::UnknownAccount(user_id.to_string()).into());
+        }
+        if !self.strategies.contains_key(strategy_id) {
+            return Err(StrategyError::UnknownStrategy(strategy_id.to_string()).into());
+        }
+        // A strategy instance trades out of a single account
+        if self.user_strategies.values().any(|strategy_ids| strategy_ids.iter().any(|id| id == strategy_id)) {
+            return Err(StrategyError::AlreadyAssigned(strategy_id.to_string()).into());
         }
         
         self.user_strategies
             .entry(user_id.to_string())
             .or_default()
             .push(strategy_id.to_string());
+
+        let registration = self.schedule.len();
+        self.schedule.push(ScheduledStrategy {
+            strategy_id: strategy_id.to_string(),
+            account_id: user_id.to_string(),
+            priority,
+            registration,
+        });
+        self.schedule.sort_by_key(|scheduled| (scheduled.priority, scheduled.registration));
         
         Ok(())
     }
 
-    fn write_user_balance(&self, user_id: &str, block: u64, user: &User, truncate: bool) -> std::io::Result<()> {
+    /// Reseeds the simulation RNG, two runs with the same seed and inputs are identical
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.market.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    /// Hash of the simulation state: users, book, halted strategies and failed actions.
+    ///
+    /// Every collection is hashed in a sorted order, so two runs with the same
+    /// inputs and seed give the same fingerprint with a given build.
+    pub fn run_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.current_block.hash(&mut hasher);
+
+        let mut user_ids: Vec<&String> = self.users.keys().collect();
+        user_ids.sort();
+        for user_id in user_ids {
+            let user = self.users[user_id].lock().unwrap();
+            user.id.hash(&mut hasher);
+            user.native.to_bits().hash(&mut hasher);
+            user.gas_spent.to_bits().hash(&mut hasher);
+            let mut balances: Vec<(&String, &f64)> = user.balances.iter().collect();
+            balances.sort_by(|a, b| a.0.cmp(b.0));
+            for (token, balance) in balances {
+                token.hash(&mut hasher);
+                balance.to_bits().hash(&mut hasher);
+            }
+        }
+
+        for offer in self.market.bids.iter().chain(self.market.asks.iter()) {
+            offer.id.hash(&mut hasher);
+            (offer.side == OfferSide::Bid).hash(&mut hasher);
+            offer.price.to_bits().hash(&mut hasher);
+            offer.volume.to_bits().hash(&mut hasher);
+            offer.gasreq.hash(&mut hasher);
+            offer.maker.lock().unwrap().id.hash(&mut hasher);
+        }
+
+        let mut halted_strategies: Vec<&String> = self.halted_strategies.iter().collect();
+        halted_strategies.sort();
+        halted_strategies.hash(&mut hasher);
+        for action in &self.failed_actions {
+            action.strategy_id.hash(&mut hasher);
+            action.decided_at.hash(&mut hasher);
+            action.executed_at.hash(&mut hasher);
+            action.reason.hash(&mut hasher);
+        }
 
-        let file_path = format!("data/output/{}.txt", user_id);
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(truncate)
-            .append(!truncate)
-            .open(file_path)?;
-        let balance_list: Vec<String> = user.get_balance_list()
+        hasher.finish()
+    }
+
+    /// Dates the blocks: block `n` is `start_timestamp + n * block_time`
+    /// seconds after the Unix epoch
+    pub fn set_block_time(&mut self, start_timestamp: u64, block_time: u64) {
+        self.start_timestamp = start_timestamp;
+        self.block_time = block_time;
+        self.set_market_block(self.market.block);
+    }
+
+    // Moves the market to `block`, the strategies read its number and time
+    pub(crate) fn set_market_block(&mut self, block: u64) {
+        self.market.block = block;
+        self.market.timestamp = self.start_timestamp + block * self.block_time;
+    }
+
+    /// Selects the gas costs of the deployment being simulated
+    pub fn set_gas_schedule(&mut self, gas_schedule: GasSchedule) {
+        self.market.set_gas_schedule(gas_schedule);
+    }
+
+    /// Sets the gas price time series, aligned on the price feed blocks.
+    ///
+    /// The market gas price follows the last point at or before the current
+    /// price point; before the first point it keeps its previous value.
+    pub fn set_gas_price_feed(&mut self, mut gas_price_feed: Vec<GasPricePoint>) {
+        gas_price_feed.sort_by_key(|point| point.block);
+        self.gas_price_feed = gas_price_feed;
+        self.gas_price_index = 0;
+    }
+
+    /// Fixes the quote value of one native token.
+    ///
+    /// By default the feed price is used, i.e. the market base is assumed to
+    /// be the wrapped native token (WETH/USDC on an ETH chain).
+    pub fn set_native_price(&mut self, native_price: f64) {
+        self.native_price = Some(native_price);
+    }
+
+    pub fn get_native_price(&self, price_point: &PricePoint) -> f64 {
+        self.native_price.unwrap_or(price_point.price)
+    }
+
+    fn update_gas_price(&mut self, price_point: &PricePoint) {
+        while let Some(point) = self.gas_price_feed.get(self.gas_price_index) {
+            if point.block > price_point.block {
+                break;
+            }
+            self.market.gas_price = point.gas_price;
+            self.gas_price_index += 1;
+        }
+    }
+
+    // Books the gas each user paid during the step at the current native price
+    fn update_gas_metrics(&mut self, price_point: &PricePoint) {
+        let native_price = self.get_native_price(price_point);
+        for (user_id, user) in &self.users {
+            if let (Some(metrics), Ok(user)) = (self.performance_metrics.get_mut(user_id), user.lock()) {
+                let gas_spent_native = user.gas_spent / WEI_PER_NATIVE;
+                metrics.gas_spent_quote += (gas_spent_native - metrics.gas_spent_native) * native_price;
+                metrics.gas_spent_native = gas_spent_native;
+            }
+        }
+    }
+
+    pub fn is_halted(&self, strategy_id: &str) -> bool {
+        self.halted_strategies.contains(strategy_id)
+    }
+
+    /// First block at which `user_id` ran out of native, if it did
+    pub fn gas_exhausted_at(&self, user_id: &str) -> Option<u64> {
+        self.gas_exhaustion_events
             .iter()
-            .map(|b| format!("{}", b))
+            .find(|event| event.user_id == user_id)
+            .map(|event| event.block)
+    }
+
+    // Halts the strategies bound to an account that can no longer pay for
+    // gas, those of its user or of the user's other vaults keep running.
+    // Their offers already on the book stay there, but they will not be
+    // reposted nor will the strategies act again.
+    fn halt_account_strategies(&mut self, user_id: &str, context: &'static str, verbose: bool) {
+        let halted_strategies: Vec<String> = self.user_strategies
+            .get(user_id)
+            .map(|strategy_ids| strategy_ids
+                .iter()
+                .filter(|strategy_id| !self.halted_strategies.contains(*strategy_id))
+                .cloned()
+                .collect())
+            .unwrap_or_default();
+        if halted_strategies.is_empty() && self.gas_exhausted_at(user_id).is_some() {
+            return;
+        }
+        if verbose {
+            println!("User {} ran out of gas at block {} ({}), halting {:?}", user_id, self.current_block, context, halted_strategies);
+        }
+        self.halted_strategies.extend(halted_strategies.iter().cloned());
+        self.pending_actions.retain(|action| !halted_strategies.contains(&action.strategy_id));
+        self.gas_exhaustion_events.push(GasExhaustionEvent {
+            block: self.current_block,
+            user_id: user_id.to_string(),
+            halted_strategies,
+            context: context.to_string(),
+        });
+    }
+
+    // Makers whose reposts were dropped for lack of gas during the last actions
+    fn handle_out_of_gas_reposts(&mut self, verbose: bool) {
+        let reposts: Vec<_> = self.market.out_of_gas_reposts.drain(..).collect();
+        for repost in reposts {
+            self.halt_account_strategies(&repost.maker_id, "repost", verbose);
+        }
+    }
+
+    // Runs a strategy on the events of the current block
+    fn execute_strategy(
+        &mut self,
+        strategy_id: &str,
+        price_point: &PricePoint,
+        user: Arc<Mutex<User>>,
+        events: &[Event],
+        verbose: bool,
+    ) -> Result<(), Error> {
+        let result = self.run_hook(strategy_id, user, "execute", events, verbose, |strategy, context| {
+            strategy.on_events(events, price_point, context)
+        });
+        self.update_trigger_state(strategy_id, price_point);
+        result
+    }
+
+    // Runs a delayed strategy on the events of the current block, on a
+    // sandbox of the market, and returns the intents it decided on. The ids
+    // of the offers it posts are reserved until they land.
+    fn decide_strategy(
+        &mut self,
+        strategy_id: &str,
+        price_point: &PricePoint,
+        user: &Arc<Mutex<User>>,
+        events: &[Event],
+        verbose: bool,
+    ) -> Result<Vec<Intent>, Error> {
+        let Some(strategy) = self.strategies.get_mut(strategy_id) else {
+            return Ok(Vec::new());
+        };
+        let (mut sandbox, account) = self.market.sandbox(user);
+        std::mem::swap(&mut sandbox.rng, &mut self.market.rng);
+        let mut context = StrategyContext::deferred(&mut sandbox, account).with_strategy(Some(strategy_id.to_string()));
+        let result = strategy.on_events(events, price_point, &mut context);
+        let intents = context.into_intents();
+        std::mem::swap(&mut sandbox.rng, &mut self.market.rng);
+        self.market.reserve_offer_ids(sandbox.next_offer_id());
+        self.update_trigger_state(strategy_id, price_point);
+
+        match result {
+            Ok(()) => Ok(intents),
+            // The account cannot pay for the transactions it would send
+            Err(error) if error.is_out_of_gas() => {
+                let account_id = user.lock().unwrap().id.clone();
+                let dry_account = error.out_of_gas_account().unwrap_or(&account_id).to_string();
+                self.halt_account_strategies(&dry_account, "execute", verbose);
+                Ok(Vec::new())
+            }
+            Err(error) => Err(self.report_error(Some(strategy_id), "execute", error)),
+        }
+    }
+
+    // Remembers what a strategy saw when it ran, for its next triggers
+    fn update_trigger_state(&mut self, strategy_id: &str, price_point: &PricePoint) {
+        if let Some(strategy) = self.strategies.get(strategy_id) {
+            let watches_book = strategy.triggers().contains(&Trigger::BookChanged);
+            let state = self.trigger_states.entry(strategy_id.to_string()).or_default();
+            state.last_price = Some(price_point.price);
+            state.last_book = watches_book.then(|| self.market.book_hash());
+        }
+    }
+
+    // Runs one of the methods of a strategy on the current market, then books
+    // its fills. Running out of gas halts the strategy instead of failing the
+    // simulation.
+    fn run_hook<F>(
+        &mut self,
+        strategy_id: &str,
+        user: Arc<Mutex<User>>,
+        context: &'static str,
+        events: &[Event],
+        verbose: bool,
+        hook: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut dyn Strategy, &mut StrategyContext) -> Result<(), Error>,
+    {
+        let Some(strategy) = self.strategies.get_mut(strategy_id) else {
+            return Ok(());
+        };
+        let result = hook(
+            strategy.as_mut(),
+            &mut StrategyContext::new(&mut self.market, Arc::clone(&user)).with_strategy(Some(strategy_id.to_string())),
+        );
+        self.handle_out_of_gas_reposts(verbose);
+        let result = result
+            .and(self.dispatch_fills(strategy_id, &user, verbose))
+            .map_err(|error| self.report_error(Some(strategy_id), context, error));
+        let account_id = user.lock().unwrap().id.clone();
+        let action = StrategyAction {
+            block: self.current_block,
+            strategy_id: strategy_id.to_string(),
+            account_id: account_id.clone(),
+            hook: context,
+            events: events.to_vec(),
+            result: result.clone(),
+        };
+        self.notify(|observer, simulator| observer.on_strategy_action(&action, simulator));
+        match result {
+            Err(error) if error.is_out_of_gas() => {
+                let dry_account = error.out_of_gas_account().unwrap_or(&account_id).to_string();
+                self.halt_account_strategies(&dry_account, context, verbose);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    // Books the fills of the last actions and hands the ones taken by `user`
+    // to the strategy that sent the orders, until it stops trading
+    fn dispatch_fills(&mut self, strategy_id: &str, user: &Arc<Mutex<User>>, verbose: bool) -> Result<(), Error> {
+        let account_id = user.lock().unwrap().id.clone();
+        loop {
+            let fills: Vec<Fill> = self.process_fills().into_iter().filter(|fill| fill.taker_id == account_id).collect();
+            if fills.is_empty() {
+                return Ok(());
+            }
+            let Some(strategy) = self.strategies.get_mut(strategy_id) else {
+                return Ok(());
+            };
+            let mut context = StrategyContext::new(&mut self.market, Arc::clone(user)).with_strategy(Some(strategy_id.to_string()));
+            let mut result = Ok(());
+            for fill in &fills {
+                result = result.and_then(|_| strategy.on_fill(fill, &mut context));
+            }
+            self.handle_out_of_gas_reposts(verbose);
+            if result.is_err() {
+                self.process_fills();
+                return result;
+            }
+        }
+    }
+
+    // Calls a lifecycle hook on every running strategy selected by `only`,
+    // in schedule order
+    fn run_lifecycle_hook<F>(
+        &mut self,
+        context: &'static str,
+        verbose: bool,
+        only: fn(&dyn Strategy) -> bool,
+        hook: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&mut dyn Strategy, &mut StrategyContext) -> Result<(), Error>,
+    {
+        let scheduled: Vec<(String, String)> = self.schedule
+            .iter()
+            .filter(|scheduled| self.strategies.get(&scheduled.strategy_id).is_some_and(|strategy| only(strategy.as_ref())))
+            .map(|scheduled| (scheduled.strategy_id.clone(), scheduled.account_id.clone()))
             .collect();
+        for (strategy_id, account_id) in scheduled {
+            if self.is_halted(&strategy_id) {
+                continue;
+            }
+            if let Some(user) = self.users.get(&account_id).cloned() {
+                self.run_hook(&strategy_id, user, context, &[], verbose, &hook)?;
+            }
+        }
+        Ok(())
+    }
 
-        writeln!(file, "{},{}", block, balance_list.join(","))?;
+    /// Opens a sub-account of `user_id` with its own balances and native.
+    ///
+    /// The vault is registered as an account named `<user_id>.<vault_name>`,
+    /// with its own metrics and output, and starts empty: fund it with
+    /// `allocate` and `allocate_native`.
+    pub fn add_vault(&mut self, user_id: &str, vault_name: &str) -> Result<Arc<Mutex<User>>, LedgerError> {
+        if vault_name.is_empty() || vault_name.contains('.') {
+            return Err(LedgerError::InvalidVaultName(vault_name.to_string()));
+        }
+        let parent = self.get_account(user_id)?;
+        let vault_id = vault_id(user_id, vault_name);
+        if self.users.contains_key(&vault_id) {
+            return Err(LedgerError::VaultExists(vault_id));
+        }
+        let vault = {
+            let mut parent = parent.lock().unwrap();
+            if parent.is_vault() {
+                return Err(LedgerError::NestedVault(user_id.to_string()));
+            }
+            parent.vaults.push(vault_id.clone());
+            User::new_vault(&parent, vault_name)
+        };
+        let vault = Arc::new(Mutex::new(vault));
+        self.users.insert(vault_id.clone(), Arc::clone(&vault));
+        self.performance_metrics.insert(vault_id, PerformanceMetrics::default());
+        Ok(vault)
+    }
 
+    pub fn get_vault(&self, user_id: &str, vault_name: &str) -> Option<Arc<Mutex<User>>> {
+        self.users.get(&vault_id(user_id, vault_name)).cloned()
+    }
+
+    fn get_account(&self, account_id: &str) -> Result<Arc<Mutex<User>>, LedgerError> {
+        self.users
+            .get(account_id)
+            .cloned()
+            .ok_or_else(|| LedgerError::UnknownAccount(account_id.to_string()))
+    }
+
+    // Moves `amount` of `token` between two accounts, the native if `token` is None
+    fn move_funds(&self, from_id: &str, to_id: &str, token: Option<&str>, amount: f64) -> Result<(), LedgerError> {
+        if amount < 0.0 {
+            return Err(LedgerError::NegativeAmount(amount));
+        }
+        let from = self.get_account(from_id)?;
+        let to = self.get_account(to_id)?;
+        if Arc::ptr_eq(&from, &to) {
+            return Self::check_debit(&from.lock().unwrap(), token, amount);
+        }
+        let (mut from, mut to) = (from.lock().unwrap(), to.lock().unwrap());
+
+        // Both sides are checked first, a failed move leaves the balances untouched
+        Self::check_debit(&from, token, amount)?;
+        let credited = match token {
+            Some(token) => to.get_token_balance(token) + amount,
+            None => to.get_native_balance() + amount,
+        };
+        if !credited.is_finite() {
+            let token = token.unwrap_or("native").to_string();
+            return Err(LedgerError::BalanceOverflow { account_id: to.id.clone(), token });
+        }
+        match token {
+            Some(token) => {
+                from.spend_token_balance(token, amount)?;
+                to.add_token_balance(token, amount)
+            }
+            None => {
+                from.spend_native(amount)?;
+                to.add_native(amount);
+                Ok(())
+            }
+        }
+    }
+
+    fn check_debit(account: &User, token: Option<&str>, amount: f64) -> Result<(), LedgerError> {
+        match token {
+            Some(token) if account.get_token_balance(token) < amount => Err(LedgerError::InsufficientBalance {
+                account_id: account.id.clone(),
+                token: token.to_string(),
+                needed: amount,
+                available: account.get_token_balance(token),
+            }),
+            None if account.get_native_balance() < amount => Err(LedgerError::InsufficientNative {
+                account_id: account.id.clone(),
+                needed: amount,
+                available: account.get_native_balance(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves tokens from a user to one of its vaults
+    pub fn allocate(&mut self, user_id: &str, vault_name: &str, token: &str, amount: f64) -> Result<(), LedgerError> {
+        self.move_funds(user_id, &vault_id(user_id, vault_name), Some(token), amount)
+    }
+
+    /// Moves native (in wei) from a user to one of its vaults, to pay the vault's gas
+    pub fn allocate_native(&mut self, user_id: &str, vault_name: &str, amount: f64) -> Result<(), LedgerError> {
+        self.move_funds(user_id, &vault_id(user_id, vault_name), None, amount)
+    }
+
+    /// Moves tokens from a vault back to its user
+    pub fn deallocate(&mut self, user_id: &str, vault_name: &str, token: &str, amount: f64) -> Result<(), LedgerError> {
+        self.move_funds(&vault_id(user_id, vault_name), user_id, Some(token), amount)
+    }
+
+    /// Moves tokens between two vaults of the same user
+    pub fn transfer_between_vaults(
+        &mut self,
+        user_id: &str,
+        from_vault: &str,
+        to_vault: &str,
+        token: &str,
+        amount: f64,
+    ) -> Result<(), LedgerError> {
+        self.move_funds(&vault_id(user_id, from_vault), &vault_id(user_id, to_vault), Some(token), amount)
+    }
+
+    /// Binds a strategy to a vault of `user_id`
+    pub fn assign_strategy_to_vault(&mut self, user_id: &str, vault_name: &str, strategy_id: &str) -> Result<(), Error> {
+        self.assign_strategy(&vault_id(user_id, vault_name), strategy_id)
+    }
+
+    /// Metrics of every vault of `user_id`, keyed by vault id
+    pub fn vault_metrics(&self, user_id: &str) -> Vec<(String, &PerformanceMetrics)> {
+        let Some(user) = self.users.get(user_id) else {
+            return Vec::new();
+        };
+        let vault_ids = user.lock().unwrap().vaults.clone();
+        vault_ids
+            .into_iter()
+            .filter_map(|vault_id| self.performance_metrics.get(&vault_id).map(|metrics| (vault_id, metrics)))
+            .collect()
+    }
+
+    /// Delays every action of `strategy_id` by `blocks` blocks.
+    ///
+    /// With a latency of 0 (the default) the strategy acts on the block it
+    /// observes. Otherwise it decides on the block it observes, but its
+    /// writes and orders land `blocks` steps later, as they were decided: an
+    /// offer gone in the meantime cannot be updated nor retracted, and an
+    /// order fails rather than fill beyond the prices the strategy saw.
+    pub fn set_strategy_latency(&mut self, strategy_id: &str, blocks: u64) -> Result<(), StrategyError> {
+        if !self.strategies.contains_key(strategy_id) {
+            return Err(StrategyError::UnknownStrategy(strategy_id.to_string()));
+        }
+        self.strategy_latency.insert(strategy_id.to_string(), blocks);
         Ok(())
     }
 
-    fn write_market_state(&self, block: u64, truncate: bool, price_point: &PricePoint) -> std::io::Result<()> {
-        let file_path = "data/output/market_state.txt";
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(truncate)
-            .append(!truncate)
-            .open(file_path)?;
-
-        // Write market state data for this block
-        writeln!(
-            file,
-            "{},{},{}", 
-            block,
-            price_point.price,
-            self.market
-        )?;
+    pub fn get_strategy_latency(&self, strategy_id: &str) -> u64 {
+        *self.strategy_latency.get(strategy_id).unwrap_or(&0)
+    }
 
+    /// Current parameters of `strategy_id`, see `Strategy::parameter_schema`
+    pub fn strategy_parameters(&self, strategy_id: &str) -> Result<ParameterMap, StrategyError> {
+        self.strategies
+            .get(strategy_id)
+            .map(|strategy| strategy.parameters())
+            .ok_or_else(|| StrategyError::UnknownStrategy(strategy_id.to_string()))
+    }
+
+    /// Updates a parameter of `strategy_id`, between two calls to `run_until`
+    /// or before the run
+    pub fn set_strategy_parameter(&mut self, strategy_id: &str, name: &str, value: ParameterValue) -> Result<(), Error> {
+        self.strategies
+            .get_mut(strategy_id)
+            .ok_or_else(|| StrategyError::UnknownStrategy(strategy_id.to_string()))?
+            .set_parameter(name, value)
+    }
+
+    /// Sets swept values named `<strategy id>.<parameter>`, on any strategy
+    /// exposing that parameter. Each value takes the kind of the parameter
+    /// in the strategy's schema.
+    pub fn apply_parameters(&mut self, parameters: &ParameterSet) -> Result<(), Error> {
+        for (name, value) in &parameters.values {
+            let (strategy_id, parameter) = name
+                .split_once('.')
+                .ok_or_else(|| ConfigError::invalid_parameter(name, "expected <strategy id>.<parameter>"))?;
+            let spec = self
+                .strategies
+                .get(strategy_id)
+                .ok_or_else(|| StrategyError::UnknownStrategy(strategy_id.to_string()))?
+                .parameter_schema()
+                .into_iter()
+                .find(|spec| spec.name == parameter);
+            // Outside the schema, the strategy rejects the parameter itself
+            let value = match spec {
+                Some(spec) => spec.swept_value(*value)?,
+                None => ParameterValue::Float(*value),
+            };
+            self.set_strategy_parameter(strategy_id, parameter, value)?;
+        }
         Ok(())
     }
 
-    pub fn run_simulation(&mut self, show_progress: bool, verbose: bool) -> Result<(), &'static str> {
+    // Lands every queued action due at the current block, its intents in the
+    // order they were decided. An action stops at its first intent that no
+    // longer holds, recorded instead of aborting the simulation: the book it
+    // was decided against may simply not exist anymore.
+    fn execute_pending_actions(&mut self, verbose: bool) {
+        while let Some(action) = self.pending_actions.front() {
+            if action.execute_at > self.current_block {
+                break;
+            }
+            let action = self.pending_actions.pop_front().unwrap();
+            if verbose {
+                println!(
+                    "Landing action of {} decided at block {}",
+                    action.strategy_id, action.decided_at
+                );
+            }
+            let intents = action.intents;
+            let landed = self.run_hook(&action.strategy_id, action.user, "execute", &action.events, verbose, |_, context| {
+                intents.iter().try_for_each(|intent| context.land(intent)).map_err(Error::from)
+            });
+            if let Err(reason) = landed {
+                if verbose {
+                    println!("Delayed action of {} failed: {}", action.strategy_id, reason);
+                }
+                self.failed_actions.push(FailedAction {
+                    strategy_id: action.strategy_id,
+                    decided_at: action.decided_at,
+                    executed_at: self.current_block,
+                    reason: reason.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Replaces the outputs by CSV files in `output_dir`
+    pub fn set_output_dir(&mut self, output_dir: &str) {
+        self.sinks = vec![Box::new(CsvSink::new(output_dir))];
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn OutputSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Removes every output, nothing is written during the run
+    pub fn clear_sinks(&mut self) {
+        self.sinks.clear();
+    }
+
+    /// Writes a full book snapshot every `full_every` blocks, and the offers
+    /// that changed in between if `deltas` is set
+    pub fn set_book_snapshots(&mut self, full_every: u64, deltas: bool) {
+        self.book_recorder = Some(BookRecorder::new(full_every, deltas));
+    }
+
+    pub fn disable_book_snapshots(&mut self) {
+        self.book_recorder = None;
+    }
+
+    /// Stops recording a valuation per block and account, so that a long run
+    /// does not grow in memory. The metrics are kept, the risk reports are
+    /// computed on the points recorded so far.
+    pub fn disable_equity_curves(&mut self) {
+        self.record_equity_curves = false;
+    }
+
+    // Balance columns of an account: native, the market tokens, then any other token
+    fn balance_columns(&self, user: &User) -> Vec<(String, f64)> {
+        let mut balances = vec![
+            ("native".to_string(), user.get_native_balance()),
+            (self.market.base.clone(), user.get_token_balance(&self.market.base)),
+            (self.market.quote.clone(), user.get_token_balance(&self.market.quote)),
+        ];
+        balances.extend(
+            user.get_labeled_balances()
+                .into_iter()
+                .filter(|(token, _)| token != "native" && *token != self.market.base && *token != self.market.quote),
+        );
+        balances
+    }
+
+    fn write_outputs(&mut self, block: u64, price_point: &PricePoint) -> std::io::Result<()> {
+        if self.sinks.is_empty() {
+            return Ok(());
+        }
+        let mut user_ids: Vec<&String> = self.users.keys().collect();
+        user_ids.sort();
+        let rows: Vec<(String, Vec<(String, f64)>)> = user_ids
+            .into_iter()
+            .map(|user_id| (user_id.clone(), self.balance_columns(&self.users[user_id].lock().unwrap())))
+            .collect();
+        let market_state = MarketState::new(block, price_point.price, &self.market);
+        let book_rows = match self.book_recorder.as_mut() {
+            Some(book_recorder) => book_recorder.rows(block, &self.market),
+            None => Vec::new(),
+        };
+
+        for sink in self.sinks.iter_mut() {
+            for (user_id, balances) in &rows {
+                sink.write_balances(block, user_id, balances)?;
+            }
+            for (user_id, _) in &rows {
+                if let Some(metrics) = self.performance_metrics.get(user_id) {
+                    sink.write_metrics(block, user_id, metrics)?;
+                }
+            }
+            sink.write_market_state(&market_state)?;
+            if !book_rows.is_empty() {
+                sink.write_book(&book_rows)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run_simulation(&mut self, show_progress: bool, verbose: bool) -> Result<(), Error> {
         if verbose {
             println!("Running simulation...");
-            println!("Price feed length: {}", self.price_feed.len());
+            match self.price_feed.len() {
+                Some(len) => println!("Price feed length: {}", len),
+                None => println!("Price feed length: streamed"),
+            }
             println!("Users: {:?}", self.users);
             println!("Market: {:?}", self.market);
             println!("--------------------------------");
             println!("--------------------------------");
         }
+        self.run_until(u64::MAX, show_progress, verbose)?;
+        self.finish_simulation(verbose)?;
+
+        if show_progress {
+            println!("Simulation progress: 100%");
+        }
+
+        Ok(())
+    }
+
+    /// Runs the blocks before `end_block`, or up to the end of the feed. The
+    /// simulation can be checkpointed and resumed from there. A simulator
+    /// restored from a checkpoint carries on from its `current_block`.
+    pub fn run_until(&mut self, end_block: u64, show_progress: bool, verbose: bool) -> Result<(), Error> {
+        if self.current_block >= end_block {
+            return Ok(());
+        }
+        // Streams of unknown length report their progress every million blocks
         let total_steps = self.price_feed.len();
-        let progress_interval = total_steps / 10;
-        
+        let progress_interval = total_steps.map_or(1_000_000, |total_steps| (total_steps / 10).max(1));
 
         let mut last_price_point: Option<PricePoint> = None;
-        let mut last_write_block = 0u64;
+        if self.current_block == 0 {
+            let Some(first_price_point) = self.price_feed.point(0)? else {
+                return Ok(());
+            };
+            self.price_point = Some(first_price_point);
+            self.set_market_block(0);
+            self.run_lifecycle_hook("on_start", verbose, |_| true, |strategy, context| {
+                strategy.on_start(&first_price_point, context)
+            })?;
 
-        // Write initial balance data
-        for (user_id, user) in &self.users {
-            if let Ok(user) = user.lock() {
-                self.write_user_balance(user_id, 0, &user, true);
-            }
-        }
-        if let Err(_) = self.write_market_state(0, true, &self.price_feed[0]) {
-            return Err("Failed to write initial market state");
+            // The accounts are valued before anyone trades, block 0 itself is
+            // written once it has run, like the equity curves
+            self.revalue_accounts(&first_price_point);
+        } else {
+            // Resuming: the previous block decides whether this one is a duplicate
+            last_price_point = self.price_point;
         }
-        let mut price_point = &self.price_feed[0];
-        while self.current_block < self.price_feed.len() as u64 {
-            if show_progress && self.current_block as usize % progress_interval == 0 {
-                println!("Simulation progress: {}%", (self.current_block as usize * 100) / total_steps);
+        while self.current_block < end_block {
+            let Some(price_point) = self.price_feed.point(self.current_block)? else {
+                break;
+            };
+            if show_progress && (self.current_block as usize).is_multiple_of(progress_interval) {
+                match total_steps {
+                    Some(total_steps) => println!("Simulation progress: {}%", (self.current_block as usize * 100) / total_steps),
+                    None => println!("Simulation progress: block {}", self.current_block),
+                }
             }
 
-            price_point = &self.price_feed[self.current_block as usize];
-            if let Some(last_pp) = last_price_point {
-                if price_point.price_equals(&last_pp) {
-                    // Write balance data for each user
-                    for (user_id, user) in &self.users {
-                        if let Ok(user) = user.lock() {
-                            if let Err(_) = self.write_user_balance(user_id, self.current_block, &user, false) {
-                                return Err("Failed to write balance data");
-                            }
-                        }
-                    }
-                    if let Err(_) = self.write_market_state(self.current_block, false, price_point) {
-                        return Err("Failed to write market state");
-                    }
-                    self.current_block += 1;
+            self.price_point = Some(price_point);
+            self.set_market_block(self.current_block);
+            self.update_gas_price(&price_point);
+            let block = self.current_block;
+            self.notify(|observer, simulator| observer.on_step_start(block, &price_point, simulator));
+
+            // Land the actions decided in previous blocks first
+            self.execute_pending_actions(verbose);
+
+            let is_duplicate = last_price_point.is_some_and(|last_pp| price_point.price_equals(&last_pp));
+            last_price_point = Some(price_point);
+
+            // Run the strategies whose triggers match, each one sees what the
+            // strategies before it did in this block
+            let scheduled: Vec<(String, String)> = self.schedule
+                .iter()
+                .map(|scheduled| (scheduled.strategy_id.clone(), scheduled.account_id.clone()))
+                .collect();
+            for (strategy_id, account_id) in scheduled {
+                let Some(user) = self.users.get(&account_id).cloned() else {
+                    continue;
+                };
+                if self.is_halted(&strategy_id) {
                     continue;
                 }
-            }
-            
-            // Collect all the actions we need to take
-            let mut actions = Vec::new();
-            for (user_id, strategy_ids) in &self.user_strategies {
-                if let Some(user) = self.users.get(user_id) {
-                    for strategy_id in strategy_ids {
-                        if let Some(strategy) = self.strategies.get(strategy_id) {
-                            actions.push((strategy_id.clone(), Arc::clone(user)));
-                        }
-                    }
+                let events = self.due_events(&strategy_id, &price_point, is_duplicate);
+                if events.is_empty() {
+                    continue;
                 }
-            }
-            
-            // Execute the actions
-            if verbose && !actions.is_empty() {
-                println!("--------------------------------");
-                println!("Price point: {}", price_point);
-                println!("Market: {}", self.market);
-                println!("Actions: {:?}", actions);
-            }
-
-            for (strategy_id, user) in actions {
-                if let Some(strategy) = self.strategies.get_mut(&strategy_id) {
-                    if verbose {
-                        println!("Executing strategy: {}", strategy_id);
-                        println!("User: {:?}", user);
+                let latency = self.get_strategy_latency(&strategy_id);
+                if latency > 0 {
+                    // Decided on this book, the transactions land later against the book at that time
+                    let intents = self.decide_strategy(&strategy_id, &price_point, &user, &events, verbose)?;
+                    if self.is_halted(&strategy_id) {
+                        continue;
                     }
-                    strategy.execute(price_point, &mut self.market, user)?;
+                    self.pending_actions.push_back(PendingAction {
+                        strategy_id,
+                        user,
+                        decision_point: price_point,
+                        decided_at: self.current_block,
+                        execute_at: self.current_block + latency,
+                        events,
+                        intents,
+                    });
+                    continue;
                 }
-            }
-
-            // Write balance data for each user
-            for (user_id, user) in &self.users {
-                if let Ok(user) = user.lock() {
-                    if let Err(_) = self.write_user_balance(user_id, self.current_block, &user, false) {
-                        return Err("Failed to write balance data");
-                    }
+                if verbose {
+                    println!("--------------------------------");
+                    println!("Price point: {}", price_point);
+                    println!("Market: {}", self.market);
+                    println!("Executing strategy: {} on {:?}", strategy_id, events);
+                    println!("User: {:?}", user);
                 }
+                self.execute_strategy(&strategy_id, &price_point, user, &events, verbose)?;
             }
-            if let Err(_) = self.write_market_state(self.current_block, false, price_point) {
-                return Err("Failed to write market state");
-            }
+            // Keep the queue ordered by landing block
+            self.pending_actions.make_contiguous().sort_by_key(|action| action.execute_at);
+
+            self.run_lifecycle_hook("on_block", verbose, |strategy| strategy.runs_on_block(), |strategy, context| {
+                strategy.on_block(&price_point, context)
+            })?;
+
+            self.update_gas_metrics(&price_point);
+            self.mark_to_market(&price_point);
+
+            // Write balance and market data
+            self.write_outputs(self.current_block, &price_point)
+                .map_err(|error| self.report_error(None, "outputs", IoError::new("writing outputs", &error).into()))?;
+            self.notify(|observer, simulator| observer.on_step_end(block, &price_point, simulator));
 
             self.current_block += 1;
+            self.auto_checkpoint().map_err(|error| self.report_error(None, "checkpoint", error))?;
         }
+        Ok(())
+    }
 
-        if show_progress {
-            println!("Simulation progress: 100%");
+    /// Closes the run: in flight actions fail, outputs are flushed and the
+    /// PnL is attributed to the strategies
+    fn finish_simulation(&mut self, verbose: bool) -> Result<(), Error> {
+        // Actions still in flight never made it on chain
+        while let Some(action) = self.pending_actions.pop_front() {
+            self.failed_actions.push(FailedAction {
+                strategy_id: action.strategy_id,
+                decided_at: action.decided_at,
+                executed_at: action.execute_at,
+                reason: "Simulation ended before action landed".to_string(),
+            });
         }
 
+        // The strategies unwind at the last price, the final valuation includes it
+        if let Some(last_price_point) = self.price_point {
+            self.run_lifecycle_hook("on_end", verbose, |_| true, |strategy, context| {
+                strategy.on_end(&last_price_point, context)
+            })?;
+            self.update_gas_metrics(&last_price_point);
+            self.revalue_accounts(&last_price_point);
+        }
+
+        let flushed = self.sinks.iter_mut().try_for_each(|sink| sink.flush());
+        flushed.map_err(|error| self.report_error(None, "outputs", IoError::new("flushing outputs", &error).into()))?;
+
+        self.strategy_pnl = self.strategy_attribution();
+        if verbose {
+            self.print_strategy_pnl();
+        }
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,19 +1,29 @@
-use crate::strats_lib::Strategy;
+use crate::error_lib::{ConfigError, Error, StrategyError};
+use crate::strats_lib::{Strategy, StrategyContext};
+use crate::strats::kandel::KandelStrategy;
 use crate::simu_lib::PricePoint;
-use crate::mgv_lib::{Market, Offer};
-use crate::chain_lib::User;
-use std::sync::{Arc, Mutex};
+use crate::mgv_lib::Offer;
+use crate::params_lib::{check_parameter, ParameterSpec, ParameterValue, Parameters};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
+// Half width of the grid, in standard deviations of the window's log prices
+const RANGE_STD_DEVS: f64 = 2.0;
+// Gridstep of a window whose price did not move
+const MIN_GRIDSTEP: f64 = 1.001;
+
+#[derive(Serialize, Deserialize)]
 pub struct ActiveKandelStrategy {
     window_size: usize,
     recalibration_interval: u64,
+    n_points: usize, // Of each side of the calibrated grid
     price_history: VecDeque<f64>,
     last_calibration: u64,
     kandel_params: KandelParams,
     initialized: bool,
 }
 
+#[derive(Serialize, Deserialize)]
 struct KandelParams {
     reference_price: f64,
     base_amount: f64,
@@ -24,10 +34,11 @@ struct KandelParams {
 }
 
 impl ActiveKandelStrategy {
-    pub fn new(window_size: usize, recalibration_interval: u64, quote_amount: f64, base_amount: f64) -> Self {
+    pub fn new(window_size: usize, recalibration_interval: u64, n_points: usize, base_amount: f64, quote_amount: f64) -> Self {
         Self {
             window_size,
             recalibration_interval,
+            n_points,
             price_history: VecDeque::with_capacity(window_size),
             last_calibration: 0,
 
@@ -36,14 +47,40 @@ impl ActiveKandelStrategy {
                 n_points: 0,
                 range_multiplier: 0.0,
                 gridstep: 0.0,
-                base_amount: base_amount,
-                quote_amount: quote_amount,
+                base_amount,
+                quote_amount,
             },
             initialized: false,
         }
     }
 
-    fn set_parameters(
+    pub fn schema() -> Vec<ParameterSpec> {
+        vec![
+            ParameterSpec::int("window_size", "Prices the grid is calibrated on")
+                .min(1.0)
+                .default_value(ParameterValue::Int(3600)),
+            ParameterSpec::int("recalibration_interval", "Blocks between two calibrations")
+                .min(1.0)
+                .default_value(ParameterValue::Int(3600)),
+            ParameterSpec::int("n_points", "Price points on each side of the last price")
+                .min(1.0)
+                .default_value(ParameterValue::Int(5)),
+            ParameterSpec::float("base_amount", "Base deployed over the asks").above(0.0),
+            ParameterSpec::float("quote_amount", "Quote deployed over the bids").above(0.0),
+        ]
+    }
+
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self, ConfigError> {
+        Ok(Self::new(
+            parameters.int("window_size")? as usize,
+            parameters.int("recalibration_interval")? as u64,
+            parameters.int("n_points")? as usize,
+            parameters.float("base_amount")?,
+            parameters.float("quote_amount")?,
+        ))
+    }
+
+    pub fn set_parameters(
         &mut self, 
         reference_price: f64, 
         base_amount: f64, 
@@ -51,30 +88,48 @@ impl ActiveKandelStrategy {
         n_points: Option<usize>, 
         range_multiplier: Option<f64>, 
         gridstep: Option<f64>
-    ) -> Result<(), &'static str> {
-        self.kandel_params.reference_price = reference_price;
-        self.kandel_params.base_amount = base_amount;
-        self.kandel_params.quote_amount = quote_amount;
-        self.kandel_params.n_points = n_points.unwrap();
-        self.kandel_params.range_multiplier = range_multiplier.unwrap();
-        self.kandel_params.gridstep = gridstep.unwrap();
+    ) -> Result<(), ConfigError> {
+        // The Kandel checks the parameters and completes the missing one
+        let kandel = KandelStrategy::new(reference_price, base_amount, quote_amount, n_points, range_multiplier, gridstep)?;
+        self.kandel_params = KandelParams {
+            reference_price,
+            base_amount,
+            quote_amount,
+            n_points: kandel.n_points(),
+            range_multiplier: kandel.range_multiplier(),
+            gridstep: kandel.gridstep(),
+        };
         Ok(())
     }
-    
 
-    fn deploy_kandel(&mut self, market: &mut Market, user: Arc<Mutex<User>>) -> Result<(), &'static str> {
+    /// Centres the grid on the last price of the window and spreads it over
+    /// `RANGE_STD_DEVS` standard deviations of its log prices
+    fn calibrate(&mut self) -> Result<(), ConfigError> {
+        let Some(&reference_price) = self.price_history.back() else {
+            return Err(ConfigError::invalid_parameter("window_size", "no price to calibrate on"));
+        };
+        let count = self.price_history.len() as f64;
+        let mean = self.price_history.iter().map(|price| price.ln()).sum::<f64>() / count;
+        let variance = self.price_history.iter().map(|price| (price.ln() - mean).powi(2)).sum::<f64>() / count;
+        let range_multiplier = (RANGE_STD_DEVS * variance.sqrt()).exp();
+        let gridstep = range_multiplier.powf(1.0 / self.n_points as f64).max(MIN_GRIDSTEP);
+        let (base_amount, quote_amount) = (self.kandel_params.base_amount, self.kandel_params.quote_amount);
+        self.set_parameters(reference_price, base_amount, quote_amount, Some(self.n_points), None, Some(gridstep))
+    }
+
+    fn deploy_kandel(&mut self, context: &mut StrategyContext) -> Result<(), Error> {
         // Create and configure a new Kandel strategy
-        let mut kandel = crate::strats::kandel::KandelStrategy::new(
+        let mut kandel = KandelStrategy::new(
             self.kandel_params.reference_price,
             self.kandel_params.base_amount,
             self.kandel_params.quote_amount,
             Some(self.kandel_params.n_points),
-            Some(self.kandel_params.range_multiplier),
+            None, // Follows from the other two
             Some(self.kandel_params.gridstep)
-        )?; // Add ? here to propagate the error
+        )?;
         
         // Execute the Kandel strategy
-        kandel.execute(&PricePoint::new(0, 0.0), market, user)?;
+        kandel.execute(&PricePoint::new(0, 0.0), context)?;
         
         Ok(())
     }
@@ -89,12 +144,7 @@ impl Strategy for ActiveKandelStrategy {
         "Deploys Kandel strategy after collecting price data and recalibrates periodically"
     }
 
-    fn execute(
-        &mut self,
-        price_point: &PricePoint,
-        market: &mut Market,
-        user: Arc<Mutex<User>>,
-    ) -> Result<(), &'static str> {
+    fn execute(&mut self, price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
         // Add current price to history
         self.price_history.push_back(price_point.price);
         if self.price_history.len() > self.window_size {
@@ -102,29 +152,71 @@ impl Strategy for ActiveKandelStrategy {
         }
 
         // Check if we should deploy/recalibrate
-        if self.price_history.len() == self.window_size {
-            if !self.initialized || 
-               (price_point.block - self.last_calibration >= self.recalibration_interval) {
-                // Clear existing orders before recalibrating
-                market.bids.clear();
-                market.asks.clear();
-
-                // Deploy new Kandel grid
-                self.deploy_kandel(market, user)?;
-                self.last_calibration = price_point.block;
-                self.initialized = true;
+        if self.price_history.len() == self.window_size
+            && (!self.initialized || price_point.block.saturating_sub(self.last_calibration) >= self.recalibration_interval)
+        {
+            // Retract the previous grid before recalibrating
+            for offer in context.own_offers() {
+                context.retract_offer(offer.offer_id)?;
             }
+
+            // Deploy a new Kandel grid around the window
+            self.calibrate()?;
+            self.deploy_kandel(context)?;
+            self.last_calibration = price_point.block;
+            self.initialized = true;
         }
 
         Ok(())
     }
 
-    fn post_hook(
-        &mut self,
-        market: &mut Market,
-        maker: Arc<Mutex<User>>,
-        filled_offer: &Offer,
-    ) -> Result<(), &'static str> {
+    fn post_hook(&mut self, _context: &mut StrategyContext, _filled_offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn parameter_schema(&self) -> Vec<ParameterSpec> {
+        Self::schema()
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<ParameterValue> {
+        match name {
+            "window_size" => Some(ParameterValue::Int(self.window_size as i64)),
+            "recalibration_interval" => Some(ParameterValue::Int(self.recalibration_interval as i64)),
+            "n_points" => Some(ParameterValue::Int(self.n_points as i64)),
+            "base_amount" => Some(ParameterValue::Float(self.kandel_params.base_amount)),
+            "quote_amount" => Some(ParameterValue::Float(self.kandel_params.quote_amount)),
+            _ => None,
+        }
+    }
+
+    // Takes effect at the next calibration
+    fn set_parameter(&mut self, name: &str, value: ParameterValue) -> Result<(), Error> {
+        match (name, check_parameter(&Self::schema(), name, value)?) {
+            ("window_size", ParameterValue::Int(window_size)) => {
+                self.window_size = window_size as usize;
+                while self.price_history.len() > self.window_size {
+                    self.price_history.pop_front();
+                }
+            }
+            ("recalibration_interval", ParameterValue::Int(interval)) => self.recalibration_interval = interval as u64,
+            ("n_points", ParameterValue::Int(n_points)) => self.n_points = n_points as usize,
+            ("base_amount", ParameterValue::Float(amount)) => self.kandel_params.base_amount = amount,
+            ("quote_amount", ParameterValue::Float(amount)) => self.kandel_params.quote_amount = amount,
+            _ => return Err(StrategyError::UnknownParameter(name.to_string()).into()),
+        }
+        Ok(())
+    }
+
+    fn factory_name(&self) -> Option<&str> {
+        Some("active_kandel")
+    }
+
+    fn save_state(&self) -> Result<serde_json::Value, Error> {
+        serde_json::to_value(self).map_err(|error| StrategyError::InvalidState(error.to_string()).into())
+    }
+
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), Error> {
+        *self = Self::deserialize(state).map_err(|error| StrategyError::InvalidState(error.to_string()))?;
         Ok(())
     }
 }
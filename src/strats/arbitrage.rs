@@ -1,13 +1,16 @@
-use crate::strats_lib::Strategy;
+use crate::error_lib::{ConfigError, Error, StrategyError};
+use crate::strats_lib::{Strategy, StrategyContext};
 use crate::simu_lib::PricePoint;
-use crate::mgv_lib::{Market, OrderSide, Offer};
-use crate::chain_lib::User;
-use std::sync::{Arc, Mutex};
+use crate::mgv_lib::{Fill, OrderSide, Offer};
+use crate::params_lib::{check_parameter, ParameterSpec, ParameterValue, Parameters};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ArbitrageStrategy {
     min_profit_threshold: f64,
     max_volume_per_trade: f64,
+    fills: u64,         // Offers taken so far
+    base_volume: f64,   // Base traded so far
 }
 
 impl ArbitrageStrategy {
@@ -15,8 +18,23 @@ impl ArbitrageStrategy {
         Self {
             min_profit_threshold,
             max_volume_per_trade,
+            fills: 0,
+            base_volume: 0.0,
         }
     }
+
+    pub fn schema() -> Vec<ParameterSpec> {
+        vec![
+            ParameterSpec::float("min_profit_threshold", "Minimum profit of a trade, in quote")
+                .min(0.0)
+                .default_value(ParameterValue::Float(0.0)),
+            ParameterSpec::float("max_volume_per_trade", "Largest order sent, in base").above(0.0),
+        ]
+    }
+
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self, ConfigError> {
+        Ok(Self::new(parameters.float("min_profit_threshold")?, parameters.float("max_volume_per_trade")?))
+    }
 }
 
 impl Strategy for ArbitrageStrategy {
@@ -28,88 +46,96 @@ impl Strategy for ArbitrageStrategy {
         "Executes trades when market prices deviate from reference price"
     }
 
-    fn execute(
-        &mut self,
-        price_point: &PricePoint,
-        market: &mut Market,
-        user: Arc<Mutex<User>>,
-    ) -> Result<(), &'static str> {
+    fn execute(&mut self, price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
         let reference_price = price_point.price;
-        
+
+        // Every trade is hedged at the reference price, so the account needs
+        // no inventory
         loop {
-            // Get market state and immediately drop the references
-            let best_bid = market.best_bid();
-            let best_ask = market.best_ask();
+            let best_bid = context.best_bid();
+            let best_ask = context.best_ask();
             
             // Exit if no orders in the book
             if best_bid.is_none() && best_ask.is_none() {
                 break;
             }
     
-            let mut traded = false;
-    
             // Process bid side first, without holding any references
             if let Some(bid) = best_bid {
-                if (bid.price as f64) - reference_price > self.min_profit_threshold {
+                if bid.price - reference_price > self.min_profit_threshold {
                     let volume = bid.volume.min(self.max_volume_per_trade);
-                    user.lock().unwrap().add_token_balance(&market.base, volume as f64);
-                    market.market_order(&user, OrderSide::Sell, volume)?;
-                    user.lock().unwrap().spend_token_balance(&market.quote, reference_price * volume as f64)?;
-                    traded = true;
-                    // Continue to next iteration immediately if we traded
-                    continue;
+                    if context.take_hedged(OrderSide::Sell, volume, reference_price)? > 0.0 {
+                        // Continue to next iteration immediately if we traded
+                        continue;
+                    }
                 }
             }
     
             // Process ask side if we didn't trade on bid side
             if let Some(ask) = best_ask {
-                if reference_price - (ask.price as f64) > self.min_profit_threshold {
+                if reference_price - ask.price > self.min_profit_threshold {
                     let volume = ask.volume.min(self.max_volume_per_trade);
-                    user.lock().unwrap().add_token_balance(&market.quote, reference_price * volume as f64);
-                    market.market_order(&user, OrderSide::Buy, volume)?;
-                    user.lock().unwrap().spend_token_balance(&market.base, volume as f64)?;
-                    traded = true;
-                    continue;
+                    if context.take_hedged(OrderSide::Buy, volume, reference_price)? > 0.0 {
+                        continue;
+                    }
                 }
             }
     
             // Exit if no profitable trades were made this iteration
-            if !traded {
-                break;
-            }
+            break;
         }
     
         Ok(())
     }
 
-    fn post_hook(
-        &mut self,
-        market: &mut Market,
-        maker: Arc<Mutex<User>>,
-        filled_offer: &Offer,
-    ) -> Result<(), &'static str> {
+    fn post_hook(&mut self, _context: &mut StrategyContext, _filled_offer: &Offer) -> Result<(), Error> {
         Ok(())
     }
 
-    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), &'static str> {
-        match name {
-            "min_profit_threshold" => {
-                self.min_profit_threshold = value;
-                Ok(())
-            }
-            "max_volume_per_trade" => {
-                self.max_volume_per_trade = value;
-                Ok(())
-            }
-            _ => Err("Unknown parameter"),
-        }
+    fn on_fill(&mut self, fill: &Fill, _context: &mut StrategyContext) -> Result<(), Error> {
+        self.fills += 1;
+        self.base_volume += fill.base_volume;
+        Ok(())
+    }
+
+    fn metrics(&self) -> Vec<(String, f64)> {
+        vec![
+            ("fills".to_string(), self.fills as f64),
+            ("base_volume".to_string(), self.base_volume),
+        ]
+    }
+
+    fn parameter_schema(&self) -> Vec<ParameterSpec> {
+        Self::schema()
     }
 
-    fn get_parameter(&self, name: &str) -> Option<f64> {
+    fn get_parameter(&self, name: &str) -> Option<ParameterValue> {
         match name {
-            "min_profit_threshold" => Some(self.min_profit_threshold),
-            "max_volume_per_trade" => Some(self.max_volume_per_trade),
+            "min_profit_threshold" => Some(ParameterValue::Float(self.min_profit_threshold)),
+            "max_volume_per_trade" => Some(ParameterValue::Float(self.max_volume_per_trade)),
             _ => None,
         }
     }
+
+    fn set_parameter(&mut self, name: &str, value: ParameterValue) -> Result<(), Error> {
+        match (name, check_parameter(&Self::schema(), name, value)?) {
+            ("min_profit_threshold", ParameterValue::Float(value)) => self.min_profit_threshold = value,
+            ("max_volume_per_trade", ParameterValue::Float(value)) => self.max_volume_per_trade = value,
+            _ => return Err(StrategyError::UnknownParameter(name.to_string()).into()),
+        }
+        Ok(())
+    }
+
+    fn factory_name(&self) -> Option<&str> {
+        Some("arbitrage")
+    }
+
+    fn save_state(&self) -> Result<serde_json::Value, Error> {
+        serde_json::to_value(self).map_err(|error| StrategyError::InvalidState(error.to_string()).into())
+    }
+
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), Error> {
+        *self = Self::deserialize(state).map_err(|error| StrategyError::InvalidState(error.to_string()))?;
+        Ok(())
+    }
 }
@@ -1,88 +1,108 @@
-use crate::strats_lib::Strategy;
+use crate::error_lib::{ConfigError, Error, StrategyError};
+use crate::strats_lib::{Strategy, StrategyContext, Trigger};
 use crate::simu_lib::PricePoint;
-use crate::mgv_lib::{Market, Offer, OfferSide};
-use crate::chain_lib::User;
+use crate::mgv_lib::{Offer, OfferSide};
+use crate::params_lib::{check_parameter, ParameterSpec, ParameterValue, Parameters};
 use std::sync::{Arc, Mutex};
-use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct KandelStrategy {
     price_grid: Vec<f64>,
     reference_price: f64,
-    initial_quote: f64,
-    initial_base: f64,
-    offers: Vec<Offer>,
+    initial_base: f64,  // Base sized over the asks
+    initial_quote: f64, // Quote sized over the bids
+    offer_ids: Vec<u64>, // Offers placed at init
     initialized: bool,
     n_points: usize,
     range_multiplier: f64,
     gridstep: f64,
 }
 
+/// An unconfigured Kandel with an empty grid, see `set_parameters`
+impl Default for KandelStrategy {
+    fn default() -> Self {
+        Self {
+            price_grid: Vec::new(),
+            reference_price: 0.0,
+            initial_base: 0.0,
+            initial_quote: 0.0,
+            offer_ids: Vec::new(),
+            initialized: false,
+            n_points: 0,
+            range_multiplier: 0.0,
+            gridstep: 0.0,
+        }
+    }
+}
+
 impl KandelStrategy {
 
     fn calculate_parameters(
         n_points: Option<usize>,
         range_multiplier: Option<f64>,
         gridstep: Option<f64>,
-    ) -> Result<(usize, f64, f64), &'static str> {
+    ) -> Result<(usize, f64, f64), ConfigError> {
         // Ensure exactly 2 parameters are provided
         let params_count = [n_points.is_some(), range_multiplier.is_some(), gridstep.is_some()]
             .iter()
             .filter(|&&x| x)
             .count();
         if params_count != 2 {
-            return Err("Exactly 2 out of 3 parameters must be provided");
+            return Err(ConfigError::invalid_parameter("n_points, range_multiplier, gridstep", "exactly 2 out of 3 must be provided"));
         }
 
         // Calculate the missing parameter
         match (n_points, range_multiplier, gridstep) {
             (None, Some(r), Some(g)) => {
                 if r <= 1.0 {
-                    return Err("Range multiplier must be greater than 1");
+                    return Err(ConfigError::invalid_parameter("range_multiplier", "must be greater than 1"));
+                }
+                if g <= 1.0 {
+                    return Err(ConfigError::invalid_parameter("gridstep", "must be greater than 1"));
                 }
-                if g <= 0.0 {
-                    return Err("Grid step must be positive");
+                // Number of steps whose last price is the closest to the range
+                let n = (r.ln() / g.ln()).round() as usize;
+                if n == 0 {
+                    return Err(ConfigError::invalid_parameter("range_multiplier", "must span at least one gridstep"));
                 }
-                // n = vol_multiplier / c in the Python code
-                let n = ((r.ln() / g.ln()).ceil() as usize) / 2;
                 Ok((n, r, g))
             },
             (Some(n), None, Some(g)) => {
                 if n == 0 {
-                    return Err("Number of points must be positive");
+                    return Err(ConfigError::invalid_parameter("n_points", "must be positive"));
                 }
-                if g <= 0.0 {
-                    return Err("Grid step must be positive");
+                if g <= 1.0 {
+                    return Err(ConfigError::invalid_parameter("gridstep", "must be greater than 1"));
                 }
-                let r = g.powf((n * 2) as f64);
+                let r = g.powi(n as i32);
                 Ok((n, r, g))
             },
             (Some(n), Some(r), None) => {
                 if n == 0 {
-                    return Err("Number of points must be positive");
+                    return Err(ConfigError::invalid_parameter("n_points", "must be positive"));
+                }
+                if r <= 1.0 {
+                    return Err(ConfigError::invalid_parameter("range_multiplier", "must be greater than 1"));
                 }
-                if r <= 0.0 || r >= 1.0 {
-                    return Err("Range multiplier must be between 0 and 1");
+                let g = r.powf(1.0 / n as f64);
+                // Rounds to 1 when the range is split in too many points
+                if g <= 1.0 {
+                    return Err(ConfigError::invalid_parameter("gridstep", "must be greater than 1"));
                 }
-                let g = (2.0 * r) / (2.0 * n as f64);
                 Ok((n, r, g))
             },
             _ => unreachable!(),
         }
     }
 
+    // `n_points` prices on each side, the outermost ones are the reference
+    // times and over the range multiplier
     fn calculate_grid(
         reference_price: f64,
         n_points: usize,
-        range_multiplier: f64,
         gridstep: f64,
     ) -> Vec<f64> {
-        println!("n_points: {}", n_points);
-        println!("range_multiplier: {}", range_multiplier);
-        println!("gridstep: {}", gridstep);
-
-        let max_price = reference_price * range_multiplier;
-        let min_price = reference_price / range_multiplier;
         let mut lower_prices = Vec::with_capacity(n_points);
         let mut higher_prices = Vec::with_capacity(n_points);
         
@@ -90,9 +110,6 @@ impl KandelStrategy {
         let mut current_price = reference_price;
         for _ in 0..n_points {
             current_price *= gridstep;
-            if current_price > max_price {
-                break;
-            }
             higher_prices.push(current_price);
         }
         
@@ -100,9 +117,6 @@ impl KandelStrategy {
         let mut current_price = reference_price;
         for _ in 0..n_points {
             current_price /= gridstep;
-            if current_price < min_price {
-                break;
-            }
             lower_prices.push(current_price);
         }
 
@@ -118,30 +132,33 @@ impl KandelStrategy {
 
     pub fn new(
         reference_price: f64,
-        initial_quote: f64,
         initial_base: f64,
+        initial_quote: f64,
         n_points: Option<usize>,
         range_multiplier: Option<f64>,
         gridstep: Option<f64>,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, ConfigError> {
         if reference_price <= 0.0 {
-            return Err("Reference price must be positive");
+            return Err(ConfigError::invalid_parameter("reference_price", "must be positive"));
         }
         if initial_quote <= 0.0 {
-            return Err("Initial quote must be positive");
+            return Err(ConfigError::invalid_parameter("initial_quote", "must be positive"));
+        }
+        if initial_base <= 0.0 {
+            return Err(ConfigError::invalid_parameter("initial_base", "must be positive"));
         }
 
         let (n_points, range_multiplier, gridstep) = 
             Self::calculate_parameters(n_points, range_multiplier, gridstep)?;
         
-        let price_grid = Self::calculate_grid(reference_price, n_points, range_multiplier, gridstep);
+        let price_grid = Self::calculate_grid(reference_price, n_points, gridstep);
 
         Ok(Self {
             price_grid,
             reference_price,
-            initial_quote,
             initial_base,
-            offers: Vec::new(),
+            initial_quote,
+            offer_ids: Vec::new(),
             initialized: false,
             n_points,
             range_multiplier,
@@ -159,35 +176,86 @@ impl KandelStrategy {
         n_points: Option<usize>, 
         range_multiplier: Option<f64>, 
         gridstep: Option<f64>
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), ConfigError> {
         if reference_price <= 0.0 {
-            return Err("Reference price must be positive");
+            return Err(ConfigError::invalid_parameter("reference_price", "must be positive"));
         }
         if initial_quote <= 0.0 {
-            return Err("Initial quote must be positive");
+            return Err(ConfigError::invalid_parameter("initial_quote", "must be positive"));
+        }
+        if initial_base <= 0.0 {
+            return Err(ConfigError::invalid_parameter("initial_base", "must be positive"));
         }
 
         let (n_points, range_multiplier, gridstep) = 
             Self::calculate_parameters(n_points, range_multiplier, gridstep)?;
         
-        self.price_grid = Self::calculate_grid(reference_price, n_points, range_multiplier, gridstep);
+        self.price_grid = Self::calculate_grid(reference_price, n_points, gridstep);
         self.reference_price = reference_price;
         self.initial_base = initial_base;
         self.initial_quote = initial_quote;
+        self.n_points = n_points;
+        self.range_multiplier = range_multiplier;
+        self.gridstep = gridstep;
         Ok(())
     }
 
+    /// Parameters of `from_parameters`, 2 out of n_points, range_multiplier and gridstep
+    pub fn schema() -> Vec<ParameterSpec> {
+        vec![
+            ParameterSpec::float("reference_price", "Price the grid is centred on").above(0.0),
+            ParameterSpec::float("initial_base", "Base sized over the asks").above(0.0),
+            ParameterSpec::float("initial_quote", "Quote sized over the bids").above(0.0),
+            ParameterSpec::int("n_points", "Price points on each side of the reference").min(1.0).optional(),
+            ParameterSpec::float("range_multiplier", "Ratio of the highest price to the reference").above(1.0).optional(),
+            ParameterSpec::float("gridstep", "Ratio between two consecutive prices").above(1.0).optional(),
+            ParameterSpec::float_list("price_grid", "Replaces the computed grid").above(0.0).optional(),
+        ]
+    }
+
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self, ConfigError> {
+        // Asks are sized in base from the second argument, bids in quote from the third
+        let mut kandel = Self::new(
+            parameters.float("reference_price")?,
+            parameters.float("initial_base")?,
+            parameters.float("initial_quote")?,
+            parameters.optional_int("n_points").map(|n_points| n_points as usize),
+            parameters.optional_float("range_multiplier"),
+            parameters.optional_float("gridstep"),
+        )?;
+        if let Some(price_grid) = parameters.optional_float_list("price_grid") {
+            kandel.set_price_grid(price_grid);
+        }
+        Ok(kandel)
+    }
+
     // For testing purposes just not be used in production
     pub fn set_price_grid(&mut self, price_grid: Vec<f64>) {
         self.price_grid = price_grid;
     }
 
+    pub fn price_grid(&self) -> &[f64] {
+        &self.price_grid
+    }
+
+    pub fn n_points(&self) -> usize {
+        self.n_points
+    }
+
+    pub fn range_multiplier(&self) -> f64 {
+        self.range_multiplier
+    }
+
+    pub fn gridstep(&self) -> f64 {
+        self.gridstep
+    }
+
     fn calculate_volumes(&self) -> (f64, f64) {
         let bids_count = self.price_grid.iter().filter(|&&p| p < self.reference_price).count();
         let asks_count = self.price_grid.iter().filter(|&&p| p > self.reference_price).count();
         
-        let volume_per_bid = self.initial_base / bids_count as f64;
-        let volume_per_ask = self.initial_quote / asks_count as f64;
+        let volume_per_bid = self.initial_quote / bids_count as f64;
+        let volume_per_ask = self.initial_base / asks_count as f64;
         
         (volume_per_bid, volume_per_ask)
     }
@@ -205,12 +273,7 @@ impl Strategy for KandelStrategy {
     }
 
 
-    fn execute(
-        &mut self,
-        _price_point: &PricePoint,
-        market: &mut Market,
-        user: Arc<Mutex<User>>,
-    ) -> Result<(), &'static str> {
+    fn execute(&mut self, _price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
         if self.initialized {
             return Ok(());  // Post-hooks are now handled automatically by the market
         }
@@ -218,7 +281,8 @@ impl Strategy for KandelStrategy {
         // Initialize the grid
         let (volume_per_bid, volume_per_ask) = self.calculate_volumes();
         let strategy: Arc<Mutex<Box<dyn Strategy>>> = Arc::new(Mutex::new(Box::new(self.clone())));
-        
+        let gasreq = context.gas_schedule().default_gasreq;
+
         // Place initial offers
         for i in 0..self.price_grid.len() {
             let price = self.price_grid[i];
@@ -226,29 +290,13 @@ impl Strategy for KandelStrategy {
             if price < self.reference_price {
                 // Place bid
                 let volume = volume_per_bid / self.reference_price;
-                let offer = Offer::new(
-                    user.clone(),
-                    OfferSide::Bid,
-                    price,
-                    volume,
-                    100_000,
-                    Arc::clone(&strategy),
-                );
-                market.place_offer(offer.clone())?;
-                self.offers.push(offer);
+                let offer_id = context.post_offer(OfferSide::Bid, price, volume, gasreq, Arc::clone(&strategy))?;
+                self.offer_ids.push(offer_id);
             } else if price > self.reference_price {
                 // Place ask
                 let volume = volume_per_ask;
-                let offer = Offer::new(
-                    user.clone(),
-                    OfferSide::Ask,
-                    price,
-                    volume,
-                    100_000,
-                    Arc::clone(&strategy),
-                );
-                market.place_offer(offer.clone())?;
-                self.offers.push(offer);
+                let offer_id = context.post_offer(OfferSide::Ask, price, volume, gasreq, Arc::clone(&strategy))?;
+                self.offer_ids.push(offer_id);
             }
         }
 
@@ -256,12 +304,7 @@ impl Strategy for KandelStrategy {
         Ok(())
     }
 
-    fn post_hook(
-        &mut self,
-        market: &mut Market,
-        maker: Arc<Mutex<User>>,
-        filled_offer: &Offer,
-    ) -> Result<(), &'static str> {
+    fn post_hook(&mut self, context: &mut StrategyContext, filled_offer: &Offer) -> Result<(), Error> {
         let flipped_side = filled_offer.side.flipped();
         
         // Find the next price in the grid
@@ -282,16 +325,87 @@ impl Strategy for KandelStrategy {
             OfferSide::Bid => quote_amount / next_price, // For bids, convert quote to base at new price
             OfferSide::Ask => quote_amount / filled_offer.price, // For asks, use original quote amount
         };
-        // Create new offer on the opposite side
-        let new_offer = Offer::new(
-            maker,
-            flipped_side,
-            next_price,
-            new_volume,
-            100_000,
-            Arc::clone(&filled_offer.strategy), // Reuse the same strategy reference
-        );
-
-        market.place_offer(new_offer)
+        // Create new offer on the opposite side, reusing the same strategy reference
+        let gasreq = context.gas_schedule().default_gasreq;
+        context.post_offer(flipped_side, next_price, new_volume, gasreq, Arc::clone(&filled_offer.strategy))?;
+        Ok(())
+    }
+
+    fn triggers(&self) -> Vec<Trigger> {
+        // Once the grid is placed, the post hooks do all the work
+        if self.initialized {
+            Vec::new()
+        } else {
+            vec![Trigger::EveryBlock]
+        }
+    }
+
+    fn parameter_schema(&self) -> Vec<ParameterSpec> {
+        Self::schema()
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<ParameterValue> {
+        match name {
+            "reference_price" => Some(ParameterValue::Float(self.reference_price)),
+            "initial_base" => Some(ParameterValue::Float(self.initial_base)),
+            "initial_quote" => Some(ParameterValue::Float(self.initial_quote)),
+            "n_points" => Some(ParameterValue::Int(self.n_points as i64)),
+            "range_multiplier" => Some(ParameterValue::Float(self.range_multiplier)),
+            "gridstep" => Some(ParameterValue::Float(self.gridstep)),
+            "price_grid" => Some(ParameterValue::FloatList(self.price_grid.clone())),
+            _ => None,
+        }
+    }
+
+    // The grid is recomputed from the other parameters, except when it is
+    // set directly. Offers repost from the grid they were placed with, so
+    // updates are refused once it is on the book.
+    fn set_parameter(&mut self, name: &str, value: ParameterValue) -> Result<(), Error> {
+        let value = check_parameter(&Self::schema(), name, value)?;
+        if self.initialized {
+            return Err(StrategyError::InvalidState("the Kandel grid is already placed".to_string()).into());
+        }
+        let mut reference_price = self.reference_price;
+        let (mut n_points, mut range_multiplier, mut gridstep) = (self.n_points, None, Some(self.gridstep));
+        match (name, value) {
+            ("reference_price", ParameterValue::Float(value)) => reference_price = value,
+            ("initial_base", ParameterValue::Float(value)) => {
+                self.initial_base = value;
+                return Ok(());
+            }
+            ("initial_quote", ParameterValue::Float(value)) => {
+                self.initial_quote = value;
+                return Ok(());
+            }
+            ("n_points", ParameterValue::Int(value)) => n_points = value as usize,
+            ("range_multiplier", ParameterValue::Float(value)) => (range_multiplier, gridstep) = (Some(value), None),
+            ("gridstep", ParameterValue::Float(value)) => gridstep = Some(value),
+            ("price_grid", ParameterValue::FloatList(price_grid)) => {
+                self.price_grid = price_grid;
+                return Ok(());
+            }
+            _ => return Err(StrategyError::UnknownParameter(name.to_string()).into()),
+        }
+        let (n_points, range_multiplier, gridstep) =
+            Self::calculate_parameters(Some(n_points), range_multiplier, gridstep)?;
+        self.price_grid = Self::calculate_grid(reference_price, n_points, gridstep);
+        self.reference_price = reference_price;
+        self.n_points = n_points;
+        self.range_multiplier = range_multiplier;
+        self.gridstep = gridstep;
+        Ok(())
+    }
+
+    fn factory_name(&self) -> Option<&str> {
+        Some("kandel")
+    }
+
+    fn save_state(&self) -> Result<serde_json::Value, Error> {
+        serde_json::to_value(self).map_err(|error| StrategyError::InvalidState(error.to_string()).into())
+    }
+
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), Error> {
+        *self = Self::deserialize(state).map_err(|error| StrategyError::InvalidState(error.to_string()))?;
+        Ok(())
     }
 }
\ No newline at end of file
@@ -1,11 +1,13 @@
-use crate::strats_lib::Strategy;
+use crate::error_lib::{ConfigError, Error, StrategyError};
+use crate::strats_lib::{Strategy, StrategyContext, Trigger};
 use crate::simu_lib::PricePoint;
-use crate::mgv_lib::{Market, Offer, OfferSide};
-use crate::chain_lib::User;
+use crate::mgv_lib::{Offer, OfferSide};
+use crate::params_lib::{check_parameter, ParameterSpec, ParameterValue, Parameters};
 use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
 
 // Example implementation of a simple limit order strategy
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LimitOrderStrategy {
     trigger_price: f64,
     volume: f64,
@@ -23,6 +25,18 @@ impl LimitOrderStrategy {
             executed: false,
         }
     }
+
+    pub fn schema() -> Vec<ParameterSpec> {
+        vec![
+            ParameterSpec::float("trigger_price", "Price the order is placed at, once reached").above(0.0),
+            ParameterSpec::float("volume", "Size of the order, in base").above(0.0),
+            ParameterSpec::side("side", "Ask or Bid"),
+        ]
+    }
+
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self, ConfigError> {
+        Ok(Self::new(parameters.float("trigger_price")?, parameters.float("volume")?, parameters.side("side")?))
+    }
 }
 
 impl Strategy for LimitOrderStrategy {
@@ -34,53 +48,65 @@ impl Strategy for LimitOrderStrategy {
         "Places a limit order when price reaches trigger level"
     }
 
-    fn execute(
-        &mut self,
-        price_point: &PricePoint,
-        market: &mut Market,
-        user: Arc<Mutex<User>>,
-    ) -> Result<(), &'static str> {
-        println!("Executing strategy: Limit Order Strategy");
+    fn execute(&mut self, price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
         if !self.executed && 
            ((self.side == OfferSide::Bid && price_point.price <= self.trigger_price) ||
             (self.side == OfferSide::Ask && price_point.price >= self.trigger_price)) {
                 let strategy = Arc::new(Mutex::new(Box::new(self.clone()) as Box<dyn Strategy>));
-                let offer = crate::new_offer!(user, self.side, self.trigger_price, self.volume, 100_000, strategy);
-                market.place_offer(offer)?;
-                println!("Market state: {:?}", market);
+                let gasreq = context.gas_schedule().default_gasreq;
+                context.post_offer(self.side, self.trigger_price, self.volume, gasreq, strategy)?;
                 self.executed = true;
         }
         Ok(())
     }
 
-    fn post_hook(
-        &mut self,
-        market: &mut Market,
-        maker: Arc<Mutex<User>>,
-        filled_offer: &Offer,
-    ) -> Result<(), &'static str> {
+    fn post_hook(&mut self, _context: &mut StrategyContext, _filled_offer: &Offer) -> Result<(), Error> {
         Ok(())
     }
 
-    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), &'static str> {
-        match name {
-            "trigger_price" => {
-                self.trigger_price = value;
-                Ok(())
-            }
-            "volume" => {
-                self.volume = value;
-                Ok(())
-            }
-            _ => Err("Unknown parameter"),
-        }
+    fn parameter_schema(&self) -> Vec<ParameterSpec> {
+        Self::schema()
     }
 
-    fn get_parameter(&self, name: &str) -> Option<f64> {
+    fn get_parameter(&self, name: &str) -> Option<ParameterValue> {
         match name {
-            "trigger_price" => Some(self.trigger_price),
-            "volume" => Some(self.volume as f64),
+            "trigger_price" => Some(ParameterValue::Float(self.trigger_price)),
+            "volume" => Some(ParameterValue::Float(self.volume)),
+            "side" => Some(ParameterValue::Side(self.side)),
             _ => None,
         }
     }
+
+    // Once the order is placed, updates no longer change it
+    fn set_parameter(&mut self, name: &str, value: ParameterValue) -> Result<(), Error> {
+        match (name, check_parameter(&Self::schema(), name, value)?) {
+            ("trigger_price", ParameterValue::Float(value)) => self.trigger_price = value,
+            ("volume", ParameterValue::Float(value)) => self.volume = value,
+            ("side", ParameterValue::Side(side)) => self.side = side,
+            _ => return Err(StrategyError::UnknownParameter(name.to_string()).into()),
+        }
+        Ok(())
+    }
+
+    fn triggers(&self) -> Vec<Trigger> {
+        // A single order, placed once the price crosses the trigger
+        if self.executed {
+            Vec::new()
+        } else {
+            vec![Trigger::EveryBlock]
+        }
+    }
+
+    fn factory_name(&self) -> Option<&str> {
+        Some("limit_order")
+    }
+
+    fn save_state(&self) -> Result<serde_json::Value, Error> {
+        serde_json::to_value(self).map_err(|error| StrategyError::InvalidState(error.to_string()).into())
+    }
+
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), Error> {
+        *self = Self::deserialize(state).map_err(|error| StrategyError::InvalidState(error.to_string()))?;
+        Ok(())
+    }
 }
\ No newline at end of file
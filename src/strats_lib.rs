@@ -1,50 +1,436 @@
 use std::sync::{Arc, Mutex};
 use crate::simu_lib::PricePoint;
-use crate::mgv_lib::{Market, OfferSide, Offer};
+use crate::mgv_lib::{BookEntry, Fill, Market, OfferSide, Offer, OrderSide};
+use crate::params_lib::{ParameterMap, ParameterSpec, ParameterValue, Parameters};
+use crate::gas_lib::GasSchedule;
+use rand_chacha::ChaCha8Rng;
 use crate::chain_lib::User;
+use crate::error_lib::{Error, MarketError, StrategyError};
 use crate::strats::limit_order::LimitOrderStrategy;
 use crate::strats::arbitrage::ArbitrageStrategy;
 use crate::strats::kandel::KandelStrategy;
 use crate::strats::active_kandel::ActiveKandelStrategy;
 use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 
 
+/// What wakes a strategy up, see `Strategy::triggers`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Trigger {
+    EveryBlock,        // Every new price, repeated price points are skipped
+    EveryNBlocks(u64), // Blocks that are a multiple of N
+    PriceMove(f64),    // Relative move since the strategy last ran, 0.01 for 1%
+    OfferFilled,       // An offer of the strategy's account was taken
+    Timer(u64),        // Once, at the given block
+    BookChanged,       // The book differs from when the strategy last ran
+}
+
+/// An event matching one of the triggers of a strategy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    NewPrice, // Also raised for `PriceMove` the first time the strategy runs
+    Interval(u64),
+    PriceMoved { from: f64, to: f64 },
+    OfferFilled(Fill),
+    Timer(u64),
+    BookChanged,
+}
+
 pub trait Strategy: Send + Sync {
     fn name(&self) -> &str;
     fn description(&self) -> &str;
     
     // Main strategy execution method
-    fn execute(
-        &mut self,
-        price_point: &PricePoint,
-        market: &mut Market,
-        user: Arc<Mutex<User>>,
-    ) -> Result<(), &'static str>;
+    fn execute(&mut self, price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error>;
 
-    fn post_hook(
-        &mut self,
-        market: &mut Market,
-        maker: Arc<Mutex<User>>,
-        filled_offer: &Offer,
-    ) -> Result<(), &'static str>;
-    
-    // Optional methods for strategy parameters
-    fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), &'static str> {
-        Err("Parameter not supported")
+    fn post_hook(&mut self, context: &mut StrategyContext, filled_offer: &Offer) -> Result<(), Error>;
+
+    // Optional lifecycle hooks, called by the simulator around the run
+
+    /// Before the first block, once the accounts are funded
+    fn on_start(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
     }
-    
-    fn get_parameter(&self, name: &str) -> Option<f64> {
+
+    /// At the end of every block, whether or not the triggers matched, for
+    /// the strategies opting in through `runs_on_block`
+    fn on_block(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Off by default, so that the simulator only visits the strategies
+    /// whose triggers matched
+    fn runs_on_block(&self) -> bool {
+        false
+    }
+
+    /// For every fill of the orders the strategy sent as a taker
+    fn on_fill(&mut self, _fill: &Fill, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// After the last block, to unwind positions before the final valuation
+    fn on_end(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Strategy specific statistics, reported with its PnL at the end of a run
+    fn metrics(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    /// Events the strategy should run on, asked again before every block
+    fn triggers(&self) -> Vec<Trigger> {
+        vec![Trigger::EveryBlock]
+    }
+
+    /// Runs the strategy on the events matching its triggers, in the order
+    /// of `triggers`. Strategies that do not need the events just `execute`.
+    fn on_events(&mut self, _events: &[Event], price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
+        self.execute(price_point, context)
+    }
+
+    // Optional typed parameters, see `params_lib`
+
+    /// Parameters that can be read and updated while the strategy runs
+    fn parameter_schema(&self) -> Vec<ParameterSpec> {
+        Vec::new()
+    }
+
+    fn get_parameter(&self, _name: &str) -> Option<ParameterValue> {
+        None
+    }
+
+    /// Updates a parameter, checked against `parameter_schema` first
+    fn set_parameter(&mut self, name: &str, _value: ParameterValue) -> Result<(), Error> {
+        Err(StrategyError::UnknownParameter(name.to_string()).into())
+    }
+
+    /// Current value of every parameter of the schema
+    fn parameters(&self) -> ParameterMap {
+        self.parameter_schema()
+            .iter()
+            .filter_map(|spec| Some((spec.name.to_string(), self.get_parameter(spec.name)?)))
+            .collect()
+    }
+
+    // Optional methods to checkpoint the strategy, see `checkpoint_lib`
+
+    /// Name the strategy is registered under in `StrategyFactory`
+    fn factory_name(&self) -> Option<&str> {
         None
     }
 
+    fn save_state(&self) -> Result<serde_json::Value, Error> {
+        Err(StrategyError::NotCheckpointable(self.name().to_string()).into())
+    }
+
+    fn load_state(&mut self, _state: &serde_json::Value) -> Result<(), Error> {
+        Err(StrategyError::NotCheckpointable(self.name().to_string()).into())
+    }
+
+}
+
+
+/// A write or an order decided by a delayed strategy, replayed as is when it
+/// lands, see `StrategyContext::deferred`
+#[derive(Clone)]
+pub enum Intent {
+    Post {
+        offer_id: u64, // Reserved when the offer was decided
+        side: OfferSide,
+        price: f64,
+        volume: f64,
+        gasreq: u128,
+        strategy: Arc<Mutex<Box<dyn Strategy>>>,
+    },
+    Update { offer_id: u64, price: f64, volume: f64 },
+    Retract { offer_id: u64 },
+    Take {
+        side: OrderSide,
+        volume: f64,
+        limit_price: f64, // Worst price the order reached when decided
+        hedge_price: Option<f64>, // Of a hedged order, see `StrategyContext::take_hedged`
+    },
+}
+
+impl std::fmt::Debug for Intent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Post { offer_id, side, price, volume, gasreq, .. } => f
+                .debug_struct("Post")
+                .field("offer_id", offer_id)
+                .field("side", side)
+                .field("price", price)
+                .field("volume", volume)
+                .field("gasreq", gasreq)
+                .finish(),
+            Self::Update { offer_id, price, volume } => f
+                .debug_struct("Update")
+                .field("offer_id", offer_id)
+                .field("price", price)
+                .field("volume", volume)
+                .finish(),
+            Self::Retract { offer_id } => f.debug_struct("Retract").field("offer_id", offer_id).finish(),
+            Self::Take { side, volume, limit_price, hedge_price } => f
+                .debug_struct("Take")
+                .field("side", side)
+                .field("volume", volume)
+                .field("limit_price", limit_price)
+                .field("hedge_price", hedge_price)
+                .finish(),
+        }
+    }
+}
+
+/// What a strategy sees of the simulation, and what it may do, while it runs.
+///
+/// The whole book can be read, but only the offers of the strategy's account
+/// can be written, updated or retracted.
+pub struct StrategyContext<'a> {
+    market: &'a mut Market,
+    account: Arc<Mutex<User>>,
+    strategy_id: Option<String>, // Of the registered strategy running, if any
+    intents: Option<Vec<Intent>>, // Recorded by a deferred context
+}
+
+impl<'a> StrategyContext<'a> {
+    pub fn new(market: &'a mut Market, account: Arc<Mutex<User>>) -> Self {
+        Self { market, account, strategy_id: None, intents: None }
+    }
+
+    /// Runs the context on behalf of the registered strategy `strategy_id`:
+    /// the offers it writes are tagged with it
+    pub fn with_strategy(mut self, strategy_id: Option<String>) -> Self {
+        self.strategy_id = strategy_id;
+        self
+    }
+
+    /// A context for a strategy whose transactions land later: its writes and
+    /// orders are tried on `sandbox`, a copy of the market, and recorded as
+    /// intents to be replayed with `land`
+    pub fn deferred(sandbox: &'a mut Market, account: Arc<Mutex<User>>) -> Self {
+        Self { market: sandbox, account, strategy_id: None, intents: Some(Vec::new()) }
+    }
+
+    /// Intents recorded by a deferred context, in the order they were decided
+    pub fn into_intents(self) -> Vec<Intent> {
+        self.intents.unwrap_or_default()
+    }
+
+    /// Replays an intent on the market as it is now. Fails if it no longer
+    /// holds: the offer to update or retract is gone, or the order would fill
+    /// beyond its limit price.
+    pub fn land(&mut self, intent: &Intent) -> Result<(), MarketError> {
+        match intent {
+            Intent::Post { offer_id, side, price, volume, gasreq, strategy } => {
+                let mut offer = Offer::new(Arc::clone(&self.account), *side, *price, *volume, *gasreq, Arc::clone(strategy));
+                offer.id = *offer_id;
+                offer.strategy_id = self.strategy_id.clone();
+                self.market.place_reserved_offer(offer).map(|_| ())
+            }
+            Intent::Update { offer_id, price, volume } => self.update_offer(*offer_id, *price, *volume),
+            Intent::Retract { offer_id } => self.retract_offer(*offer_id),
+            Intent::Take { side, volume, limit_price, hedge_price } => {
+                // Without enough liquidity the order itself fails
+                if let Some(price) = self.market.worst_fill_price(*side, *volume) {
+                    let moved = match side {
+                        OrderSide::Buy => price > *limit_price,
+                        OrderSide::Sell => price < *limit_price,
+                    };
+                    if moved {
+                        return Err(MarketError::PriceMoved { side: *side, limit_price: *limit_price, price });
+                    }
+                }
+                self.order(*side, *volume, *hedge_price).map(|_| ())
+            }
+        }
+    }
+
+    fn record(&mut self, intent: Intent) {
+        if let Some(intents) = self.intents.as_mut() {
+            intents.push(intent);
+        }
+    }
+
+    pub fn block(&self) -> u64 {
+        self.market.block
+    }
+
+    /// Unix time of the current block, in seconds
+    pub fn timestamp(&self) -> u64 {
+        self.market.timestamp
+    }
+
+    pub fn base(&self) -> &str {
+        &self.market.base
+    }
+
+    pub fn quote(&self) -> &str {
+        &self.market.quote
+    }
+
+    pub fn account_id(&self) -> String {
+        self.account.lock().unwrap().id.clone()
+    }
+
+    pub fn balance(&self, token: &str) -> f64 {
+        self.account.lock().unwrap().get_token_balance(token)
+    }
+
+    /// Native balance of the account, in wei
+    pub fn native_balance(&self) -> f64 {
+        self.account.lock().unwrap().get_native_balance()
+    }
+
+    /// Gas price of the current block, in gwei
+    pub fn gas_price(&self) -> f64 {
+        self.market.gas_price
+    }
+
+    pub fn gas_schedule(&self) -> &GasSchedule {
+        &self.market.gas_schedule
+    }
+
+    pub fn rng(&mut self) -> &mut ChaCha8Rng {
+        &mut self.market.rng
+    }
+
+    pub fn best_bid(&self) -> Option<BookEntry> {
+        self.market.best_bid().map(BookEntry::new)
+    }
+
+    pub fn best_ask(&self) -> Option<BookEntry> {
+        self.market.best_ask().map(BookEntry::new)
+    }
+
+    /// Every offer of the book, bids from the best one then asks from the best one
+    pub fn book(&self) -> Vec<BookEntry> {
+        self.market.snapshot()
+    }
+
+    /// Offers of the strategy's account, in book order. For a registered
+    /// strategy, only the ones it wrote: strategies sharing an account do
+    /// not see each other's offers.
+    pub fn own_offers(&self) -> Vec<BookEntry> {
+        self.market
+            .bids
+            .iter()
+            .chain(self.market.asks.iter())
+            .filter(|offer| Arc::ptr_eq(&offer.maker, &self.account))
+            .filter(|offer| self.strategy_id.is_none() || offer.strategy_id == self.strategy_id)
+            .map(BookEntry::new)
+            .collect()
+    }
+
+    /// Writes an offer made by the strategy's account, `strategy` runs its post hook
+    pub fn post_offer(
+        &mut self,
+        side: OfferSide,
+        price: f64,
+        volume: f64,
+        gasreq: u128,
+        strategy: Arc<Mutex<Box<dyn Strategy>>>,
+    ) -> Result<u64, MarketError> {
+        let mut offer = Offer::new(Arc::clone(&self.account), side, price, volume, gasreq, Arc::clone(&strategy));
+        offer.strategy_id = self.strategy_id.clone();
+        let offer_id = self.market.place_offer(offer)?;
+        self.record(Intent::Post { offer_id, side, price, volume, gasreq, strategy });
+        Ok(offer_id)
+    }
+
+    pub fn update_offer(&mut self, offer_id: u64, price: f64, volume: f64) -> Result<(), MarketError> {
+        self.check_owner(offer_id)?;
+        self.market.update_offer(offer_id, price, volume)?;
+        self.record(Intent::Update { offer_id, price, volume });
+        Ok(())
+    }
+
+    pub fn retract_offer(&mut self, offer_id: u64) -> Result<(), MarketError> {
+        self.check_owner(offer_id)?;
+        self.market.retract_offer(offer_id)?;
+        self.record(Intent::Retract { offer_id });
+        Ok(())
+    }
+
+    /// Sends a market order from the strategy's account, returns the base
+    /// volume filled, see `Market::market_order`
+    pub fn take(&mut self, side: OrderSide, volume: f64) -> Result<f64, MarketError> {
+        self.order(side, volume, None)
+    }
+
+    /// Sends a market order hedged on an outside venue at `hedge_price`: the
+    /// venue advances what the order spends, then buys the base received or
+    /// sells back the base given, so only the quote balance of the account
+    /// changes. Returns the base volume filled.
+    pub fn take_hedged(&mut self, side: OrderSide, volume: f64, hedge_price: f64) -> Result<f64, MarketError> {
+        self.order(side, volume, Some(hedge_price))
+    }
+
+    fn order(&mut self, side: OrderSide, volume: f64, hedge_price: Option<f64>) -> Result<f64, MarketError> {
+        let limit_price = self.market.worst_fill_price(side, volume);
+        let filled = match hedge_price {
+            Some(hedge_price) => self.hedged_order(side, volume, hedge_price, limit_price.unwrap_or(0.0))?,
+            None => self.market.market_order(&self.account, side, volume)?,
+        };
+        if let Some(limit_price) = limit_price {
+            self.record(Intent::Take { side, volume, limit_price, hedge_price });
+        }
+        Ok(filled)
+    }
+
+    fn hedged_order(&mut self, side: OrderSide, volume: f64, hedge_price: f64, worst_price: f64) -> Result<f64, MarketError> {
+        let base = self.market.base.clone();
+        let quote = self.market.quote.clone();
+        let (advanced_token, advance) = match side {
+            OrderSide::Buy => (&quote, volume * worst_price),
+            OrderSide::Sell => (&base, volume),
+        };
+        self.account.lock().unwrap().add_token_balance(advanced_token, advance)?;
+        let base_before = self.balance(&base);
+        let result = self.market.market_order(&self.account, side, volume);
+
+        let mut account = self.account.lock().unwrap();
+        let bought = account.get_token_balance(&base) - base_before; // Negative when sold
+        if bought > 0.0 {
+            account.spend_token_balance(&base, bought)?;
+            account.add_token_balance(&quote, bought * hedge_price)?;
+        } else if bought < 0.0 {
+            account.add_token_balance(&base, -bought)?;
+            account.spend_token_balance(&quote, -bought * hedge_price)?;
+        }
+        account.spend_token_balance(advanced_token, advance)?;
+        result
+    }
+
+    fn check_owner(&self, offer_id: u64) -> Result<(), MarketError> {
+        match self.market.get_offer(offer_id) {
+            Some(offer) if Arc::ptr_eq(&offer.maker, &self.account) => Ok(()),
+            Some(_) => Err(MarketError::NotOfferOwner {
+                offer_id,
+                account_id: self.account_id(),
+            }),
+            None => Err(MarketError::OfferNotFound(offer_id)),
+        }
+    }
 }
 
 
+type BuildFn = Box<dyn Fn(&Parameters) -> Result<Box<dyn Strategy>, Error> + Send + Sync>;
+type RestoreFn = fn(&serde_json::Value) -> Result<Box<dyn Strategy>, Error>;
+
+struct StrategyBuilder {
+    schema: Vec<ParameterSpec>,
+    build: BuildFn,
+    restore: Option<RestoreFn>, // Set for strategies that can be checkpointed
+}
 
-// Strategy Factory for dynamic strategy creation
+/// Builds strategies by name from parameter maps, checked against the schema
+/// each strategy is registered with.
 pub struct StrategyFactory {
-    builders: HashMap<String, Box<dyn Fn() -> Box<dyn Strategy> + Send + Sync>>,
+    builders: HashMap<String, StrategyBuilder>,
 }
 
 impl StrategyFactory {
@@ -52,36 +438,77 @@ impl StrategyFactory {
         let mut factory = Self {
             builders: HashMap::new(),
         };
-        
+
         // Register default strategies
-        factory.register_strategy("limit_order", || {
-            Box::new(LimitOrderStrategy::new(0.0, 0.0, OfferSide::Bid))
+        factory.register_checkpointable::<LimitOrderStrategy, _>("limit_order", LimitOrderStrategy::schema(), |parameters| {
+            Ok(Box::new(LimitOrderStrategy::from_parameters(parameters)?))
         });
-        factory.register_strategy("arbitrage", || {
-            Box::new(ArbitrageStrategy::new(0.1, 1000.0))
+        factory.register_checkpointable::<ArbitrageStrategy, _>("arbitrage", ArbitrageStrategy::schema(), |parameters| {
+            Ok(Box::new(ArbitrageStrategy::from_parameters(parameters)?))
         });
-        factory.register_strategy("kandel", || {
-            Box::new(KandelStrategy::new(0.0, 0.0, 0.0, None, None, None).unwrap())
+        factory.register_checkpointable::<KandelStrategy, _>("kandel", KandelStrategy::schema(), |parameters| {
+            Ok(Box::new(KandelStrategy::from_parameters(parameters)?))
         });
-        factory.register_strategy("active_kandel", || {
-            Box::new(ActiveKandelStrategy::new(3600, 3600, 0.0, 0.0))
+        factory.register_checkpointable::<ActiveKandelStrategy, _>("active_kandel", ActiveKandelStrategy::schema(), |parameters| {
+            Ok(Box::new(ActiveKandelStrategy::from_parameters(parameters)?))
         });
-        
+
         factory
     }
 
-    pub fn register_strategy<F>(&mut self, name: &str, builder: F)
+    /// Registers a strategy that cannot be restored from a checkpoint
+    pub fn register_strategy<F>(&mut self, name: &str, schema: Vec<ParameterSpec>, build: F)
+    where
+        F: Fn(&Parameters) -> Result<Box<dyn Strategy>, Error> + Send + Sync + 'static,
+    {
+        let builder = StrategyBuilder { schema, build: Box::new(build), restore: None };
+        self.builders.insert(name.to_string(), builder);
+    }
+
+    /// Registers a strategy restored from the state saved by `Strategy::save_state`
+    pub fn register_checkpointable<S, F>(&mut self, name: &str, schema: Vec<ParameterSpec>, build: F)
     where
-        F: Fn() -> Box<dyn Strategy> + Send + Sync + 'static,
+        S: Strategy + DeserializeOwned + 'static,
+        F: Fn(&Parameters) -> Result<Box<dyn Strategy>, Error> + Send + Sync + 'static,
     {
-        self.builders.insert(name.to_string(), Box::new(builder));
+        let restore: RestoreFn = |state| {
+            let strategy = S::deserialize(state).map_err(|error| StrategyError::InvalidState(error.to_string()))?;
+            Ok(Box::new(strategy))
+        };
+        let builder = StrategyBuilder { schema, build: Box::new(build), restore: Some(restore) };
+        self.builders.insert(name.to_string(), builder);
+    }
+
+    pub fn create_strategy(&self, name: &str, parameters: &ParameterMap) -> Result<Box<dyn Strategy>, Error> {
+        let builder = self.builder(name)?;
+        let parameters = Parameters::validate(&builder.schema, parameters.clone())?;
+        (builder.build)(&parameters)
     }
 
-    pub fn create_strategy(&self, name: &str) -> Option<Box<dyn Strategy>> {
-        self.builders.get(name).map(|builder| builder())
+    pub fn restore_strategy(&self, name: &str, state: &serde_json::Value) -> Result<Box<dyn Strategy>, Error> {
+        let restore = self.builder(name)?.restore.ok_or_else(|| StrategyError::NotCheckpointable(name.to_string()))?;
+        restore(state)
     }
 
+    /// Parameters a strategy is built from
+    pub fn schema(&self, name: &str) -> Option<&[ParameterSpec]> {
+        self.builders.get(name).map(|builder| builder.schema.as_slice())
+    }
+
+    /// Registered names, sorted
     pub fn list_strategies(&self) -> Vec<String> {
-        self.builders.keys().cloned().collect()
+        let mut names: Vec<String> = self.builders.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn builder(&self, name: &str) -> Result<&StrategyBuilder, StrategyError> {
+        self.builders.get(name).ok_or_else(|| StrategyError::UnknownStrategy(name.to_string()))
     }
-}
\ No newline at end of file
+}
+
+impl Default for StrategyFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
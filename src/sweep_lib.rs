@@ -0,0 +1,253 @@
+use crate::analytics_lib::{AnalyticsConfig, EquityCurve};
+use crate::error_lib::{ConfigError, Error};
+use crate::simu_lib::{PricePoint, Simulator};
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Values of the swept parameters for one run, in the order of the grid
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParameterSet {
+    pub values: Vec<(String, f64)>,
+}
+
+impl ParameterSet {
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.values.iter().find(|(key, _)| key == name).map(|(_, value)| *value)
+    }
+}
+
+impl std::fmt::Display for ParameterSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values: Vec<String> = self.values.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        write!(f, "{}", values.join(" "))
+    }
+}
+
+/// Values to try for each parameter, every combination is run
+#[derive(Debug, Clone, Default)]
+pub struct ParameterGrid {
+    pub parameters: Vec<(String, Vec<f64>)>,
+}
+
+impl ParameterGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: &str, values: Vec<f64>) -> Self {
+        self.parameters.push((name.to_string(), values));
+        self
+    }
+
+    /// Every combination, the last parameter varying fastest
+    pub fn combinations(&self) -> Vec<ParameterSet> {
+        let mut combinations = vec![ParameterSet::default()];
+        for (name, values) in &self.parameters {
+            combinations = combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.values.push((name.clone(), *value));
+                        combination
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+}
+
+/// Summary of one account after one run of a sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow {
+    pub run: usize,
+    pub parameters: ParameterSet,
+    pub account_id: String,
+    pub final_value: f64,
+    pub pnl: f64,
+    pub total_trades: u64,
+    pub total_volume: f64,
+    pub gas_spent_quote: f64,
+    pub hodl_value: f64,
+    pub max_drawdown: f64,
+    pub sharpe: f64,
+    pub fingerprint: u64,
+}
+
+/// A run that could not be built or failed during the simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepFailure {
+    pub run: usize,
+    pub parameters: ParameterSet,
+    pub reason: Error,
+}
+
+/// Results of a sweep, ordered by run then account
+#[derive(Debug, Clone, Default)]
+pub struct SweepTable {
+    pub rows: Vec<SweepRow>,
+    pub failures: Vec<SweepFailure>,
+}
+
+impl SweepTable {
+    /// Rows of `account_id`, from the best to the worst according to `key`
+    pub fn ranked_by<F>(&self, account_id: &str, key: F) -> Vec<&SweepRow>
+    where
+        F: Fn(&SweepRow) -> f64,
+    {
+        let mut rows: Vec<&SweepRow> = self.rows.iter().filter(|row| row.account_id == account_id).collect();
+        rows.sort_by(|a, b| key(b).total_cmp(&key(a)).then_with(|| a.run.cmp(&b.run)));
+        rows
+    }
+
+    /// One line per row, with a column per swept parameter
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        let parameter_names: Vec<String> = self.rows
+            .first()
+            .map(|row| row.parameters.values.iter().map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default();
+        let mut header = vec!["run".to_string()];
+        header.extend(parameter_names);
+        header.push("account,final_value,pnl,trades,volume,gas_quote,hodl_value,max_drawdown,sharpe,fingerprint".to_string());
+        writeln!(file, "{}", header.join(","))?;
+        for row in &self.rows {
+            let mut line = vec![row.run.to_string()];
+            line.extend(row.parameters.values.iter().map(|(_, value)| value.to_string()));
+            line.push(format!(
+                "{},{},{},{},{},{},{},{},{},{}",
+                row.account_id,
+                row.final_value,
+                row.pnl,
+                row.total_trades,
+                row.total_volume,
+                row.gas_spent_quote,
+                row.hodl_value,
+                row.max_drawdown,
+                row.sharpe,
+                row.fingerprint,
+            ));
+            writeln!(file, "{}", line.join(","))?;
+        }
+        file.flush()
+    }
+}
+
+/// Runs `run(0..n)` on `threads` threads, one per core by default, and
+/// returns the results in order
+pub(crate) fn run_parallel<T, F>(threads: Option<usize>, n: usize, run: F) -> Result<Vec<T>, ConfigError>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let run_all = || (0..n).into_par_iter().map(&run).collect();
+    match threads {
+        Some(threads) => Ok(rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|error| ConfigError::ThreadPool(error.to_string()))?
+            .install(run_all)),
+        None => Ok(run_all()),
+    }
+}
+
+/// Runs one simulation per combination of a parameter grid, in parallel.
+///
+/// Every run gets a fresh `Simulator` from the builder, all of them share the
+/// same read-only price feed. The builder's outputs are removed unless
+/// `keep_outputs` is set, since every run would write to the same files.
+pub struct Sweep {
+    pub grid: ParameterGrid,
+    pub price_feed: Arc<[PricePoint]>,
+    pub threads: Option<usize>, // Defaults to one per core
+    pub analytics: AnalyticsConfig,
+    pub keep_outputs: bool,
+}
+
+impl Sweep {
+    pub fn new(grid: ParameterGrid, price_feed: impl Into<Arc<[PricePoint]>>) -> Self {
+        Self {
+            grid,
+            price_feed: price_feed.into(),
+            threads: None,
+            analytics: AnalyticsConfig::default(),
+            keep_outputs: false,
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    pub fn with_analytics(mut self, analytics: AnalyticsConfig) -> Self {
+        self.analytics = analytics;
+        self
+    }
+
+    pub fn run<F>(&self, build: F) -> Result<SweepTable, Error>
+    where
+        F: Fn(&ParameterSet, Arc<[PricePoint]>) -> Result<Simulator, Error> + Sync,
+    {
+        let combinations = self.grid.combinations();
+        let results = run_parallel(self.threads, combinations.len(), |run| {
+            self.run_one(run, &combinations[run], &build)
+        })?;
+
+        let mut table = SweepTable::default();
+        for result in results {
+            match result {
+                Ok(rows) => table.rows.extend(rows),
+                Err(failure) => table.failures.push(failure),
+            }
+        }
+        Ok(table)
+    }
+
+    fn run_one<F>(&self, run: usize, parameters: &ParameterSet, build: &F) -> Result<Vec<SweepRow>, SweepFailure>
+    where
+        F: Fn(&ParameterSet, Arc<[PricePoint]>) -> Result<Simulator, Error>,
+    {
+        let failure = |reason| SweepFailure { run, parameters: parameters.clone(), reason };
+        let mut simulator = build(parameters, Arc::clone(&self.price_feed)).map_err(failure)?;
+        if !self.keep_outputs {
+            simulator.clear_sinks();
+            simulator.disable_book_snapshots();
+        }
+        simulator.run_simulation(false, false).map_err(failure)?;
+
+        let fingerprint = simulator.run_fingerprint();
+        let mut account_ids: Vec<&String> = simulator.performance_metrics.keys().collect();
+        account_ids.sort();
+        Ok(account_ids
+            .into_iter()
+            .map(|account_id| {
+                let metrics = &simulator.performance_metrics[account_id];
+                let report = simulator
+                    .risk_report(account_id, &self.analytics)
+                    .unwrap_or_else(|| EquityCurve::new().report(&self.analytics));
+                SweepRow {
+                    run,
+                    parameters: parameters.clone(),
+                    account_id: account_id.clone(),
+                    final_value: metrics.current_balance,
+                    pnl: metrics.total_profit_loss,
+                    total_trades: metrics.total_trades,
+                    total_volume: metrics.total_volume,
+                    gas_spent_quote: metrics.gas_spent_quote,
+                    hodl_value: metrics.hodl_value,
+                    max_drawdown: report.max_drawdown,
+                    sharpe: report.sharpe,
+                    fingerprint,
+                }
+            })
+            .collect())
+    }
+}
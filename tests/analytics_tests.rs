@@ -0,0 +1,89 @@
+use mgv_simulator::analytics_lib::{rank_by, AnalyticsConfig, EquityCurve, EquityPoint};
+use mgv_simulator::mgv_lib::Market;
+use mgv_simulator::simu_lib::{PricePoint, Simulator};
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+
+
+fn curve(values: &[f64]) -> EquityCurve {
+    let mut curve = EquityCurve::new();
+    for (block, value) in values.iter().enumerate() {
+        curve.push(EquityPoint { block: block as u64, price: 100.0 + block as f64, value: *value, volume: 10.0 * block as f64 });
+    }
+    curve
+}
+
+#[test]
+fn test_drawdown_and_returns() {
+    let curve = curve(&[100.0, 110.0, 88.0, 99.0, 121.0, 110.0]);
+
+    // From 110 down to 88, recovered at block 4
+    assert!((curve.max_drawdown() - 0.2).abs() < 1e-12);
+    assert_eq!(curve.max_drawdown_duration(), 2);
+    assert!((curve.total_return() - 0.1).abs() < 1e-12);
+
+    let returns = curve.returns(2);
+    assert_eq!(returns.len(), 2);
+    assert!((returns[0] - (88.0 / 100.0 - 1.0)).abs() < 1e-12);
+    assert!((returns[1] - (121.0 / 88.0 - 1.0)).abs() < 1e-12);
+
+    assert_eq!(curve.time_in_range(101.0, 103.0), 0.5);
+    assert!((curve.turnover() - 50.0 / (628.0 / 6.0)).abs() < 1e-12);
+}
+
+#[test]
+fn test_report_annualises_sampled_returns() {
+    let config = AnalyticsConfig { sample_every: 1, blocks_per_year: 4.0, risk_free_rate: 0.0 };
+    let report = curve(&[100.0, 110.0, 99.0]).report(&config);
+
+    // Returns of +10% and -10%: no mean return, 10% volatility per block
+    assert!(report.annualized_return.abs() < 1e-12);
+    assert!((report.volatility - 0.2).abs() < 1e-12);
+    assert!(report.sharpe.abs() < 1e-12);
+
+    let steady = curve(&[100.0, 101.0, 102.01]).report(&config);
+    assert!(steady.volatility < 1e-9);
+    // No downside and no volatility: ratios are left at 0 rather than infinite
+    assert_eq!(steady.sortino, 0.0);
+}
+
+#[test]
+fn test_simulator_records_equity_curves() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed = vec![
+        PricePoint::new(0, 100.0),
+        PricePoint::new(1, 103.0),
+        PricePoint::new(2, 96.0),
+        PricePoint::new(3, 101.0),
+    ];
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.clear_sinks();
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+
+    let mut kandel_strat = KandelStrategy::new(100.0, 1.0, 100.0, Some(1), None, Some(1.02)).unwrap();
+    kandel_strat.set_price_grid(vec![98.0, 100.0, 102.0]);
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // One point per block
+    let curve = &simulator.equity_curves["kandel"];
+    assert_eq!(curve.len(), 4);
+    assert!(curve.points.iter().enumerate().all(|(block, point)| point.block == block as u64));
+    let metrics = &simulator.performance_metrics["kandel"];
+    let last = curve.points.last().unwrap();
+    assert_eq!(last.value, metrics.current_balance - metrics.gas_spent_quote + metrics.bounties_quote);
+    assert_eq!(last.volume, metrics.total_volume);
+    assert_eq!(curve.time_in_range(98.0, 102.0), 0.5);
+
+    let reports = simulator.risk_reports(&AnalyticsConfig::default());
+    assert_eq!(reports.len(), 2);
+    let ranked = rank_by(reports, |report| report.total_return);
+    assert!(ranked[0].1.total_return >= ranked[1].1.total_return);
+    assert_eq!(simulator.risk_report("kandel", &AnalyticsConfig::default()).unwrap().turnover, curve.turnover());
+}
@@ -0,0 +1,115 @@
+use mgv_simulator::checkpoint_lib::Checkpoint;
+use mgv_simulator::error_lib::{ConfigError, Error, StrategyError};
+use mgv_simulator::mgv_lib::{Market, Offer};
+use mgv_simulator::simu_lib::{PricePoint, Simulator};
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+use mgv_simulator::strats_lib::{Strategy, StrategyContext, StrategyFactory};
+
+
+fn price_feed() -> Vec<PricePoint> {
+    [100.0, 103.0, 96.0, 96.0, 101.0, 104.0, 97.0, 102.0]
+        .iter()
+        .enumerate()
+        .map(|(block, price)| PricePoint::new(block as u64, *price))
+        .collect()
+}
+
+fn kandel_simulator() -> Simulator {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, price_feed());
+    simulator.clear_sinks();
+    simulator.disable_book_snapshots();
+    simulator.set_seed(7);
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+
+    // Asks are sized in base from the second argument, bids in quote from the third
+    let kandel_strat = KandelStrategy::new(100.0, 10.0, 1000.0, Some(3), None, Some(1.02)).unwrap();
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator
+}
+
+#[test]
+fn test_resumed_run_matches_uninterrupted_run() {
+    let mut uninterrupted = kandel_simulator();
+    uninterrupted.run_simulation(false, false).unwrap();
+
+    let mut first_half = kandel_simulator();
+    first_half.run_until(4, false, false).unwrap();
+    assert_eq!(first_half.current_block, 4);
+    assert!(!first_half.market.asks.is_empty());
+
+    let path = std::env::temp_dir().join("mgv_checkpoint_tests").join("resume.json");
+    first_half.save_checkpoint(&path).unwrap();
+    let mut resumed = Simulator::load_checkpoint(&path, price_feed(), &StrategyFactory::new()).unwrap();
+    // Outputs are not checkpointed, the resumed run writes nothing unless told to
+    assert!(resumed.sinks.is_empty());
+    resumed.disable_book_snapshots();
+    assert_eq!(resumed.run_fingerprint(), first_half.run_fingerprint());
+    resumed.run_simulation(false, false).unwrap();
+
+    assert_eq!(resumed.current_block, 8);
+    assert_eq!(resumed.run_fingerprint(), uninterrupted.run_fingerprint());
+    for account_id in ["kandel", "arb"] {
+        let (expected, actual) = (&uninterrupted.performance_metrics[account_id], &resumed.performance_metrics[account_id]);
+        assert_eq!(actual.total_trades, expected.total_trades);
+        assert_eq!(actual.total_profit_loss, expected.total_profit_loss);
+        assert_eq!(resumed.equity_curves[account_id].len(), uninterrupted.equity_curves[account_id].len());
+    }
+
+    // Branching the checkpoint on another future keeps the shared past
+    let mut crash = price_feed();
+    for point in crash.iter_mut().skip(4) {
+        point.price = 80.0;
+    }
+    let checkpoint = Checkpoint::load(&path).unwrap();
+    let mut branch = Simulator::from_checkpoint(checkpoint, crash, &StrategyFactory::new()).unwrap();
+    branch.clear_sinks();
+    branch.run_simulation(false, false).unwrap();
+    assert_ne!(branch.run_fingerprint(), uninterrupted.run_fingerprint());
+    assert!(matches!(
+        Simulator::load_checkpoint(&path, &price_feed()[..2], &StrategyFactory::new()),
+        Err(Error::Config(ConfigError::PriceFeedTooShort { available: 2, .. }))
+    ));
+}
+
+struct Opaque;
+
+impl Strategy for Opaque {
+    fn name(&self) -> &str {
+        "Opaque"
+    }
+
+    fn description(&self) -> &str {
+        "Strategy without checkpoint support"
+    }
+
+    fn execute(&mut self, _: &PricePoint, _: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn post_hook(&mut self, _: &mut StrategyContext, _: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_checkpoint_requires_strategy_support() {
+    let mut simulator = kandel_simulator();
+    simulator.add_strategy("opaque".to_string(), Box::new(Opaque));
+    assert!(matches!(
+        simulator.checkpoint(),
+        Err(Error::Strategy(StrategyError::NotCheckpointable(name))) if name == "Opaque"
+    ));
+
+    // Automatic checkpoints stop the run when they cannot be taken
+    let path = std::env::temp_dir().join("mgv_checkpoint_tests").join("auto.json");
+    simulator.set_checkpointing(2, &path);
+    assert!(simulator.run_simulation(false, false).is_err());
+}
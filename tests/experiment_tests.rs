@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use mgv_simulator::error_lib::Error;
+use mgv_simulator::experiment_lib::{quantile, Distribution, MonteCarlo};
+use mgv_simulator::gen_lib::{generate_path, Gbm, PathConfig};
+use mgv_simulator::mgv_lib::Market;
+use mgv_simulator::simu_lib::{PricePoint, Simulator};
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+
+
+fn kandel_simulator(price_feed: Arc<[PricePoint]>) -> Result<Simulator, Error> {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, price_feed);
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0)?;
+    kandel_user.lock().unwrap().add_token_balance("USDC", 1000.0)?;
+    simulator.add_user("arb".to_string(), 1e18);
+
+    let kandel_strat = KandelStrategy::new(100.0, 1.0, 100.0, Some(2), None, Some(1.01))?;
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat")?;
+    simulator.assign_strategy("arb", "arb_strat")?;
+    Ok(simulator)
+}
+
+fn gbm_path(seed: u64) -> Vec<PricePoint> {
+    generate_path(Gbm { mu: 0.0, sigma: 0.2 }, PathConfig { dt: 1.0 / 365.0, ..PathConfig::new(100.0, 30, seed) })
+}
+
+#[test]
+fn test_distribution_statistics() {
+    let distribution = Distribution::from_samples(&[3.0, 1.0, 5.0, 2.0, 4.0]).unwrap();
+    assert_eq!(distribution.count, 5);
+    assert_eq!(distribution.mean, 3.0);
+    assert_eq!(distribution.median, 3.0);
+    assert_eq!(distribution.p25, 2.0);
+    assert!((distribution.p95 - 4.8).abs() < 1e-12);
+    assert!((distribution.std_dev - 2.5f64.sqrt()).abs() < 1e-12);
+    let half_width = 1.96 * 2.5f64.sqrt() / 5f64.sqrt();
+    assert!((distribution.ci_high - 3.0 - half_width).abs() < 1e-12);
+    assert!(Distribution::from_samples(&[]).is_none());
+    assert_eq!(quantile(&[1.0, 2.0], 0.5), 1.5);
+}
+
+#[test]
+fn test_monte_carlo_over_seeded_paths() {
+    let experiment = MonteCarlo::new(6, 100).with_threads(3).run(gbm_path, kandel_simulator).unwrap();
+    assert!(experiment.failures.is_empty());
+    assert_eq!(experiment.runs.len(), 12);
+    assert_eq!(experiment.runs[2].seed, 101);
+    assert_eq!(experiment.runs[2].account_id, "arb");
+
+    // The same seeds give the same runs whatever the number of threads
+    let again = MonteCarlo::new(6, 100).with_threads(1).run(gbm_path, kandel_simulator).unwrap();
+    assert_eq!(again.runs, experiment.runs);
+
+    let aggregate = experiment.aggregate();
+    assert_eq!(aggregate.len(), 6);
+    let kandel_pnl = aggregate.iter().find(|row| row.account_id == "kandel" && row.metric == "pnl").unwrap();
+    assert_eq!(kandel_pnl.distribution.count, 6);
+    assert!(kandel_pnl.distribution.ci_low <= kandel_pnl.distribution.mean);
+    assert!(kandel_pnl.distribution.min <= kandel_pnl.distribution.median);
+
+    let dir = std::env::temp_dir().join("mgv_experiment_tests");
+    experiment.write_runs_csv(&dir.join("runs.csv")).unwrap();
+    experiment.write_aggregate_csv(&dir.join("aggregate.csv")).unwrap();
+    assert_eq!(std::fs::read_to_string(dir.join("runs.csv")).unwrap().lines().count(), 13);
+    let aggregate_csv = std::fs::read_to_string(dir.join("aggregate.csv")).unwrap();
+    assert!(aggregate_csv.lines().nth(1).unwrap().starts_with("arb,pnl,6,"));
+}
@@ -0,0 +1,324 @@
+use mgv_simulator::error_lib::{ConfigError, Error, StrategyError};
+use mgv_simulator::mgv_lib::{Offer, OfferSide};
+use mgv_simulator::params_lib::{ParameterKind, ParameterMap, ParameterSpec, ParameterValue, Parameters};
+use mgv_simulator::simu_lib::PricePoint;
+use mgv_simulator::strats::kandel::KandelStrategy;
+use mgv_simulator::strats_lib::{Strategy, StrategyContext, StrategyFactory};
+
+
+fn parameters(values: &[(&str, ParameterValue)]) -> ParameterMap {
+    values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+}
+
+fn kandel_parameters() -> ParameterMap {
+    parameters(&[
+        ("reference_price", ParameterValue::Float(100.0)),
+        ("initial_base", ParameterValue::Float(10.0)),
+        ("initial_quote", ParameterValue::Float(1000.0)),
+        ("n_points", ParameterValue::Int(3)),
+        ("gridstep", ParameterValue::Float(1.02)),
+    ])
+}
+
+fn invalid_parameter(result: Result<Box<dyn Strategy>, Error>) -> (String, String) {
+    match result {
+        Err(Error::Config(ConfigError::InvalidParameter { name, reason })) => (name, reason),
+        Err(error) => panic!("unexpected error {}", error),
+        Ok(strategy) => panic!("{} was built", strategy.name()),
+    }
+}
+
+#[test]
+fn test_factory_builds_every_strategy_from_parameters() {
+    let factory = StrategyFactory::new();
+    assert_eq!(factory.list_strategies(), ["active_kandel", "arbitrage", "kandel", "limit_order"]);
+
+    let kandel = factory.create_strategy("kandel", &kandel_parameters()).unwrap();
+    assert_eq!(kandel.factory_name(), Some("kandel"));
+
+    // Defaults fill what is not given
+    let arbitrage = factory
+        .create_strategy("arbitrage", &parameters(&[("max_volume_per_trade", ParameterValue::Int(5))]))
+        .unwrap();
+    assert_eq!(arbitrage.get_parameter("min_profit_threshold"), Some(ParameterValue::Float(0.0)));
+    assert_eq!(arbitrage.get_parameter("max_volume_per_trade"), Some(ParameterValue::Float(5.0)));
+
+    let limit_order = factory
+        .create_strategy(
+            "limit_order",
+            &parameters(&[
+                ("trigger_price", ParameterValue::Float(95.0)),
+                ("volume", ParameterValue::Float(1.0)),
+                ("side", ParameterValue::Side(OfferSide::Ask)),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(limit_order.get_parameter("side"), Some(ParameterValue::Side(OfferSide::Ask)));
+
+    let active_kandel = factory
+        .create_strategy(
+            "active_kandel",
+            &parameters(&[("base_amount", ParameterValue::Float(1.0)), ("quote_amount", ParameterValue::Float(100.0))]),
+        )
+        .unwrap();
+    assert_eq!(active_kandel.factory_name(), Some("active_kandel"));
+}
+
+#[test]
+fn test_factory_rejects_invalid_parameters() {
+    let factory = StrategyFactory::new();
+    assert!(matches!(
+        factory.create_strategy("martingale", &ParameterMap::new()),
+        Err(Error::Strategy(StrategyError::UnknownStrategy(name))) if name == "martingale"
+    ));
+
+    let mut unknown = kandel_parameters();
+    unknown.insert("spread".to_string(), ParameterValue::Float(0.1));
+    assert!(matches!(
+        factory.create_strategy("kandel", &unknown),
+        Err(Error::Strategy(StrategyError::UnknownParameter(name))) if name == "spread"
+    ));
+
+    let mut missing = kandel_parameters();
+    missing.remove("reference_price");
+    assert_eq!(invalid_parameter(factory.create_strategy("kandel", &missing)), ("reference_price".to_string(), "missing".to_string()));
+
+    let mut mistyped = kandel_parameters();
+    mistyped.insert("n_points".to_string(), ParameterValue::Float(2.5));
+    assert_eq!(
+        invalid_parameter(factory.create_strategy("kandel", &mistyped)),
+        ("n_points".to_string(), "expected int, got 2.5".to_string())
+    );
+
+    let mut out_of_range = kandel_parameters();
+    out_of_range.insert("price_grid".to_string(), ParameterValue::FloatList(vec![98.0, 0.0, 102.0]));
+    assert_eq!(
+        invalid_parameter(factory.create_strategy("kandel", &out_of_range)),
+        ("price_grid".to_string(), "must be > 0, got 0".to_string())
+    );
+
+    // Rules spanning several parameters are left to the strategy
+    let mut three_of_three = kandel_parameters();
+    three_of_three.insert("range_multiplier".to_string(), ParameterValue::Float(1.2));
+    assert_eq!(invalid_parameter(factory.create_strategy("kandel", &three_of_three)).0, "n_points, range_multiplier, gridstep");
+}
+
+#[test]
+fn test_kandel_range_is_the_ratio_of_the_highest_price_to_the_reference() {
+    let factory = StrategyFactory::new();
+    let mut by_range = kandel_parameters();
+    by_range.remove("gridstep");
+    by_range.insert("range_multiplier".to_string(), ParameterValue::Float(1.21));
+    let kandel = factory.create_strategy("kandel", &by_range).unwrap();
+    let Some(ParameterValue::FloatList(price_grid)) = kandel.get_parameter("price_grid") else {
+        panic!("no price grid");
+    };
+    assert_eq!(price_grid.len(), 7);
+    assert!((price_grid[6] - 121.0).abs() < 1e-9);
+    assert!((price_grid[0] - 100.0 / 1.21).abs() < 1e-9);
+
+    // Any two of the three give the same grid
+    let by_step = factory.create_strategy("kandel", &kandel_parameters()).unwrap();
+    let gridstep: f64 = 1.02;
+    assert_eq!(by_step.get_parameter("range_multiplier"), Some(ParameterValue::Float(gridstep.powi(3))));
+    by_range.remove("n_points");
+    by_range.insert("gridstep".to_string(), ParameterValue::Float(1.1));
+    assert_eq!(factory.create_strategy("kandel", &by_range).unwrap().get_parameter("n_points"), Some(ParameterValue::Int(2)));
+
+    by_range.insert("range_multiplier".to_string(), ParameterValue::Float(0.5));
+    assert_eq!(
+        invalid_parameter(factory.create_strategy("kandel", &by_range)),
+        ("range_multiplier".to_string(), "must be > 1, got 0.5".to_string())
+    );
+
+    // The amounts read back as they were given
+    assert_eq!(by_step.get_parameter("initial_base"), Some(ParameterValue::Float(10.0)));
+    assert_eq!(by_step.get_parameter("initial_quote"), Some(ParameterValue::Float(1000.0)));
+}
+
+#[test]
+fn test_kandel_gridstep_must_be_greater_than_one() {
+    // A gridstep of 1 puts every price of the grid on the reference
+    let factory = StrategyFactory::new();
+    let mut flat = kandel_parameters();
+    flat.insert("gridstep".to_string(), ParameterValue::Float(1.0));
+    assert_eq!(
+        invalid_parameter(factory.create_strategy("kandel", &flat)),
+        ("gridstep".to_string(), "must be > 1, got 1".to_string())
+    );
+
+    // Checked without the schema too, whichever two parameters are given
+    assert_eq!(
+        KandelStrategy::new(100.0, 10.0, 1000.0, Some(3), None, Some(1.0)).err(),
+        Some(ConfigError::invalid_parameter("gridstep", "must be greater than 1"))
+    );
+    assert_eq!(
+        KandelStrategy::new(100.0, 10.0, 1000.0, None, Some(1.21), Some(1.0)).err(),
+        Some(ConfigError::invalid_parameter("gridstep", "must be greater than 1"))
+    );
+    let mut kandel = factory.create_strategy("kandel", &kandel_parameters()).unwrap();
+    assert!(kandel.set_parameter("gridstep", ParameterValue::Float(1.0)).is_err());
+    assert_eq!(kandel.get_parameter("gridstep"), Some(ParameterValue::Float(1.02)));
+}
+
+#[test]
+fn test_parameter_specs() {
+    let spec = ParameterSpec::int("window", "Blocks averaged").min(1.0).max(100.0).default_value(ParameterValue::Int(10));
+    assert!(!spec.is_required());
+    assert_eq!(spec.to_string(), "window (int, in [1, 100], default 10): Blocks averaged");
+    assert_eq!(spec.check(ParameterValue::Float(20.0)), Ok(ParameterValue::Int(20)));
+    assert!(spec.check(ParameterValue::Int(101)).is_err());
+    assert!(spec.check(ParameterValue::Side(OfferSide::Bid)).is_err());
+
+    let schema = vec![spec, ParameterSpec::float("spread", "Relative spread").above(0.0).optional()];
+    let validated = Parameters::validate(&schema, ParameterMap::new()).unwrap();
+    assert_eq!(validated.int("window"), Ok(10));
+    assert_eq!(validated.optional_float("spread"), None);
+    assert_eq!(schema[1].kind, ParameterKind::Float);
+
+    // Values keep their type through scenario files
+    let parsed: ParameterMap = toml::from_str("a = 1\nb = 1.5\nc = \"Bid\"\nd = [1, 2.5]").unwrap();
+    assert_eq!(
+        parsed,
+        parameters(&[
+            ("a", ParameterValue::Int(1)),
+            ("b", ParameterValue::Float(1.5)),
+            ("c", ParameterValue::Side(OfferSide::Bid)),
+            ("d", ParameterValue::FloatList(vec![1.0, 2.5])),
+        ])
+    );
+}
+
+
+struct Idle {
+    spread: f64,
+}
+
+impl Strategy for Idle {
+    fn name(&self) -> &str {
+        "Idle"
+    }
+
+    fn description(&self) -> &str {
+        "Does nothing"
+    }
+
+    fn execute(&mut self, _: &PricePoint, _: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn post_hook(&mut self, _: &mut StrategyContext, _: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<ParameterValue> {
+        (name == "spread").then_some(ParameterValue::Float(self.spread))
+    }
+}
+
+#[test]
+fn test_custom_strategies_are_registered_with_their_schema() {
+    let mut factory = StrategyFactory::new();
+    let schema = vec![ParameterSpec::float("spread", "Relative spread").min(0.0).default_value(ParameterValue::Float(0.01))];
+    factory.register_strategy("idle", schema, |parameters| Ok(Box::new(Idle { spread: parameters.float("spread")? })));
+    assert_eq!(factory.schema("idle").unwrap()[0].name, "spread");
+    let idle = factory.create_strategy("idle", &ParameterMap::new()).unwrap();
+    assert_eq!(idle.get_parameter("spread"), Some(ParameterValue::Float(0.01)));
+
+    // Only strategies registered as checkpointable can be restored
+    assert!(matches!(
+        factory.restore_strategy("idle", &serde_json::Value::Null),
+        Err(Error::Strategy(StrategyError::NotCheckpointable(name))) if name == "idle"
+    ));
+}
+
+#[test]
+fn test_strategies_expose_typed_parameters() {
+    let factory = StrategyFactory::new();
+    for kind in factory.list_strategies() {
+        let schema = factory.schema(&kind).unwrap();
+        let required: ParameterMap = schema
+            .iter()
+            .filter(|spec| spec.is_required())
+            .map(|spec| {
+                let value = match spec.kind {
+                    ParameterKind::Float => ParameterValue::Float(100.0),
+                    ParameterKind::Int => ParameterValue::Int(2),
+                    ParameterKind::Side => ParameterValue::Side(OfferSide::Bid),
+                    ParameterKind::FloatList => ParameterValue::FloatList(vec![100.0]),
+                };
+                (spec.name.to_string(), value)
+            })
+            .collect();
+        let mut built = required.clone();
+        if kind == "kandel" {
+            built.insert("n_points".to_string(), ParameterValue::Int(2));
+            built.insert("gridstep".to_string(), ParameterValue::Float(1.02));
+        }
+        let strategy = factory.create_strategy(&kind, &built).unwrap();
+
+        // Every parameter of the schema is readable, with its declared type
+        assert_eq!(strategy.parameter_schema(), schema);
+        let parameters = strategy.parameters();
+        assert_eq!(parameters.len(), schema.len(), "{}", kind);
+        for spec in schema {
+            assert_eq!(parameters[spec.name].kind(), spec.kind, "{}.{}", kind, spec.name);
+        }
+        for (name, value) in &required {
+            assert_eq!(&parameters[name], value, "{}.{}", kind, name);
+        }
+    }
+}
+
+#[test]
+fn test_parameters_are_updated_with_their_type() {
+    let factory = StrategyFactory::new();
+    let mut kandel = factory.create_strategy("kandel", &kandel_parameters()).unwrap();
+    assert_eq!(kandel.get_parameter("initial_base"), Some(ParameterValue::Float(10.0)));
+    assert_eq!(kandel.get_parameter("price_grid").map(|grid| grid.kind()), Some(ParameterKind::FloatList));
+
+    // Grid parameters recompute the grid, as long as it is not on the book
+    kandel.set_parameter("n_points", ParameterValue::Float(1.0)).unwrap();
+    assert_eq!(kandel.get_parameter("n_points"), Some(ParameterValue::Int(1)));
+    match kandel.get_parameter("price_grid") {
+        Some(ParameterValue::FloatList(grid)) => assert_eq!(grid.len(), 3),
+        other => panic!("unexpected grid {:?}", other),
+    }
+    kandel.set_parameter("price_grid", ParameterValue::FloatList(vec![99.0, 100.0, 101.0])).unwrap();
+    assert_eq!(kandel.get_parameter("price_grid"), Some(ParameterValue::FloatList(vec![99.0, 100.0, 101.0])));
+
+    assert!(matches!(
+        kandel.set_parameter("gridstep", ParameterValue::Side(OfferSide::Bid)),
+        Err(Error::Config(ConfigError::InvalidParameter { name, .. })) if name == "gridstep"
+    ));
+    assert!(matches!(
+        kandel.set_parameter("spread", ParameterValue::Float(0.1)),
+        Err(Error::Strategy(StrategyError::UnknownParameter(name))) if name == "spread"
+    ));
+    // A rejected update leaves the strategy as it was
+    assert!(kandel.set_parameter("reference_price", ParameterValue::Float(-1.0)).is_err());
+    assert_eq!(kandel.get_parameter("reference_price"), Some(ParameterValue::Float(100.0)));
+
+    let mut limit_order = factory
+        .create_strategy(
+            "limit_order",
+            &parameters(&[
+                ("trigger_price", ParameterValue::Float(95.0)),
+                ("volume", ParameterValue::Float(1.0)),
+                ("side", ParameterValue::Side(OfferSide::Bid)),
+            ]),
+        )
+        .unwrap();
+    limit_order.set_parameter("side", ParameterValue::Side(OfferSide::Ask)).unwrap();
+    assert_eq!(limit_order.get_parameter("side"), Some(ParameterValue::Side(OfferSide::Ask)));
+
+    let mut active_kandel = factory
+        .create_strategy(
+            "active_kandel",
+            &parameters(&[("base_amount", ParameterValue::Float(1.0)), ("quote_amount", ParameterValue::Float(100.0))]),
+        )
+        .unwrap();
+    active_kandel.set_parameter("window_size", ParameterValue::Int(10)).unwrap();
+    assert!(active_kandel.set_parameter("window_size", ParameterValue::Int(0)).is_err());
+    assert_eq!(active_kandel.parameters()["window_size"], ParameterValue::Int(10));
+}
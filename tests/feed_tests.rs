@@ -0,0 +1,157 @@
+use std::io::Cursor;
+
+use mgv_simulator::error_lib::{ConfigError, Error};
+use mgv_simulator::mgv_lib::Market;
+use mgv_simulator::read_utils::{read_price_feed, stream_price_feed, PriceFeedReader};
+use mgv_simulator::scenario_lib::{OutputFormat, PriceFeedConfig, Scenario};
+use mgv_simulator::simu_lib::{PriceFeed, PricePoint, Simulator};
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+use mgv_simulator::strats_lib::StrategyFactory;
+
+
+fn prices() -> Vec<PricePoint> {
+    [100.0, 103.0, 96.0, 96.0, 101.0, 104.0, 97.0, 102.0]
+        .iter()
+        .enumerate()
+        .map(|(block, price)| PricePoint::new(block as u64, *price))
+        .collect()
+}
+
+fn kandel_simulator(price_feed: impl Into<PriceFeed>) -> Simulator {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.clear_sinks();
+    simulator.disable_book_snapshots();
+    simulator.set_seed(7);
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+
+    // Asks are sized in base from the second argument, bids in quote from the third
+    let kandel_strat = KandelStrategy::new(100.0, 10.0, 1000.0, Some(3), None, Some(1.02)).unwrap();
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator
+}
+
+#[test]
+fn test_reader_parses_lines_as_they_come() {
+    let content = "block_number 1;100.5\n\n2; 101\n3;oops\n4;102\n";
+    let mut reader = PriceFeedReader::new(Cursor::new(content), "inline");
+    assert_eq!(reader.next().unwrap().unwrap().price, 100.5);
+    assert_eq!(reader.next().unwrap().unwrap().block, 2);
+    match reader.next().unwrap() {
+        Err(Error::Config(ConfigError::InvalidFeed(reason))) => assert_eq!(reason, "inline: Invalid price at line 4: oops"),
+        other => panic!("unexpected {:?}", other),
+    }
+    // The reader carries on after a bad line, the simulator stops on it
+    assert_eq!(reader.next().unwrap().unwrap().price, 102.0);
+    assert!(reader.next().is_none());
+
+    let streamed: Vec<PricePoint> = PriceFeedReader::open("data/input/fast_test_input.txt")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let loaded = read_price_feed("data/input/fast_test_input.txt").unwrap();
+    assert_eq!(streamed.len(), loaded.len());
+    assert!(streamed.iter().zip(&loaded).all(|(a, b)| a.block == b.block && a.price == b.price));
+    assert!(matches!(stream_price_feed("data/input/missing.txt"), Err(Error::Io(_))));
+}
+
+#[test]
+fn test_streamed_feed_runs_like_the_loaded_one() {
+    let mut loaded = kandel_simulator(prices());
+    loaded.run_simulation(false, false).unwrap();
+
+    // Of unknown length, nothing tells the simulator where the feed ends
+    let mut points = prices().into_iter();
+    let stream = PriceFeed::stream(std::iter::from_fn(move || points.next()));
+    assert_eq!(stream.len(), None);
+    let mut streamed = kandel_simulator(stream);
+    streamed.run_simulation(false, false).unwrap();
+
+    assert_eq!(streamed.current_block, 8);
+    assert_eq!(streamed.price_point.unwrap().price, 102.0);
+    assert_eq!(streamed.run_fingerprint(), loaded.run_fingerprint());
+    assert_eq!(
+        streamed.performance_metrics["kandel"].total_trades,
+        loaded.performance_metrics["kandel"].total_trades
+    );
+    assert_eq!(PriceFeed::stream(prices()).len(), Some(8));
+}
+
+#[test]
+fn test_streamed_feed_resumes_from_a_checkpoint() {
+    let mut uninterrupted = kandel_simulator(prices());
+    uninterrupted.run_simulation(false, false).unwrap();
+
+    let mut first_half = kandel_simulator(PriceFeed::stream(prices()));
+    first_half.run_until(4, false, false).unwrap();
+    let checkpoint = first_half.checkpoint().unwrap();
+
+    // The stream is read again from its start up to the checkpoint
+    let mut resumed = Simulator::from_checkpoint(checkpoint.clone(), PriceFeed::stream(prices()), &StrategyFactory::new()).unwrap();
+    resumed.clear_sinks();
+    resumed.disable_book_snapshots();
+    resumed.run_simulation(false, false).unwrap();
+    assert_eq!(resumed.run_fingerprint(), uninterrupted.run_fingerprint());
+
+    let short = PriceFeed::stream(prices().into_iter().take(2));
+    assert!(matches!(
+        Simulator::from_checkpoint(checkpoint, short, &StrategyFactory::new()),
+        Err(Error::Config(ConfigError::PriceFeedTooShort { needed: 4, available: 2 }))
+    ));
+}
+
+#[test]
+fn test_feed_errors_stop_the_run() {
+    let points = prices()
+        .into_iter()
+        .enumerate()
+        .map(|(block, point)| if block == 5 { Err(ConfigError::InvalidFeed("line 6".to_string()).into()) } else { Ok(point) });
+    let mut simulator = kandel_simulator(PriceFeed::try_stream(points));
+    assert!(matches!(
+        simulator.run_simulation(false, false),
+        Err(Error::Config(ConfigError::InvalidFeed(reason))) if reason == "line 6"
+    ));
+    assert_eq!(simulator.current_block, 5);
+
+    // A stream only goes forward
+    let mut feed = PriceFeed::stream(prices());
+    assert_eq!(feed.point(3).unwrap().unwrap().price, 96.0);
+    assert_eq!(feed.point(3).unwrap().unwrap().price, 96.0);
+    assert!(feed.point(2).is_err());
+    assert!(feed.point(20).unwrap().is_none());
+    assert_eq!(feed.len(), Some(8));
+}
+
+#[test]
+fn test_scenario_streams_its_file_feed() {
+    let mut scenario = Scenario::from_toml(&std::fs::read_to_string("scenarios/kandel_arb.toml").unwrap()).unwrap();
+    scenario.output.format = OutputFormat::None;
+    scenario.price_feed = PriceFeedConfig::File { path: "data/input/fast_test_input.txt".to_string(), stream: false };
+    let loaded = scenario.run(false, false).unwrap();
+
+    scenario.price_feed = PriceFeedConfig::File { path: "data/input/fast_test_input.txt".to_string(), stream: true };
+    assert_eq!(scenario.build().unwrap().price_feed.len(), None);
+    let streamed = scenario.run(false, false).unwrap();
+    assert_eq!(streamed.blocks, 360);
+    assert_eq!(streamed.fingerprint, loaded.fingerprint);
+
+    // Streamed runs keep no point per block, unless asked to
+    let mut simulator = scenario.build().unwrap();
+    simulator.run_simulation(false, false).unwrap();
+    assert!(simulator.equity_curves.is_empty());
+    assert_eq!(simulator.performance_metrics["kandel"].total_trades, streamed.accounts[1].metrics.total_trades);
+    scenario.output.equity_curves = Some(true);
+    let mut simulator = scenario.build().unwrap();
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(simulator.equity_curves["kandel"].len(), 360);
+
+    // Every run of a sweep would need the whole feed
+    assert!(matches!(scenario.run_sweep(), Err(Error::Config(ConfigError::InvalidScenario(_)))));
+}
@@ -0,0 +1,92 @@
+use mgv_simulator::gen_lib::{
+    generate_path, generate_paths, BlockBootstrap, BootstrapMethod, Gbm, MertonJumpDiffusion, OrnsteinUhlenbeck,
+    PathConfig, PricePath, Regime, RegimeSwitching,
+};
+use mgv_simulator::mgv_lib::Market;
+use mgv_simulator::read_utils::read_price_feed;
+use mgv_simulator::simu_lib::{PricePoint, Simulator};
+
+
+#[test]
+fn test_paths_are_seeded() {
+    let model = Gbm { mu: 0.05, sigma: 0.8 };
+    let config = PathConfig::new(100.0, 50, 42);
+    let path = generate_path(model, config);
+    assert_eq!(path.len(), 50);
+    assert_eq!(path[0].price, 100.0);
+    assert_eq!(path[49].block, 49);
+
+    let prices: Vec<f64> = path.iter().map(|point| point.price).collect();
+    let again: Vec<f64> = PricePath::new(model, config).map(|point| point.price).collect();
+    assert_eq!(prices, again);
+
+    let paths = generate_paths(&model, config, 3);
+    let first: Vec<f64> = paths[0].iter().map(|point| point.price).collect();
+    assert_eq!(first, prices);
+    assert_ne!(paths[1][49].price, paths[2][49].price);
+
+    // Without volatility the path is the drift alone
+    let flat = generate_path(Gbm { mu: 0.1, sigma: 0.0 }, PathConfig { dt: 1.0, ..PathConfig::new(100.0, 3, 0) });
+    assert!((flat[2].price - 100.0 * 0.2f64.exp()).abs() < 1e-9);
+}
+
+#[test]
+fn test_models_shapes() {
+    // Mean reversion pulls the price back to its mean
+    let ou = OrnsteinUhlenbeck { theta: 5.0, mean_price: 100.0, sigma: 0.1 };
+    let path = generate_path(ou, PathConfig { dt: 0.01, ..PathConfig::new(150.0, 2000, 1) });
+    let tail_mean = path[1000..].iter().map(|point| point.price).sum::<f64>() / 1000.0;
+    assert!((tail_mean - 100.0).abs() < 5.0);
+
+    // Frequent jumps with no diffusion: some blocks move, the others do not
+    let merton = MertonJumpDiffusion { mu: 0.0, sigma: 0.0, jump_intensity: 10.0, jump_mean: 0.0, jump_std: 0.1 };
+    let path = generate_path(merton, PathConfig { dt: 0.05, ..PathConfig::new(100.0, 200, 3) });
+    let jumps = path.windows(2).filter(|pair| (pair[1].price / pair[0].price).ln().abs() > 0.01).count();
+    assert!(jumps > 50 && jumps < 150);
+
+    let regimes = vec![Regime { mu: 0.0, sigma: 0.0 }, Regime { mu: 0.0, sigma: 1.0 }];
+    assert!(RegimeSwitching::new(regimes.clone(), vec![vec![0.5, 0.4], vec![0.0, 1.0]]).is_err());
+    let switching = RegimeSwitching::new(regimes, vec![vec![0.9, 0.1], vec![0.1, 0.9]]).unwrap();
+    let path = generate_path(switching, PathConfig { dt: 0.01, ..PathConfig::new(100.0, 500, 5) });
+    let calm = path.windows(2).filter(|pair| pair[1].price == pair[0].price).count();
+    assert!(calm > 100 && calm < 400);
+    assert!(path.iter().all(|point| point.price > 0.0));
+}
+
+#[test]
+fn test_generated_path_feeds_simulator() {
+    let path = generate_path(Gbm { mu: 0.0, sigma: 0.5 }, PathConfig::new(100.0, 20, 9));
+    let mut simulator = Simulator::new(Market::new("WETH".to_string(), "USDC".to_string()), path);
+    simulator.clear_sinks();
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(simulator.current_block, 20);
+}
+
+#[test]
+fn test_block_bootstrap_from_feed() {
+    let feed = read_price_feed("data/input/fast_test_input.txt").unwrap();
+    let config = PathConfig { start_block: feed[0].block, ..PathConfig::new(feed[0].price, feed.len(), 11) };
+
+    // A single block spanning the whole sample gives the feed back
+    let whole = BlockBootstrap::from_feed(&feed, feed.len() - 1, BootstrapMethod::Moving).unwrap();
+    let path = generate_path(whole, config);
+    assert_eq!(path[0].block, feed[0].block);
+    for (generated, recorded) in path.iter().zip(feed.iter()) {
+        assert!((generated.price - recorded.price).abs() < 1e-6);
+    }
+
+    // Resampled returns all come from the sample, and a seed gives one path
+    let sample = BlockBootstrap::from_feed(&feed, 20, BootstrapMethod::Stationary).unwrap();
+    let path = generate_path(sample.clone(), config);
+    let again = generate_path(sample.clone(), config);
+    assert_eq!(path.iter().map(|point| point.price).collect::<Vec<_>>(), again.iter().map(|point| point.price).collect::<Vec<_>>());
+    for pair in path.windows(2) {
+        let log_return = (pair[1].price / pair[0].price).ln();
+        assert!(sample.log_returns.iter().any(|sampled| (sampled - log_return).abs() < 1e-9));
+    }
+    assert_ne!(generate_paths(&sample, config, 2)[1].last().unwrap().price, path.last().unwrap().price);
+
+    let short = vec![PricePoint::new(0, 100.0), PricePoint::new(1, 101.0)];
+    assert!(BlockBootstrap::from_feed(&short, 2, BootstrapMethod::Moving).is_err());
+    assert!(BlockBootstrap::from_feed(&short, 0, BootstrapMethod::Moving).is_err());
+}
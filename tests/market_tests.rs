@@ -1,12 +1,14 @@
 use std::sync::{Arc, Mutex};
 
-use mgv_simulator::mgv_lib::{Market, Offer, OfferSide, OrderSide};
+use mgv_simulator::error_lib::{Error, LedgerError, MarketError};
+use mgv_simulator::mgv_lib::{Market, MarketEvent, Offer, OfferSide, OrderSide};
 use mgv_simulator::chain_lib::User;
+use mgv_simulator::gas_lib::GasSchedule;
 use mgv_simulator::{new_user, new_offer};
 use mgv_simulator::strats::{arbitrage::ArbitrageStrategy, kandel::KandelStrategy};
 use mgv_simulator::simu_lib::PricePoint;
 use mgv_simulator::simu_lib::Simulator;
-use mgv_simulator::strats_lib::Strategy;
+use mgv_simulator::strats_lib::{Strategy, StrategyContext};
 
 
 const GASREQ: u128 = 100_000;
@@ -15,7 +17,7 @@ const GASREQ: u128 = 100_000;
 
 struct DummyStrategy;
 impl Strategy for DummyStrategy {
-    fn post_hook(&mut self, _market: &mut Market, _user: Arc<Mutex<User>>, _offer: &Offer) -> Result<(), &'static str> {
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
         Ok(())
     }
     fn name(&self) -> &str {
@@ -24,7 +26,7 @@ impl Strategy for DummyStrategy {
     fn description(&self) -> &str {
         "DummyStrategy"
     }   
-    fn execute(&mut self, _price_point: &PricePoint, _market: &mut Market, _user: Arc<Mutex<User>>) -> Result<(), &'static str> {
+    fn execute(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -32,7 +34,7 @@ impl Strategy for DummyStrategy {
 #[test]
 fn test_place_offer() {
     let maker = new_user!("maker", 100000000000000000.0);
-    maker.lock().unwrap().add_token_balance("USDC", 2000.0);
+    maker.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
     
     let offer = new_offer!(maker, OfferSide::Bid, 2000.0, 1.0, GASREQ, Arc::new(Mutex::new(Box::new(DummyStrategy)))); 
     let mut market = Market::new("WETH".to_string(), "USDC".to_string());
@@ -40,12 +42,108 @@ fn test_place_offer() {
     assert_eq!(market.best_bid().unwrap().price, 2000.0);
 }
 
+#[test]
+fn test_offer_write_gas_cost() {
+    let maker = new_user!("maker", 100000000000000000.0);
+    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+    market.gas_price = 0.5;
+
+    let offer = new_offer!(maker.clone(), OfferSide::Ask, 2000.0, 1.0, GASREQ, Arc::new(Mutex::new(Box::new(DummyStrategy))));
+    market.place_offer(offer).unwrap();
+
+    // 200k gas at 0.5 gwei
+    let expected = 200_000.0 * 0.5 * 1e9;
+    assert_eq!(maker.lock().unwrap().gas_spent, expected);
+    assert_eq!(maker.lock().unwrap().get_native_balance(), 100000000000000000.0 - expected);
+}
+
+#[test]
+fn test_update_and_retract_offer() {
+    let maker = new_user!("maker", 100000000000000000.0);
+    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+    let strategy: Arc<Mutex<Box<dyn Strategy>>> = Arc::new(Mutex::new(Box::new(DummyStrategy)));
+
+    let low = market.place_offer(new_offer!(maker.clone(), OfferSide::Ask, 2000.0, 1.0, GASREQ, Arc::clone(&strategy))).unwrap();
+    let high = market.place_offer(new_offer!(maker.clone(), OfferSide::Ask, 2100.0, 1.0, GASREQ, Arc::clone(&strategy))).unwrap();
+    assert_ne!(low, high);
+
+    // Moving the best ask above the other one reorders the book
+    market.update_offer(low, 2200.0, 0.5).unwrap();
+    assert_eq!(market.best_ask().unwrap().id, high);
+    assert_eq!(market.get_offer(low).unwrap().volume, 0.5);
+
+    let retracted = market.retract_offer(high).unwrap();
+    assert_eq!(retracted.price, 2100.0);
+    assert_eq!(market.best_ask().unwrap().id, low);
+    assert_eq!(market.retract_offer(high).unwrap_err(), MarketError::OfferNotFound(high));
+
+    // Two writes, one update and one retract with the default schedule at 1 gwei
+    let schedule = GasSchedule::default();
+    let expected_gas = 2 * schedule.offer_write + schedule.offer_update + schedule.offer_retract;
+    assert_eq!(maker.lock().unwrap().gas_spent, expected_gas as f64 * 1e9);
+}
+
+#[test]
+fn test_context_only_writes_own_offers() {
+    let maker = new_user!("maker", 100000000000000000.0);
+    let other = new_user!("other", 100000000000000000.0);
+    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+    market.block = 7;
+    let strategy: Arc<Mutex<Box<dyn Strategy>>> = Arc::new(Mutex::new(Box::new(DummyStrategy)));
+    let theirs = market.place_offer(new_offer!(other.clone(), OfferSide::Ask, 2000.0, 1.0, GASREQ, Arc::clone(&strategy))).unwrap();
+
+    let mut context = StrategyContext::new(&mut market, maker.clone());
+    assert_eq!(context.block(), 7);
+    let mine = context.post_offer(OfferSide::Ask, 2100.0, 1.0, GASREQ, Arc::clone(&strategy)).unwrap();
+    assert_eq!(context.book().len(), 2);
+    let own: Vec<u64> = context.own_offers().iter().map(|offer| offer.offer_id).collect();
+    assert_eq!(own, vec![mine]);
+    assert_eq!(context.best_ask().unwrap().offer_id, theirs);
+
+    // Another account's offer can be read but not touched
+    let not_owner = MarketError::NotOfferOwner { offer_id: theirs, account_id: "maker".to_string() };
+    assert_eq!(context.update_offer(theirs, 1900.0, 1.0), Err(not_owner.clone()));
+    assert_eq!(context.retract_offer(theirs), Err(not_owner));
+    context.update_offer(mine, 2050.0, 2.0).unwrap();
+    context.retract_offer(mine).unwrap();
+    assert_eq!(market.snapshot().len(), 1);
+    assert_eq!(market.get_offer(theirs).unwrap().price, 2000.0);
+}
+
+#[test]
+fn test_market_order_gas_with_l1_fee() {
+    let maker = new_user!("maker", 100000000000000000.0);
+    maker.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    let taker = new_user!("taker", 100000000000000000.0);
+    taker.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
+
+    let schedule = GasSchedule::from_name("arbitrum").unwrap();
+    let mut market = Market::with_gas_schedule("WETH".to_string(), "USDC".to_string(), schedule.clone());
+    let offer = new_offer!(maker.clone(), OfferSide::Bid, 2000.0, 1.0, GASREQ, Arc::new(Mutex::new(Box::new(DummyStrategy))));
+    market.place_offer(offer).unwrap();
+    market.market_order(&taker, OrderSide::Sell, 1.0).unwrap();
+
+    let gas = schedule.taker_gasbase + schedule.offer_gasbase + GASREQ;
+    let expected = gas as f64 * schedule.default_gas_price * 1e9 + schedule.l1_take_fee();
+    assert!(schedule.l1_take_fee() > 0.0);
+    assert!((taker.lock().unwrap().gas_spent - expected).abs() < 1.0);
+}
+
+#[test]
+fn test_gas_profiles_are_found_by_name() {
+    assert_eq!(GasSchedule::list_profiles(), vec!["default", "arbitrum", "blast", "polygon"]);
+    for name in GasSchedule::list_profiles() {
+        assert_eq!(GasSchedule::from_name(name).unwrap().name, name);
+    }
+    assert!(GasSchedule::from_name("optimism").is_none());
+}
+
 #[test]
 fn test_market_order() {
     let maker = new_user!("maker", 100000000000000000.0);
-    maker.lock().unwrap().add_token_balance("USDC", 2000.0);
+    maker.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
     let taker = new_user!("taker", 100000000000000000.0);
-    taker.lock().unwrap().add_token_balance("WETH", 1.0);
+    taker.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
 
     let mut market = Market::new("WETH".to_string(), "USDC".to_string());
 
@@ -60,11 +158,77 @@ fn test_market_order() {
 
 }
 
+#[test]
+fn test_market_order_errors() {
+    let maker = new_user!("maker", 100000000000000000.0);
+    maker.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    let taker = new_user!("taker", 100000000000000000.0);
+    taker.lock().unwrap().add_token_balance("WETH", 0.5).unwrap();
+
+    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+    let offer = new_offer!(maker.clone(), OfferSide::Bid, 2000.0, 1.0, GASREQ, Arc::new(Mutex::new(Box::new(DummyStrategy))));
+    market.place_offer(offer).unwrap();
+    let maker_native = maker.lock().unwrap().get_native_balance();
+
+    assert_eq!(
+        market.market_order(&taker, OrderSide::Sell, 2.0),
+        Err(MarketError::InsufficientLiquidity { side: OrderSide::Sell, requested: 2.0, available: 1.0 })
+    );
+    // The taker only holds half of what it sells
+    assert_eq!(
+        market.market_order(&taker, OrderSide::Sell, 1.0),
+        Err(MarketError::Ledger(LedgerError::InsufficientBalance {
+            account_id: "taker".to_string(),
+            token: "WETH".to_string(),
+            needed: 1.0,
+            available: 0.5,
+        }))
+    );
+    // Neither order touched the book nor any balance, gas included
+    assert_eq!(market.bids.len(), 1);
+    assert_eq!(market.bids[0].volume, 1.0);
+    assert_eq!(taker.lock().unwrap().get_token_balance("WETH"), 0.5);
+    assert_eq!(taker.lock().unwrap().get_native_balance(), 100000000000000000.0);
+    assert_eq!(maker.lock().unwrap().get_token_balance("USDC"), 2000.0);
+    assert_eq!(maker.lock().unwrap().get_native_balance(), maker_native);
+
+    // A buyer short of quote is turned away the same way
+    let ask = new_offer!(maker.clone(), OfferSide::Ask, 2100.0, 1.0, GASREQ, Arc::new(Mutex::new(Box::new(DummyStrategy))));
+    market.place_offer(ask).unwrap();
+    taker.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    assert_eq!(
+        market.market_order(&taker, OrderSide::Buy, 1.0),
+        Err(MarketError::Ledger(LedgerError::InsufficientBalance {
+            account_id: "taker".to_string(),
+            token: "USDC".to_string(),
+            needed: 2100.0,
+            available: 1000.0,
+        }))
+    );
+    assert_eq!(market.asks.len(), 1);
+    assert_eq!(taker.lock().unwrap().get_token_balance("USDC"), 1000.0);
+    assert_eq!(taker.lock().unwrap().get_native_balance(), 100000000000000000.0);
+}
+
+#[test]
+fn test_error_wrappers_are_transparent() {
+    use std::error::Error as _;
+
+    let ledger = LedgerError::NegativeAmount(-1.0);
+    let error = Error::Market(MarketError::Ledger(ledger.clone()));
+    assert_eq!(error.to_string(), ledger.to_string());
+    assert!(error.source().is_none());
+
+    // A failed post hook is a distinct error, its cause is the source
+    let error = Error::Market(MarketError::PostHook { offer_id: 1, source: Box::new(ledger.clone().into()) });
+    assert_eq!(error.source().unwrap().to_string(), ledger.to_string());
+}
+
 
 #[test]
 fn test_kandel_with_arb() {
     // Initialize simulator with market and price feed
-    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
     let price_feed = vec![
         PricePoint::new(0, 100.0),  // Initial price
         PricePoint::new(1, 101.0),  // Price moves up
@@ -82,12 +246,12 @@ fn test_kandel_with_arb() {
 
     // Create and register users
     let kandel_user = simulator.add_user("kandel".to_string(), 100000000000000000.0);
-    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0);
-    kandel_user.lock().unwrap().add_token_balance("USDC", 20000.0);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 20000.0).unwrap();
 
     let arb_user = simulator.add_user("arb".to_string(), 100000000000000000.0);
-    arb_user.lock().unwrap().add_token_balance("WETH", 10.0);
-    arb_user.lock().unwrap().add_token_balance("USDC", 20000.0);
+    arb_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    arb_user.lock().unwrap().add_token_balance("USDC", 20000.0).unwrap();
 
     // Create and configure strategies
     let reference_price = 100.0;
@@ -96,7 +260,7 @@ fn test_kandel_with_arb() {
     let n_points = 2;
     //let range_multiplier = 0.0;
     let gridstep = 2.0;
-    let mut kandel_strat = KandelStrategy::new(
+    let kandel_strat = KandelStrategy::new(
                                             reference_price, 
                                             initial_base, 
                                             initial_quote, 
@@ -130,4 +294,30 @@ fn test_kandel_with_arb() {
 
     // Print metrics
     simulator.print_metrics();
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_failing_offer_pays_bounty() {
+    let maker = new_user!("maker", 1e18);
+    let taker = new_user!("taker", 1e18);
+    taker.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+
+    // The maker does not hold the WETH it offers
+    let offer = new_offer!(maker.clone(), OfferSide::Ask, 2000.0, 1.0, GASREQ, Arc::new(Mutex::new(Box::new(DummyStrategy))));
+    market.place_offer(offer).unwrap();
+    // Nothing is filled, the taker only gets the bounty
+    assert_eq!(market.market_order(&taker, OrderSide::Buy, 1.0).unwrap(), 0.0);
+
+    // 100k gasreq at 1 gwei, the order itself costs the taker 100k as well
+    let bounty = 100_000.0 * 1e9;
+    let failures = market.take_offer_failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].bounty, bounty);
+    assert_eq!(failures[0].maker_id, "maker");
+    assert!(market.asks.is_empty());
+    assert!(!market.take_events().iter().any(|event| matches!(event, MarketEvent::Fill(_))));
+    assert_eq!(taker.lock().unwrap().get_token_balance("USDC"), 2000.0);
+    assert_eq!(taker.lock().unwrap().get_native_balance(), 1e18 - 100_000.0 * 1e9 + bounty);
+    assert_eq!(maker.lock().unwrap().get_native_balance(), 1e18 - 200_000.0 * 1e9 - bounty);
+}
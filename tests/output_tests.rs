@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::PathBuf;
+
+use mgv_simulator::mgv_lib::{Market, OfferSide};
+use mgv_simulator::output_lib::{replay_book, BookRowKind, CsvSink, JsonLinesSink, MemoryRecorder};
+use mgv_simulator::simu_lib::{PricePoint, Simulator};
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+use mgv_simulator::strats::limit_order::LimitOrderStrategy;
+
+
+fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mgv_simulator_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn limit_order_simulator() -> Simulator {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed = vec![PricePoint::new(0, 100.0), PricePoint::new(1, 102.0)];
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.clear_sinks();
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("WETH", 2.0).unwrap();
+    maker.lock().unwrap().add_token_balance("DAI", 5.0).unwrap();
+
+    let strategy = LimitOrderStrategy::new(102.0, 1.0, OfferSide::Ask);
+    simulator.add_strategy("limit".to_string(), Box::new(strategy));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator
+}
+
+#[test]
+fn test_outputs_are_opt_in() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, vec![PricePoint::new(0, 100.0)]);
+    assert!(simulator.sinks.is_empty());
+
+    let dir = output_dir("opt_in");
+    simulator.set_output_dir(dir.to_str().unwrap());
+    simulator.add_user("maker".to_string(), 1e18);
+    simulator.run_simulation(false, false).unwrap();
+    assert!(dir.join("maker.csv").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_memory_recorder_labels_balances() {
+    let mut simulator = limit_order_simulator();
+    let recorder = MemoryRecorder::new();
+    simulator.add_sink(Box::new(recorder.clone()));
+    simulator.run_simulation(false, false).unwrap();
+
+    let recording = recorder.recording();
+    let recording = recording.lock().unwrap();
+    // One row per block, block 0 included once
+    assert_eq!(recording.balances.len(), 2);
+    assert_eq!(recording.market_states.len(), 2);
+    assert_eq!(recording.market_states[0].block, 0);
+
+    let columns: Vec<&str> = recording.balances[0].balances.iter().map(|(column, _)| column.as_str()).collect();
+    assert_eq!(columns, vec!["native", "WETH", "USDC", "DAI"]);
+    assert_eq!(recording.balances[0].balances[1].1, 2.0);
+
+    let last_state = recording.market_states.last().unwrap();
+    assert_eq!(last_state.block, 1);
+    assert_eq!(last_state.best_ask, Some(102.0));
+    assert_eq!(last_state.best_bid, None);
+    assert_eq!(last_state.ask_volume, 1.0);
+}
+
+#[test]
+fn test_csv_and_json_lines_sinks() {
+    let csv_dir = output_dir("csv");
+    let json_dir = output_dir("jsonl");
+    let mut simulator = limit_order_simulator();
+    simulator.add_sink(Box::new(CsvSink::new(&csv_dir)));
+    simulator.add_sink(Box::new(JsonLinesSink::new(&json_dir)));
+    simulator.run_simulation(false, false).unwrap();
+
+    let balances = fs::read_to_string(csv_dir.join("maker.csv")).unwrap();
+    let lines: Vec<&str> = balances.lines().collect();
+    assert_eq!(lines[0], "block,native,WETH,USDC,DAI");
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("0,"));
+    assert!(lines[2].starts_with("1,"));
+
+    let market_state = fs::read_to_string(csv_dir.join("market_state.csv")).unwrap();
+    assert!(market_state.starts_with("block,price,best_bid,best_ask"));
+    assert!(market_state.lines().last().unwrap().starts_with("1,102,,102,0,1,"));
+
+    let json_balances = fs::read_to_string(json_dir.join("balances.jsonl")).unwrap();
+    let first: serde_json::Value = serde_json::from_str(json_balances.lines().next().unwrap()).unwrap();
+    assert_eq!(first["account"], "maker");
+    assert_eq!(first["balances"]["WETH"], 2.0);
+    assert_eq!(fs::read_to_string(json_dir.join("market_state.jsonl")).unwrap().lines().count(), 2);
+
+    let book = fs::read_to_string(csv_dir.join("order_book.csv")).unwrap();
+    let book_lines: Vec<&str> = book.lines().collect();
+    assert_eq!(book_lines[0], "block,kind,side,price,volume,maker,offer_id");
+    assert_eq!(book_lines[1], "0,clear,,,,,");
+    assert_eq!(book_lines.last(), Some(&"1,add,ask,102,1,maker,1"));
+    let json_book = fs::read_to_string(json_dir.join("order_book.jsonl")).unwrap();
+    let row: serde_json::Value = serde_json::from_str(json_book.lines().last().unwrap()).unwrap();
+    assert_eq!(row["kind"], "add");
+    assert_eq!(row["offer_id"], 1);
+
+    fs::remove_dir_all(csv_dir).unwrap();
+    fs::remove_dir_all(json_dir).unwrap();
+}
+
+#[test]
+fn test_book_snapshots_and_deltas_replay() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed = vec![
+        PricePoint::new(0, 100.0),
+        PricePoint::new(1, 103.0),
+        PricePoint::new(2, 96.0),
+        PricePoint::new(3, 101.0),
+        PricePoint::new(4, 99.0),
+    ];
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.clear_sinks();
+    simulator.set_book_snapshots(3, true);
+    let recorder = MemoryRecorder::new();
+    simulator.add_sink(Box::new(recorder.clone()));
+
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+    let mut kandel_strat = KandelStrategy::new(100.0, 1.0, 100.0, Some(1), None, Some(1.02)).unwrap();
+    kandel_strat.set_price_grid(vec![98.0, 100.0, 102.0]);
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+
+    simulator.run_simulation(false, false).unwrap();
+
+    let recording = recorder.recording();
+    let rows = &recording.lock().unwrap().book;
+    // Block 3 is a full snapshot, blocks 1, 2 and 4 only carry deltas
+    let snapshot: Vec<BookRowKind> = rows.iter().filter(|row| row.block == 3).map(|row| row.kind).collect();
+    assert_eq!(snapshot[0], BookRowKind::Clear);
+    assert!(snapshot[1..].iter().all(|kind| *kind == BookRowKind::Snapshot));
+    assert!(rows.iter().filter(|row| row.block == 1).all(|row| row.kind != BookRowKind::Snapshot));
+    assert!(rows.iter().any(|row| row.block == 1 && row.kind == BookRowKind::Remove));
+    assert!(rows.iter().any(|row| row.block == 1 && row.kind == BookRowKind::Add));
+
+    // The final book can be rebuilt from the rows alone
+    let replayed = replay_book(rows, 4);
+    let mut expected = simulator.market.snapshot();
+    expected.sort_by_key(|entry| entry.offer_id);
+    assert_eq!(replayed, expected);
+    assert!(!expected.is_empty());
+    assert!(replayed.iter().all(|entry| entry.maker == "kandel"));
+}
+
+#[test]
+fn test_replay_rebuilds_an_emptied_book() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed = vec![PricePoint::new(0, 100.0), PricePoint::new(1, 100.0), PricePoint::new(2, 103.0)];
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.set_book_snapshots(2, false);
+    let recorder = MemoryRecorder::new();
+    simulator.add_sink(Box::new(recorder.clone()));
+
+    // The ask is posted at block 0 and taken at block 2, leaving the book empty
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
+    let arb = simulator.add_user("arb".to_string(), 1e18);
+    arb.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    simulator.add_strategy("limit".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask)));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+
+    simulator.run_simulation(false, false).unwrap();
+
+    let recording = recorder.recording();
+    let rows = &recording.lock().unwrap().book;
+    assert_eq!(replay_book(rows, 0).len(), 1);
+    assert!(simulator.market.asks.is_empty());
+    assert!(rows.iter().any(|row| row.block == 2 && row.kind == BookRowKind::Clear));
+    assert!(replay_book(rows, 2).is_empty());
+}
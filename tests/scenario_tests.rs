@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use mgv_simulator::error_lib::{ConfigError, Error, StrategyError};
+use mgv_simulator::params_lib::ParameterValue;
+use mgv_simulator::scenario_lib::{OutputFormat, PriceFeedConfig, RunSummary, Scenario, SUMMARY_FILE};
+use mgv_simulator::sweep_lib::ParameterSet;
+
+
+const SCENARIO: &str = r#"
+name = "test"
+seed = 7
+
+[market]
+base = "WETH"
+quote = "USDC"
+
+[price_feed]
+source = "inline"
+prices = [100, 103, 96, 96, 101, 104, 97, 102]
+
+[[users]]
+id = "kandel"
+native = 1e18
+balances = { WETH = 10.0, USDC = 1000.0 }
+
+[[users]]
+id = "arb"
+native = 1e18
+balances = { WETH = 100.0, USDC = 10000.0 }
+
+[[strategies]]
+id = "kandel_strat"
+account = "kandel"
+kind = "kandel"
+reference_price = 100.0
+initial_base = 10.0
+initial_quote = 1000.0
+n_points = 3
+gridstep = 1.02
+
+[[strategies]]
+id = "arb_strat"
+account = "arb"
+kind = "arbitrage"
+min_profit_threshold = 0.0
+max_volume_per_trade = 1000.0
+
+[output]
+format = "none"
+
+[sweep]
+parameters = { "kandel_strat.gridstep" = [1.01, 1.02], "kandel_strat.n_points" = [2, 3] }
+threads = 2
+"#;
+
+#[test]
+fn test_scenario_runs_like_its_toml_says() {
+    let scenario = Scenario::from_toml(SCENARIO).unwrap();
+    assert_eq!(scenario.price_feed().unwrap().len(), 8);
+    assert_eq!(scenario.strategies[0].priority, 0);
+    assert_eq!(scenario.strategies[0].kind, "kandel");
+    assert_eq!(scenario.strategies[0].parameters["n_points"], ParameterValue::Int(3));
+    assert!(!scenario.strategies[0].parameters.contains_key("range_multiplier"));
+
+    // The same scenario in JSON builds the same simulation
+    let json = serde_json::to_string(&scenario).unwrap();
+    assert_eq!(Scenario::from_json(&json).unwrap(), scenario);
+
+    let summary = scenario.run(false, false).unwrap();
+    assert_eq!(summary.blocks, 8);
+    assert_eq!(summary.accounts.iter().map(|account| account.account_id.as_str()).collect::<Vec<_>>(), ["arb", "kandel"]);
+    assert!(summary.accounts[1].metrics.total_trades > 0);
+    assert_eq!(summary.strategies.len(), 2);
+
+    let mut simulator = scenario.build().unwrap();
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(simulator.run_fingerprint(), summary.fingerprint);
+}
+
+#[test]
+fn test_summary_is_written_and_read_back() {
+    let dir = std::env::temp_dir().join("mgv_scenario_tests").join("summary");
+    let mut scenario = Scenario::from_toml(SCENARIO).unwrap();
+    scenario.output.dir = dir.to_string_lossy().to_string();
+    scenario.output.format = OutputFormat::JsonLines;
+    let summary = scenario.run(false, false).unwrap();
+
+    // Either the file or the directory holding it
+    for path in [dir.join(SUMMARY_FILE), dir.clone()] {
+        let loaded = RunSummary::load(&path).unwrap();
+        assert_eq!(loaded.fingerprint, summary.fingerprint);
+        assert_eq!(loaded.accounts[1].metrics.total_trades, summary.accounts[1].metrics.total_trades);
+        assert_eq!(loaded.to_string(), summary.to_string());
+    }
+    assert!(dir.join("market_state.jsonl").exists());
+
+    // Any other file is not mistaken for a scenario
+    assert!(matches!(
+        RunSummary::load(&dir.join("market_state.jsonl")),
+        Err(Error::Config(ConfigError::InvalidSummary(_)))
+    ));
+}
+
+#[test]
+fn test_parameters_override_strategy_parameters() {
+    let scenario = Scenario::from_toml(SCENARIO).unwrap();
+    let parameters = ParameterSet { values: vec![("kandel_strat.n_points".to_string(), 2.0), ("arb_strat.min_profit_threshold".to_string(), 0.5)] };
+    let overridden = scenario.with_parameters(&parameters).unwrap();
+    assert_eq!(overridden.strategies[0].parameters["n_points"], ParameterValue::Float(2.0));
+    assert_eq!(overridden.strategies[1].parameters["min_profit_threshold"], ParameterValue::Float(0.5));
+    overridden.build().unwrap();
+
+    for name in ["maker.gridstep", "gridstep", "kandel_strat.kind"] {
+        let parameters = ParameterSet { values: vec![(name.to_string(), 1.0)] };
+        assert!(matches!(
+            scenario.with_parameters(&parameters),
+            Err(Error::Config(ConfigError::InvalidParameter { name: invalid, .. })) if invalid == name
+        ));
+    }
+
+    // Parameters unknown to the strategy are rejected when it is built
+    let parameters = ParameterSet { values: vec![("kandel_strat.spread".to_string(), 1.0)] };
+    assert!(matches!(
+        scenario.with_parameters(&parameters).unwrap().build(),
+        Err(Error::Strategy(StrategyError::UnknownParameter(name))) if name == "spread"
+    ));
+}
+
+#[test]
+fn test_invalid_scenarios_are_rejected() {
+    let unknown_kind = SCENARIO.replace("kind = \"arbitrage\"", "kind = \"martingale\"");
+    assert!(matches!(
+        Scenario::from_toml(&unknown_kind).unwrap().build(),
+        Err(Error::Strategy(StrategyError::UnknownStrategy(kind))) if kind == "martingale"
+    ));
+    let no_kind = SCENARIO.replace("kind = \"arbitrage\"", "");
+    assert!(matches!(Scenario::from_toml(&no_kind), Err(Error::Config(ConfigError::InvalidScenario(_)))));
+
+    let mut scenario = Scenario::from_toml(SCENARIO).unwrap();
+    scenario.market.gas_profile = Some("solana".to_string());
+    assert!(matches!(scenario.build(), Err(Error::Config(ConfigError::InvalidScenario(_)))));
+
+    // Strategy parameters are checked against their schema
+    let mut scenario = Scenario::from_toml(SCENARIO).unwrap();
+    scenario.price_feed = PriceFeedConfig::Inline { prices: vec![100.0] };
+    scenario.strategies[0].parameters.insert("gridstep".to_string(), ParameterValue::Float(-1.0));
+    assert!(matches!(
+        scenario.build(),
+        Err(Error::Config(ConfigError::InvalidParameter { name, .. })) if name == "gridstep"
+    ));
+
+    scenario.price_feed = PriceFeedConfig::Inline { prices: vec![] };
+    assert!(matches!(scenario.price_feed(), Err(Error::Config(ConfigError::InvalidScenario(_)))));
+    assert!(Scenario::load(Path::new("scenarios/kandel_arb.yaml")).is_err());
+}
+
+#[test]
+fn test_scenario_sweep() {
+    let scenario = Scenario::from_toml(SCENARIO).unwrap();
+    let table = scenario.run_sweep().unwrap();
+    assert!(table.failures.is_empty());
+    assert_eq!(table.rows.len(), 4 * 2);
+
+    // Each run is the scenario with its parameters
+    let row = table.rows.iter().find(|row| row.run == 3 && row.account_id == "kandel").unwrap();
+    let mut simulator = scenario.with_parameters(&row.parameters).unwrap().build().unwrap();
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(simulator.run_fingerprint(), row.fingerprint);
+
+    let mut without_sweep = scenario.clone();
+    without_sweep.sweep = None;
+    assert!(matches!(without_sweep.run_sweep(), Err(Error::Config(ConfigError::InvalidScenario(_)))));
+}
+
+#[test]
+fn test_active_kandel_calibrates_on_its_window() {
+    let kandel = "kind = \"kandel\"\nreference_price = 100.0\ninitial_base = 10.0\ninitial_quote = 1000.0\nn_points = 3\ngridstep = 1.02";
+    let active_kandel = "kind = \"active_kandel\"\nwindow_size = 3\nrecalibration_interval = 4\nn_points = 2\nbase_amount = 10.0\nquote_amount = 1000.0";
+    assert!(SCENARIO.contains(kandel));
+    let scenario = Scenario::from_toml(&SCENARIO.replace(kandel, active_kandel)).unwrap();
+    let mut simulator = scenario.build().unwrap();
+
+    // Nothing is placed before the window is full, then the grid is centred
+    // on the last price and spans the window's dispersion
+    simulator.run_until(2, false, false).unwrap();
+    assert!(simulator.market.bids.is_empty() && simulator.market.asks.is_empty());
+    simulator.run_until(3, false, false).unwrap();
+    let bids: Vec<f64> = simulator.market.bids.iter().map(|offer| offer.price).collect();
+    let asks: Vec<f64> = simulator.market.asks.iter().map(|offer| offer.price).collect();
+    assert!(!bids.is_empty() && !asks.is_empty(), "{:?} {:?}", bids, asks);
+    assert!(bids.iter().all(|price| *price < 96.0) && asks.iter().all(|price| *price > 96.0));
+    let log_prices = [100.0f64.ln(), 103.0f64.ln(), 96.0f64.ln()];
+    let mean = log_prices.iter().sum::<f64>() / 3.0;
+    let std_dev = (log_prices.iter().map(|price| (price - mean).powi(2)).sum::<f64>() / 3.0).sqrt();
+    assert_eq!(asks.len(), 2);
+    assert!((asks[1] - 96.0 * (2.0 * std_dev).exp()).abs() < 1e-9);
+    assert!((bids[1] - 96.0 / (2.0 * std_dev).exp()).abs() < 1e-9);
+
+    simulator.run_simulation(false, false).unwrap();
+    assert!(simulator.performance_metrics["kandel"].total_trades > 0);
+}
+
+#[test]
+fn test_shipped_scenarios_build() {
+    for path in ["scenarios/kandel_arb.toml", "scenarios/gbm_limit_order.json"] {
+        let scenario = Scenario::load(Path::new(path)).unwrap();
+        assert!(!scenario.strategies.is_empty());
+        scenario.build().unwrap();
+    }
+}
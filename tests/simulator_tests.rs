@@ -0,0 +1,877 @@
+use std::sync::{Arc, Mutex};
+
+use mgv_simulator::error_lib::{Error, LedgerError, StrategyError};
+use mgv_simulator::mgv_lib::{Fill, Market, Offer, OfferSide, OfferWrite, OrderSide};
+use mgv_simulator::observer_lib::{ErrorEvent, Observer, StrategyAction};
+use mgv_simulator::output_lib::MemoryRecorder;
+use mgv_simulator::params_lib::ParameterValue;
+use mgv_simulator::simu_lib::{GasPricePoint, PricePoint, Simulator};
+use mgv_simulator::strats::active_kandel::ActiveKandelStrategy;
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+use mgv_simulator::strats::limit_order::LimitOrderStrategy;
+use mgv_simulator::strats_lib::{Event, Strategy, StrategyContext, Trigger};
+
+
+// Records the block of every price point it acts upon
+struct RecordingStrategy {
+    seen: Arc<Mutex<Vec<u64>>>,
+}
+
+// Records its tag every time it runs
+struct TaggingStrategy {
+    tag: u64,
+    log: Arc<Mutex<Vec<u64>>>,
+}
+
+impl Strategy for TaggingStrategy {
+    fn name(&self) -> &str {
+        "TaggingStrategy"
+    }
+    fn description(&self) -> &str {
+        "TaggingStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        self.log.lock().unwrap().push(self.tag);
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Strategy for RecordingStrategy {
+    fn name(&self) -> &str {
+        "RecordingStrategy"
+    }
+    fn description(&self) -> &str {
+        "RecordingStrategy"
+    }
+    fn execute(&mut self, price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        self.seen.lock().unwrap().push(price_point.block);
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Buys once, on the first block it runs in
+struct BuyingStrategy {
+    volume: f64,
+    done: bool,
+}
+
+impl Strategy for BuyingStrategy {
+    fn name(&self) -> &str {
+        "BuyingStrategy"
+    }
+    fn description(&self) -> &str {
+        "BuyingStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
+        if !self.done {
+            self.done = true;
+            context.take(OrderSide::Buy, self.volume)?;
+        }
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Records the time of every block it runs in
+struct ClockStrategy {
+    timestamps: Arc<Mutex<Vec<u64>>>,
+}
+
+impl Strategy for ClockStrategy {
+    fn name(&self) -> &str {
+        "ClockStrategy"
+    }
+    fn description(&self) -> &str {
+        "ClockStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
+        self.timestamps.lock().unwrap().push(context.timestamp());
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+type EventLog = Arc<Mutex<Vec<(u64, Vec<Event>)>>>;
+
+// Logs the events it is woken up by, and posts an ask on its timer
+#[derive(Clone)]
+struct SubscribingStrategy {
+    triggers: Vec<Trigger>,
+    log: EventLog,
+}
+
+impl Strategy for SubscribingStrategy {
+    fn name(&self) -> &str {
+        "SubscribingStrategy"
+    }
+    fn description(&self) -> &str {
+        "SubscribingStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+    fn triggers(&self) -> Vec<Trigger> {
+        self.triggers.clone()
+    }
+    fn on_events(&mut self, events: &[Event], price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
+        self.log.lock().unwrap().push((price_point.block, events.to_vec()));
+        if events.iter().any(|event| matches!(event, Event::Timer(_))) {
+            let strategy: Arc<Mutex<Box<dyn Strategy>>> = Arc::new(Mutex::new(Box::new(self.clone())));
+            let gasreq = context.gas_schedule().default_gasreq;
+            context.post_offer(OfferSide::Ask, 102.0, 1.0, gasreq, strategy)?;
+        }
+        Ok(())
+    }
+}
+
+// Sells its base at the end of the run and keeps track of its lifecycle
+#[derive(Default)]
+struct UnwindingStrategy {
+    start_balance: f64,
+    blocks: u64,
+    fills: Vec<Fill>,
+}
+
+impl Strategy for UnwindingStrategy {
+    fn name(&self) -> &str {
+        "UnwindingStrategy"
+    }
+    fn description(&self) -> &str {
+        "UnwindingStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+    fn on_start(&mut self, _price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
+        self.start_balance = context.balance(context.base());
+        Ok(())
+    }
+    fn on_block(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        self.blocks += 1;
+        Ok(())
+    }
+    fn runs_on_block(&self) -> bool {
+        true
+    }
+    fn on_fill(&mut self, fill: &Fill, _context: &mut StrategyContext) -> Result<(), Error> {
+        self.fills.push(fill.clone());
+        Ok(())
+    }
+    fn on_end(&mut self, _price_point: &PricePoint, context: &mut StrategyContext) -> Result<(), Error> {
+        context.take(OrderSide::Sell, self.start_balance)?;
+        Ok(())
+    }
+    fn metrics(&self) -> Vec<(String, f64)> {
+        vec![
+            ("blocks".to_string(), self.blocks as f64),
+            ("fills".to_string(), self.fills.len() as f64),
+        ]
+    }
+}
+
+fn rising_feed(len: u64) -> Vec<PricePoint> {
+    (0..len).map(|i| PricePoint::new(i, 100.0 + i as f64)).collect()
+}
+
+#[test]
+fn test_latency_delays_actions() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(5));
+    simulator.add_user("bot".to_string(), 100000000000000000.0);
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    simulator.add_strategy("recorder".to_string(), Box::new(RecordingStrategy { seen: Arc::clone(&seen) }));
+    simulator.assign_strategy("bot", "recorder").unwrap();
+    simulator.set_strategy_latency("recorder", 2).unwrap();
+
+    simulator.run_simulation(false, false).unwrap();
+
+    // The strategy decides on every block, the last two decisions were still in flight
+    assert_eq!(*seen.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(simulator.failed_actions.len(), 2);
+    assert_eq!(simulator.failed_actions[0].decided_at, 3);
+    assert_eq!(simulator.failed_actions[0].executed_at, 5);
+}
+
+#[test]
+fn test_delayed_take_fails_when_the_book_moves() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(4));
+    let maker = simulator.add_user("maker".to_string(), 100000000000000000.0);
+    maker.lock().unwrap().add_token_balance("WETH", 2.0).unwrap();
+    let buyer = simulator.add_user("buyer".to_string(), 100000000000000000.0);
+    buyer.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    let strategy: Arc<Mutex<Box<dyn Strategy>>> = Arc::new(Mutex::new(Box::new(BuyingStrategy { volume: 1.0, done: true })));
+    let ask = simulator.market.place_offer(Offer::new(Arc::clone(&maker), OfferSide::Ask, 100.0, 1.0, 100_000, Arc::clone(&strategy))).unwrap();
+
+    simulator.add_strategy("buy".to_string(), Box::new(BuyingStrategy { volume: 1.0, done: false }));
+    simulator.assign_strategy("buyer", "buy").unwrap();
+    simulator.set_strategy_latency("buy", 1).unwrap();
+
+    // Decided at block 0 on the ask at 100, nothing moved yet
+    simulator.run_until(1, false, false).unwrap();
+    assert_eq!(simulator.market.best_ask().unwrap().id, ask);
+    assert_eq!(buyer.lock().unwrap().get_token_balance("USDC"), 1000.0);
+
+    // The maker reprices before the order lands
+    simulator.market.retract_offer(ask).unwrap();
+    simulator.market.place_offer(Offer::new(Arc::clone(&maker), OfferSide::Ask, 110.0, 1.0, 100_000, strategy)).unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // The decision of the last block was still in flight
+    assert_eq!(simulator.failed_actions.len(), 2);
+    assert_eq!(simulator.failed_actions[0].decided_at, 0);
+    assert_eq!(simulator.failed_actions[0].executed_at, 1);
+    assert_eq!(
+        simulator.failed_actions[0].reason,
+        "Price moved: Buy order limited to 100 would fill at 110"
+    );
+    assert_eq!(buyer.lock().unwrap().get_token_balance("USDC"), 1000.0);
+    assert_eq!(buyer.lock().unwrap().get_token_balance("WETH"), 0.0);
+    assert_eq!(simulator.market.best_ask().unwrap().price, 110.0);
+}
+
+#[test]
+fn test_delayed_take_lands_when_the_book_holds() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(4));
+    let maker = simulator.add_user("maker".to_string(), 100000000000000000.0);
+    maker.lock().unwrap().add_token_balance("WETH", 2.0).unwrap();
+    let buyer = simulator.add_user("buyer".to_string(), 100000000000000000.0);
+    buyer.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    let strategy: Arc<Mutex<Box<dyn Strategy>>> = Arc::new(Mutex::new(Box::new(BuyingStrategy { volume: 1.0, done: true })));
+    simulator.market.place_offer(Offer::new(Arc::clone(&maker), OfferSide::Ask, 100.0, 1.0, 100_000, strategy)).unwrap();
+
+    simulator.add_strategy("buy".to_string(), Box::new(BuyingStrategy { volume: 1.0, done: false }));
+    simulator.assign_strategy("buyer", "buy").unwrap();
+    simulator.set_strategy_latency("buy", 2).unwrap();
+
+    simulator.run_until(2, false, false).unwrap();
+    assert_eq!(buyer.lock().unwrap().get_token_balance("WETH"), 0.0);
+    simulator.run_simulation(false, false).unwrap();
+
+    // Only the decisions of the last two blocks, still in flight, failed
+    assert!(simulator.failed_actions.iter().all(|action| action.decided_at >= 2));
+    assert_eq!(buyer.lock().unwrap().get_token_balance("WETH"), 1.0);
+    assert_eq!(buyer.lock().unwrap().get_token_balance("USDC"), 900.0);
+    assert!(simulator.market.best_ask().is_none());
+}
+
+#[test]
+fn test_recalibration_leaves_the_other_strategies_of_the_account_alone() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(6));
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("WETH", 20.0).unwrap();
+    maker.lock().unwrap().add_token_balance("USDC", 3000.0).unwrap();
+
+    simulator.add_strategy("limit".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Bid)));
+    simulator.add_strategy("active".to_string(), Box::new(ActiveKandelStrategy::new(2, 1, 2, 10.0, 1000.0)));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator.assign_strategy("maker", "active").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // The grid was redeployed on every block, the limit order stayed
+    let owners = |side: &Vec<Offer>| -> Vec<Option<String>> { side.iter().map(|offer| offer.strategy_id.clone()).collect() };
+    let bids = owners(&simulator.market.bids);
+    assert_eq!(bids.iter().filter(|owner| owner.as_deref() == Some("limit")).count(), 1);
+    assert_eq!(bids.iter().filter(|owner| owner.as_deref() == Some("active")).count(), 2);
+    assert_eq!(owners(&simulator.market.asks), vec![Some("active".to_string()); 2]);
+}
+
+#[test]
+fn test_latency_requires_known_strategy() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(3));
+    assert_eq!(
+        simulator.set_strategy_latency("missing", 1),
+        Err(StrategyError::UnknownStrategy("missing".to_string()))
+    );
+}
+
+#[test]
+fn test_gas_price_feed_and_gas_in_quote() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(4));
+    simulator.set_gas_price_feed(vec![GasPricePoint::new(0, 1.0), GasPricePoint::new(2, 3.0)]);
+    let user = simulator.add_user("maker".to_string(), 1e18);
+
+    let strategy = LimitOrderStrategy::new(102.0, 1.0, OfferSide::Ask);
+    simulator.add_strategy("limit".to_string(), Box::new(strategy));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // The ask is written at block 2, once the gas price moved to 3 gwei
+    assert_eq!(simulator.market.gas_price, 3.0);
+    let gas_spent = user.lock().unwrap().gas_spent;
+    assert_eq!(gas_spent, 200_000.0 * 3.0 * 1e9);
+
+    // Converted at the native price of block 2 (WETH at 102 USDC)
+    let metrics = &simulator.performance_metrics["maker"];
+    assert!((metrics.gas_spent_native - 0.0006).abs() < 1e-12);
+    assert!((metrics.gas_spent_quote - 0.0006 * 102.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_out_of_gas_maker_is_halted() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed = vec![
+        PricePoint::new(0, 100.0),
+        PricePoint::new(1, 103.0),
+        PricePoint::new(2, 96.0),
+    ];
+    let mut simulator = Simulator::new(market, price_feed);
+
+    // Exactly enough native for the two initial offers at 200k gas and 1 gwei
+    let kandel_user = simulator.add_user("kandel".to_string(), 4e14);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+
+    let mut kandel_strat = KandelStrategy::new(100.0, 1.0, 100.0, Some(1), None, Some(1.02)).unwrap();
+    kandel_strat.set_price_grid(vec![98.0, 100.0, 102.0]);
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+
+    simulator.run_simulation(false, false).unwrap();
+
+    // The ask is taken at block 1 but cannot be reposted
+    assert_eq!(simulator.gas_exhausted_at("kandel"), Some(1));
+    assert!(simulator.is_halted("kandel_strat"));
+    assert!(!simulator.is_halted("arb_strat"));
+    assert_eq!(simulator.gas_exhaustion_events.len(), 1);
+    assert_eq!(simulator.gas_exhaustion_events[0].halted_strategies, vec!["kandel_strat".to_string()]);
+
+    // The provisioned bid stayed on the book and was taken at block 2
+    assert!(simulator.market.bids.is_empty());
+    assert!(simulator.market.asks.is_empty());
+    assert_eq!(kandel_user.lock().unwrap().get_native_balance(), 0.0);
+}
+
+#[test]
+fn test_vaults_segregate_funds() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(3));
+    let user = simulator.add_user("fund".to_string(), 1e18);
+    user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    user.lock().unwrap().add_token_balance("USDC", 5000.0).unwrap();
+
+    let grid = simulator.add_vault("fund", "grid").unwrap();
+    let limit = simulator.add_vault("fund", "limit").unwrap();
+    assert!(matches!(simulator.add_vault("fund", "grid"), Err(LedgerError::VaultExists(_))));
+    assert_eq!(grid.lock().unwrap().id, "fund.grid");
+
+    simulator.allocate("fund", "grid", "WETH", 4.0).unwrap();
+    simulator.allocate("fund", "grid", "USDC", 2000.0).unwrap();
+    simulator.allocate_native("fund", "grid", 1e17).unwrap();
+    simulator.transfer_between_vaults("fund", "grid", "limit", "WETH", 1.0).unwrap();
+    assert!(matches!(
+        simulator.allocate("fund", "limit", "WETH", 100.0),
+        Err(LedgerError::InsufficientBalance { needed: 100.0, available: 6.0, .. })
+    ));
+
+    assert_eq!(
+        simulator.add_vault("fund", "grid.limit").map(|_| ()),
+        Err(LedgerError::InvalidVaultName("grid.limit".to_string()))
+    );
+
+    // A move the vault cannot receive leaves the user's balance untouched
+    grid.lock().unwrap().add_token_balance("DAI", f64::MAX).unwrap();
+    user.lock().unwrap().add_token_balance("DAI", f64::MAX).unwrap();
+    assert!(matches!(
+        simulator.allocate("fund", "grid", "DAI", f64::MAX),
+        Err(LedgerError::BalanceOverflow { .. })
+    ));
+    assert_eq!(user.lock().unwrap().get_token_balance("DAI"), f64::MAX);
+
+    assert_eq!(user.lock().unwrap().get_token_balance("WETH"), 6.0);
+    assert_eq!(grid.lock().unwrap().get_token_balance("WETH"), 3.0);
+    assert_eq!(limit.lock().unwrap().get_token_balance("WETH"), 1.0);
+
+    // The limit order vault has no native, its strategy halts without touching the grid's
+    let strategy = LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask);
+    simulator.add_strategy("limit".to_string(), Box::new(strategy));
+    simulator.assign_strategy_to_vault("fund", "limit", "limit").unwrap();
+    assert_eq!(
+        simulator.assign_strategy("fund", "limit"),
+        Err(Error::Strategy(StrategyError::AlreadyAssigned("limit".to_string())))
+    );
+
+    simulator.run_simulation(false, false).unwrap();
+
+    assert!(simulator.is_halted("limit"));
+    assert_eq!(simulator.gas_exhausted_at("fund.limit"), Some(0));
+    assert_eq!(grid.lock().unwrap().get_native_balance(), 1e17);
+    assert_eq!(user.lock().unwrap().get_native_balance(), 1e18 - 1e17);
+
+    let vault_ids: Vec<String> = simulator.vault_metrics("fund").into_iter().map(|(id, _)| id).collect();
+    assert_eq!(vault_ids, vec!["fund.grid".to_string(), "fund.limit".to_string()]);
+}
+
+#[test]
+fn test_out_of_gas_only_halts_the_dry_account() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(3));
+    let user = simulator.add_user("fund".to_string(), 1e17);
+    user.lock().unwrap().add_token_balance("WETH", 2.0).unwrap();
+    simulator.add_vault("fund", "grid").unwrap();
+    simulator.allocate("fund", "grid", "WETH", 1.0).unwrap();
+    simulator.allocate_native("fund", "grid", 1e17).unwrap();
+
+    // The user gave all its native to the vault
+    simulator.add_strategy("main".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask)));
+    simulator.add_strategy("vault".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask)));
+    simulator.assign_strategy("fund", "main").unwrap();
+    simulator.assign_strategy_to_vault("fund", "grid", "vault").unwrap();
+
+    simulator.run_simulation(false, false).unwrap();
+
+    assert!(simulator.is_halted("main"));
+    assert!(!simulator.is_halted("vault"));
+    assert_eq!(simulator.gas_exhausted_at("fund"), Some(0));
+    assert_eq!(simulator.gas_exhausted_at("fund.grid"), None);
+    assert_eq!(simulator.market.asks.len(), 1);
+}
+
+#[test]
+fn test_bounties_reconcile_with_the_pnl() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, vec![PricePoint::new(0, 100.0), PricePoint::new(1, 103.0)]);
+
+    // The maker offers WETH it does not hold, the arb gets a bounty instead
+    simulator.add_user("maker".to_string(), 1e18);
+    let arb = simulator.add_user("arb".to_string(), 1e18);
+    arb.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
+    arb.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    simulator.add_strategy("limit".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask)));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // 100k gasreq at 1 gwei, paid at the native price of block 1
+    let bounty = 100_000.0 * 1e-9 * 103.0;
+    for (account_id, bounties) in [("maker", -bounty), ("arb", bounty)] {
+        let metrics = &simulator.performance_metrics[account_id];
+        let attribution = &simulator.pnl_attribution[account_id];
+        assert!((metrics.bounties_quote - bounties).abs() < 1e-9);
+        assert_eq!(attribution.bounties, metrics.bounties_quote);
+        assert!((attribution.total() - metrics.total_profit_loss).abs() < 1e-9);
+    }
+}
+
+fn kandel_and_arb_simulator(seed: u64) -> Simulator {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed = vec![
+        PricePoint::new(0, 100.0),
+        PricePoint::new(1, 103.0),
+        PricePoint::new(2, 96.0),
+        PricePoint::new(3, 101.0),
+    ];
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.set_seed(seed);
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    kandel_user.lock().unwrap().add_token_balance("USDC", 2000.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+
+    let mut kandel_strat = KandelStrategy::new(100.0, 1.0, 100.0, Some(1), None, Some(1.02)).unwrap();
+    kandel_strat.set_price_grid(vec![98.0, 100.0, 102.0]);
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator
+}
+
+#[test]
+fn test_runs_are_reproducible() {
+    let mut first = kandel_and_arb_simulator(7);
+    first.run_simulation(false, false).unwrap();
+    let mut second = kandel_and_arb_simulator(7);
+    second.run_simulation(false, false).unwrap();
+
+    assert_eq!(first.run_fingerprint(), second.run_fingerprint());
+
+    // A different final state gives a different fingerprint
+    second.users["arb"].lock().unwrap().add_native(1e12);
+    assert_ne!(first.run_fingerprint(), second.run_fingerprint());
+}
+
+#[test]
+fn test_parameters_are_updated_during_the_run() {
+    let mut simulator = kandel_and_arb_simulator(7);
+    assert_eq!(
+        simulator.strategy_parameters("arb_strat").unwrap()["max_volume_per_trade"],
+        ParameterValue::Float(1000.0)
+    );
+    simulator.run_until(2, false, false).unwrap();
+
+    // The arbitrageur barely trades once its orders are capped
+    let base_volume = |simulator: &Simulator| {
+        simulator.strategies["arb_strat"].metrics().into_iter().find(|(name, _)| name == "base_volume").unwrap().1
+    };
+    simulator.set_strategy_parameter("arb_strat", "max_volume_per_trade", ParameterValue::Float(1e-9)).unwrap();
+    let traded = base_volume(&simulator);
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(simulator.strategy_parameters("arb_strat").unwrap()["max_volume_per_trade"], ParameterValue::Float(1e-9));
+    assert!(base_volume(&simulator) - traded < 1e-8);
+    let mut uninterrupted = kandel_and_arb_simulator(7);
+    uninterrupted.run_simulation(false, false).unwrap();
+    assert!(base_volume(&uninterrupted) - traded > 1e-8);
+
+    // The grid cannot move under offers already on the book
+    assert!(matches!(
+        simulator.set_strategy_parameter("kandel_strat", "gridstep", ParameterValue::Float(1.05)),
+        Err(Error::Strategy(StrategyError::InvalidState(_)))
+    ));
+    assert!(matches!(
+        simulator.set_strategy_parameter("maker", "gridstep", ParameterValue::Float(1.05)),
+        Err(Error::Strategy(StrategyError::UnknownStrategy(id))) if id == "maker"
+    ));
+}
+
+#[test]
+fn test_strategies_run_by_priority_then_registration() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(1));
+    simulator.add_user("bot".to_string(), 1e18);
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    for tag in 0..4 {
+        let strategy = TaggingStrategy { tag, log: Arc::clone(&log) };
+        simulator.add_strategy(format!("s{}", tag), Box::new(strategy));
+    }
+    simulator.assign_strategy("bot", "s0").unwrap();
+    simulator.assign_strategy_with_priority("bot", "s1", 5).unwrap();
+    simulator.assign_strategy_with_priority("bot", "s2", -1).unwrap();
+    simulator.assign_strategy("bot", "s3").unwrap();
+
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(*log.lock().unwrap(), vec![2, 0, 3, 1]);
+}
+
+#[test]
+fn test_metrics_are_marked_to_market() {
+    let mut market = Market::new("WETH".to_string(), "USDC".to_string());
+    market.fee_bps = 10.0;
+    let price_feed = vec![
+        PricePoint::new(0, 95.0),
+        PricePoint::new(1, 105.0),
+        PricePoint::new(2, 110.0),
+    ];
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.clear_sinks();
+    let recorder = MemoryRecorder::new();
+    simulator.add_sink(Box::new(recorder.clone()));
+
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("WETH", 10.0).unwrap();
+    maker.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    let arb = simulator.add_user("arb".to_string(), 1e18);
+    arb.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
+
+    // The ask at 100 is posted at block 1 and taken right away by the arbitrageur
+    simulator.add_strategy("limit".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask)));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    let metrics = &simulator.performance_metrics["maker"];
+    assert_eq!(metrics.total_trades, 1);
+    assert_eq!(metrics.total_volume, 100.0);
+    assert_eq!(metrics.initial_balance, 10.0 * 95.0 + 1000.0);
+    assert_eq!(metrics.current_balance, 9.0 * 110.0 + 1100.0);
+    assert_eq!(metrics.hodl_value, 10.0 * 110.0 + 1000.0);
+    let rebalanced = 1950.0 * (1.0 + 0.5 * (105.0 / 95.0 - 1.0)) * (1.0 + 0.5 * (110.0 / 105.0 - 1.0));
+    assert!((metrics.rebalanced_value - rebalanced).abs() < 1e-9);
+    assert!((metrics.realized_pnl - 5.0).abs() < 1e-9);
+    assert!((metrics.unrealized_pnl - 9.0 * 15.0).abs() < 1e-9);
+    assert!((metrics.gas_spent_quote - 0.0002 * 105.0).abs() < 1e-9);
+    assert!((metrics.total_profit_loss - (2090.0 - 1950.0 - 0.0002 * 105.0)).abs() < 1e-9);
+
+    // The taker fee is paid by the arbitrageur only
+    assert_eq!(metrics.fees_paid, 0.0);
+    assert!((simulator.performance_metrics["arb"].fees_paid - 0.1).abs() < 1e-12);
+    assert_eq!(simulator.performance_metrics["arb"].total_trades, 1);
+
+    // Picked off 5 below the reference price, the rest is the WETH held appreciating
+    let attribution = &simulator.pnl_attribution["maker"];
+    assert!((attribution.spread_capture + 5.0).abs() < 1e-9);
+    assert!((attribution.inventory_drift - 145.0).abs() < 1e-9);
+    assert!((attribution.total() - metrics.total_profit_loss).abs() < 1e-9);
+    assert!((simulator.pnl_attribution["arb"].taker_edge - 4.9).abs() < 1e-9);
+    let strategy_ids: Vec<&str> = simulator.strategy_pnl.iter().map(|pnl| pnl.strategy_id.as_str()).collect();
+    assert_eq!(strategy_ids, vec!["limit", "arb_strat"]);
+    assert_eq!(simulator.strategy_pnl[0].attribution, *attribution);
+    assert_eq!(simulator.strategy_pnl[1].metrics, vec![("fills".to_string(), 1.0), ("base_volume".to_string(), 1.0)]);
+
+    // Every block once, for both accounts
+    let recording = recorder.recording();
+    let recording = recording.lock().unwrap();
+    assert_eq!(recording.metrics.len(), 6);
+    let last = recording.metrics.iter().rev().find(|record| record.account_id == "maker").unwrap();
+    assert_eq!(last.block, 2);
+    assert_eq!(last.metrics.current_balance, metrics.current_balance);
+}
+
+#[test]
+fn test_triggers_see_earlier_strategies_of_the_block() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, vec![PricePoint::new(0, 100.0), PricePoint::new(1, 100.0)]);
+    simulator.add_user("maker".to_string(), 1e18);
+    simulator.add_user("watcher".to_string(), 1e18);
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let poster = SubscribingStrategy { triggers: vec![Trigger::Timer(1)], log: Arc::new(Mutex::new(Vec::new())) };
+    let watcher = SubscribingStrategy { triggers: vec![Trigger::BookChanged], log: Arc::clone(&log) };
+    simulator.add_strategy("poster".to_string(), Box::new(poster));
+    simulator.add_strategy("watcher".to_string(), Box::new(watcher));
+    simulator.assign_strategy("maker", "poster").unwrap();
+    simulator.assign_strategy("watcher", "watcher").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // The ask posted at block 1 wakes the watcher up in the same block
+    let blocks: Vec<u64> = log.lock().unwrap().iter().map(|(block, _)| *block).collect();
+    assert_eq!(blocks, vec![0, 1]);
+}
+
+#[test]
+fn test_strategies_run_on_their_triggers() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed: Vec<PricePoint> = [100.0, 100.0, 101.0, 106.0, 106.0, 106.0, 106.0]
+        .iter()
+        .enumerate()
+        .map(|(block, price)| PricePoint::new(block as u64, *price))
+        .collect();
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.clear_sinks();
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
+    simulator.add_user("arb".to_string(), 1e18);
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let triggers = vec![Trigger::Timer(0), Trigger::EveryNBlocks(3), Trigger::PriceMove(0.05), Trigger::OfferFilled];
+    simulator.add_strategy("subscriber".to_string(), Box::new(SubscribingStrategy { triggers, log: Arc::clone(&log) }));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("maker", "subscriber").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // The ask posted at block 0 is taken at block 3, after the subscriber ran
+    let log = log.lock().unwrap();
+    let blocks: Vec<u64> = log.iter().map(|(block, _)| *block).collect();
+    assert_eq!(blocks, vec![0, 3, 4, 6]);
+    assert_eq!(log[0].1, vec![Event::Timer(0), Event::Interval(3), Event::NewPrice]);
+    assert_eq!(log[1].1, vec![Event::Interval(3), Event::PriceMoved { from: 100.0, to: 106.0 }]);
+    match &log[2].1[..] {
+        [Event::OfferFilled(fill)] => {
+            assert_eq!(fill.maker_id, "maker");
+            assert_eq!(fill.taker_id, "arb");
+            assert_eq!(fill.price, 102.0);
+        }
+        events => panic!("Unexpected events {:?}", events),
+    }
+    assert_eq!(log[3].1, vec![Event::Interval(3)]);
+
+    // A placed Kandel has nothing left to do outside of its post hooks
+    let mut simulator = kandel_and_arb_simulator(0);
+    simulator.clear_sinks();
+    assert_eq!(simulator.strategies["kandel_strat"].triggers(), vec![Trigger::EveryBlock]);
+    simulator.run_simulation(false, false).unwrap();
+    assert!(simulator.strategies["kandel_strat"].triggers().is_empty());
+}
+
+#[test]
+fn test_lifecycle_hooks() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, vec![PricePoint::new(0, 100.0), PricePoint::new(1, 100.0), PricePoint::new(2, 110.0)]);
+    simulator.clear_sinks();
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("USDC", 1000.0).unwrap();
+    let holder = simulator.add_user("holder".to_string(), 1e18);
+    holder.lock().unwrap().add_token_balance("WETH", 2.0).unwrap();
+
+    simulator.add_strategy("bid".to_string(), Box::new(LimitOrderStrategy::new(100.0, 5.0, OfferSide::Bid)));
+    simulator.add_strategy("unwind".to_string(), Box::new(UnwindingStrategy::default()));
+    simulator.assign_strategy("maker", "bid").unwrap();
+    simulator.assign_strategy("holder", "unwind").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    // The holder sold its 2 WETH into the bid after the last block
+    let holder = holder.lock().unwrap();
+    assert_eq!(holder.get_token_balance("WETH"), 0.0);
+    assert_eq!(holder.get_token_balance("USDC"), 200.0);
+    let metrics = &simulator.performance_metrics["holder"];
+    assert_eq!(metrics.total_trades, 1);
+    assert_eq!(metrics.current_balance, 200.0);
+    assert_eq!(simulator.equity_curves["holder"].len(), 3);
+
+    let report = &simulator.strategy_pnl[1];
+    assert_eq!(report.strategy_id, "unwind");
+    assert_eq!(report.metrics, vec![("blocks".to_string(), 3.0), ("fills".to_string(), 1.0)]);
+}
+
+// Logs every notification as a line
+struct LoggingObserver {
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Observer for LoggingObserver {
+    fn on_step_start(&mut self, block: u64, _price_point: &PricePoint, _simulator: &Simulator) {
+        self.log.lock().unwrap().push(format!("start {}", block));
+    }
+    fn on_step_end(&mut self, block: u64, _price_point: &PricePoint, simulator: &Simulator) {
+        let curve_len = simulator.equity_curves["arb"].len();
+        self.log.lock().unwrap().push(format!("end {} {}", block, curve_len));
+    }
+    fn on_strategy_action(&mut self, action: &StrategyAction, _simulator: &Simulator) {
+        let status = if action.result.is_ok() { "ok" } else { "failed" };
+        self.log.lock().unwrap().push(format!("{} {} {}", action.hook, action.strategy_id, status));
+    }
+    fn on_fill(&mut self, fill: &Fill, _simulator: &Simulator) {
+        self.log.lock().unwrap().push(format!("fill {} {}", fill.offer_id, fill.taker_id));
+    }
+    fn on_offer_write(&mut self, write: &OfferWrite, _simulator: &Simulator) {
+        self.log.lock().unwrap().push(format!("{:?} {} {}", write.kind, write.offer_id, write.price));
+    }
+    fn on_error(&mut self, error: &ErrorEvent, _simulator: &Simulator) {
+        self.log.lock().unwrap().push(format!("error {:?} {}", error.strategy_id, error.error));
+    }
+}
+
+// Fails on its first run
+struct FailingStrategy;
+
+impl Strategy for FailingStrategy {
+    fn name(&self) -> &str {
+        "FailingStrategy"
+    }
+    fn description(&self) -> &str {
+        "FailingStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Err(StrategyError::UnknownParameter("size".to_string()).into())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_observers_follow_the_run() {
+    let mut simulator = kandel_and_arb_simulator(0);
+    simulator.clear_sinks();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    simulator.add_observer(Box::new(LoggingObserver { log: Arc::clone(&log) }));
+    simulator.run_simulation(false, false).unwrap();
+
+    let log = log.lock().unwrap();
+    let lines: Vec<&str> = log.iter().map(String::as_str).collect();
+    // The start hooks run before the first block, the grid is written before
+    // the Kandel's action is notified
+    assert_eq!(
+        lines[..7],
+        [
+            "on_start kandel_strat ok",
+            "on_start arb_strat ok",
+            "start 0",
+            "Post 1 98",
+            "Post 2 102",
+            "execute kandel_strat ok",
+            "execute arb_strat ok",
+        ]
+    );
+    // Each step ends after the equity point of its block is recorded
+    let ends: Vec<&str> = lines.iter().copied().filter(|line| line.starts_with("end")).collect();
+    assert_eq!(ends, ["end 0 1", "end 1 2", "end 2 3", "end 3 4"]);
+    let fills = lines.iter().filter(|line| line.starts_with("fill") && line.ends_with("arb")).count();
+    assert!(fills > 0);
+    // Each post hook's repost follows the fill that called it
+    let block_2 = lines.iter().position(|line| *line == "start 2").unwrap();
+    assert_eq!(lines[block_2 + 1..block_2 + 5], ["fill 3 arb", "Post 4 102", "fill 1 arb", "Post 5 100"]);
+    assert_eq!(fills as u64, simulator.performance_metrics["arb"].total_trades);
+    assert!(!lines.iter().any(|line| line.starts_with("error")));
+    // Neither strategy asked for `on_block`
+    assert!(!lines.iter().any(|line| line.starts_with("on_block")));
+
+    // A failing strategy is reported before the run stops
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, rising_feed(3));
+    simulator.clear_sinks();
+    simulator.add_user("bot".to_string(), 1e18);
+    simulator.add_strategy("failing".to_string(), Box::new(FailingStrategy));
+    simulator.assign_strategy("bot", "failing").unwrap();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    simulator.add_observer(Box::new(LoggingObserver { log: Arc::clone(&log) }));
+    assert_eq!(
+        simulator.run_simulation(false, false),
+        Err(Error::Strategy(StrategyError::UnknownParameter("size".to_string())))
+    );
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["on_start failing ok", "start 0", "error Some(\"failing\") Unknown parameter size", "execute failing failed"]
+    );
+}
+
+#[test]
+fn test_strategies_see_the_block_time() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let price_feed: Vec<PricePoint> = [100.0, 101.0, 102.0]
+        .iter()
+        .enumerate()
+        .map(|(block, price)| PricePoint::new(block as u64, *price))
+        .collect();
+    let mut simulator = Simulator::new(market, price_feed);
+    simulator.set_block_time(1_700_000_000, 2);
+    simulator.add_user("clock".to_string(), 1e18);
+    let timestamps = Arc::new(Mutex::new(Vec::new()));
+    simulator.add_strategy("clock".to_string(), Box::new(ClockStrategy { timestamps: Arc::clone(&timestamps) }));
+    simulator.assign_strategy("clock", "clock").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    assert_eq!(*timestamps.lock().unwrap(), vec![1_700_000_000, 1_700_000_002, 1_700_000_004]);
+    assert_eq!(simulator.market.timestamp, 1_700_000_004);
+}
+
+#[test]
+fn test_arbitrageur_hedges_at_the_reference_price() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, vec![PricePoint::new(0, 95.0), PricePoint::new(1, 110.0)]);
+    let maker = simulator.add_user("maker".to_string(), 1e18);
+    maker.lock().unwrap().add_token_balance("WETH", 1.0).unwrap();
+    let arb = simulator.add_user("arb".to_string(), 1e18);
+
+    // The ask of 1 WETH at 100 is bought and sold outside at 110, without any inventory
+    simulator.add_strategy("limit".to_string(), Box::new(LimitOrderStrategy::new(100.0, 1.0, OfferSide::Ask)));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("maker", "limit").unwrap();
+    simulator.assign_strategy("arb", "arb_strat").unwrap();
+    simulator.run_simulation(false, false).unwrap();
+
+    let arb = arb.lock().unwrap();
+    assert_eq!(arb.get_token_balance("USDC"), 10.0);
+    assert_eq!(arb.get_token_balance("WETH"), 0.0);
+    assert_eq!(simulator.performance_metrics["arb"].total_trades, 1);
+    assert_eq!(maker.lock().unwrap().get_token_balance("USDC"), 100.0);
+}
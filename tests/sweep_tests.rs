@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use mgv_simulator::error_lib::{ConfigError, Error, StrategyError};
+use mgv_simulator::params_lib::{ParameterSpec, ParameterValue};
+use mgv_simulator::mgv_lib::{Market, Offer};
+use mgv_simulator::simu_lib::{PricePoint, Simulator};
+use mgv_simulator::strats::arbitrage::ArbitrageStrategy;
+use mgv_simulator::strats::kandel::KandelStrategy;
+use mgv_simulator::strats_lib::{Strategy, StrategyContext};
+use mgv_simulator::sweep_lib::{ParameterGrid, ParameterSet, Sweep};
+
+
+fn price_feed() -> Vec<PricePoint> {
+    [100.0, 103.0, 96.0, 101.0, 104.0, 97.0]
+        .iter()
+        .enumerate()
+        .map(|(block, price)| PricePoint::new(block as u64, *price))
+        .collect()
+}
+
+fn kandel_simulator(parameters: &ParameterSet, price_feed: Arc<[PricePoint]>) -> Result<Simulator, Error> {
+    let parameter = |name: &str| parameters.get(name).ok_or_else(|| ConfigError::invalid_parameter(name, "missing"));
+    let capital = parameter("capital")?;
+    let gridstep = parameter("gridstep")?;
+    let n_points = parameter("n_points")? as usize;
+
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, price_feed);
+    let kandel_user = simulator.add_user("kandel".to_string(), 1e18);
+    kandel_user.lock().unwrap().add_token_balance("WETH", capital / 200.0)?;
+    kandel_user.lock().unwrap().add_token_balance("USDC", capital / 2.0)?;
+    simulator.add_user("arb".to_string(), 1e18);
+
+    // Asks are sized in base from the second argument, bids in quote from the third
+    let kandel_strat = KandelStrategy::new(100.0, capital / 200.0, capital / 2.0, Some(n_points), None, Some(gridstep))?;
+    simulator.add_strategy("kandel_strat".to_string(), Box::new(kandel_strat));
+    simulator.add_strategy("arb_strat".to_string(), Box::new(ArbitrageStrategy::new(0.0, 1000.0)));
+    simulator.assign_strategy("kandel", "kandel_strat")?;
+    simulator.assign_strategy("arb", "arb_strat")?;
+    Ok(simulator)
+}
+
+// Only takes its level as an int, without going through `check_parameter`
+struct LevelStrategy {
+    level: i64,
+}
+
+impl Strategy for LevelStrategy {
+    fn name(&self) -> &str {
+        "LevelStrategy"
+    }
+    fn description(&self) -> &str {
+        "LevelStrategy"
+    }
+    fn execute(&mut self, _price_point: &PricePoint, _context: &mut StrategyContext) -> Result<(), Error> {
+        Ok(())
+    }
+    fn post_hook(&mut self, _context: &mut StrategyContext, _offer: &Offer) -> Result<(), Error> {
+        Ok(())
+    }
+    fn parameter_schema(&self) -> Vec<ParameterSpec> {
+        vec![ParameterSpec::int("level", "Any level")]
+    }
+    fn get_parameter(&self, name: &str) -> Option<ParameterValue> {
+        (name == "level").then_some(ParameterValue::Int(self.level))
+    }
+    fn set_parameter(&mut self, name: &str, value: ParameterValue) -> Result<(), Error> {
+        match (name, value) {
+            ("level", ParameterValue::Int(level)) => self.level = level,
+            (name, value) => return Err(ConfigError::invalid_parameter(name, &format!("unexpected {}", value)).into()),
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_grid_combinations() {
+    let grid = ParameterGrid::new()
+        .with("gridstep", vec![1.01, 1.02])
+        .with("n_points", vec![1.0, 2.0, 3.0]);
+    let combinations = grid.combinations();
+    assert_eq!(combinations.len(), 6);
+    assert_eq!(combinations[1].to_string(), "gridstep=1.01 n_points=2");
+    assert_eq!(combinations[5].get("gridstep"), Some(1.02));
+    assert_eq!(combinations[5].get("capital"), None);
+}
+
+#[test]
+fn test_sweep_runs_every_combination() {
+    let grid = ParameterGrid::new()
+        .with("capital", vec![0.0, 2000.0])
+        .with("gridstep", vec![1.01, 1.02])
+        .with("n_points", vec![1.0, 2.0]);
+    let table = Sweep::new(grid.clone(), price_feed()).with_threads(4).run(kandel_simulator).unwrap();
+
+    // No capital is rejected by the Kandel, the other runs have a row per account
+    assert_eq!(table.failures.len(), 4);
+    assert!(table.failures.iter().all(|failure| failure.parameters.get("capital") == Some(0.0)));
+    // Kandel refuses to start without quote
+    assert!(table.failures.iter().all(|failure| matches!(
+        &failure.reason,
+        Error::Config(ConfigError::InvalidParameter { name, .. }) if name == "initial_quote"
+    )));
+    assert_eq!(table.rows.len(), 8);
+    let runs: Vec<usize> = table.rows.iter().map(|row| row.run).collect();
+    assert_eq!(runs, vec![4, 4, 5, 5, 6, 6, 7, 7]);
+    assert_eq!(table.ranked_by("kandel", |row| row.pnl).len(), 4);
+
+    // Running on a single thread gives the same results
+    let sequential = Sweep::new(grid, price_feed()).with_threads(1).run(kandel_simulator).unwrap();
+    assert_eq!(sequential.rows, table.rows);
+
+    let path = std::env::temp_dir().join("mgv_sweep_tests").join("sweep.csv");
+    table.write_csv(&path).unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    assert!(csv.starts_with("run,capital,gridstep,n_points,account,final_value"));
+    assert_eq!(csv.lines().count(), 9);
+}
+
+#[test]
+fn test_sweep_sets_any_strategy_parameter() {
+    let build = |parameters: &ParameterSet, price_feed: Arc<[PricePoint]>| {
+        let base = ParameterSet { values: vec![("capital".to_string(), 2000.0), ("gridstep".to_string(), 1.02), ("n_points".to_string(), 2.0)] };
+        let mut simulator = kandel_simulator(&base, price_feed)?;
+        simulator.apply_parameters(parameters)?;
+        Ok(simulator)
+    };
+    let grid = ParameterGrid::new()
+        .with("kandel_strat.gridstep", vec![1.01, 1.03])
+        .with("kandel_strat.n_points", vec![1.0, 3.0])
+        .with("arb_strat.max_volume_per_trade", vec![0.5]);
+    let table = Sweep::new(grid, price_feed()).with_threads(2).run(build).unwrap();
+    assert!(table.failures.is_empty());
+
+    // Same as building the Kandel with those values in the first place
+    let row = table.rows.iter().find(|row| row.run == 3 && row.account_id == "kandel").unwrap();
+    let direct = ParameterSet { values: vec![("capital".to_string(), 2000.0), ("gridstep".to_string(), 1.03), ("n_points".to_string(), 3.0)] };
+    let mut simulator = kandel_simulator(&direct, price_feed().into()).unwrap();
+    simulator.set_strategy_parameter("arb_strat", "max_volume_per_trade", ParameterValue::Float(0.5)).unwrap();
+    simulator.run_simulation(false, false).unwrap();
+    assert_eq!(simulator.run_fingerprint(), row.fingerprint);
+
+    let unknown = ParameterGrid::new().with("arb_strat.spread", vec![0.1]);
+    let table = Sweep::new(unknown, price_feed()).run(build).unwrap();
+    assert!(matches!(
+        &table.failures[0].reason,
+        Error::Strategy(StrategyError::UnknownParameter(name)) if name == "spread"
+    ));
+}
+
+#[test]
+fn test_swept_values_take_the_kind_of_the_parameter() {
+    let market = Market::new("WETH".to_string(), "USDC".to_string());
+    let mut simulator = Simulator::new(market, price_feed());
+    simulator.add_strategy("levels".to_string(), Box::new(LevelStrategy { level: 0 }));
+
+    let integral = ParameterSet { values: vec![("levels.level".to_string(), 3.0)] };
+    simulator.apply_parameters(&integral).unwrap();
+    assert_eq!(simulator.strategies["levels"].get_parameter("level"), Some(ParameterValue::Int(3)));
+
+    let fractional = ParameterSet { values: vec![("levels.level".to_string(), 2.5)] };
+    assert_eq!(
+        simulator.apply_parameters(&fractional),
+        Err(Error::Config(ConfigError::invalid_parameter("level", "expected int, got 2.5")))
+    );
+    assert_eq!(simulator.strategies["levels"].get_parameter("level"), Some(ParameterValue::Int(3)));
+}
@@ -1,4 +1,5 @@
 use mgv_simulator::chain_lib;  // Changed from 'use crate::chain_lib'
+use mgv_simulator::error_lib::LedgerError;
 
 #[test]
 fn test_native_token_operations() {
@@ -16,7 +17,10 @@ fn test_native_token_operations() {
     assert_eq!(alice.get_native_balance(), 1200.0);
     
     // Test spending more than balance
-    assert!(alice.spend_native(2000.0).is_err());
+    assert_eq!(
+        alice.spend_native(2000.0),
+        Err(LedgerError::InsufficientNative { account_id: "alice".to_string(), needed: 2000.0, available: 1200.0 })
+    );
 }
 
 #[test]
@@ -24,7 +28,7 @@ fn test_token_operations() {
     let mut alice = chain_lib::User::new("alice".to_string(), 1000.0);
     
     // Test USDC operations
-    alice.add_token_balance("USDC", 1000.0);
+    alice.add_token_balance("USDC", 1000.0).unwrap();
     assert_eq!(alice.get_token_balance("USDC"), 1000.0);
     
     assert!(alice.spend_token_balance("USDC", 500.0).is_ok());
@@ -34,5 +38,26 @@ fn test_token_operations() {
     assert!(alice.spend_token_balance("WETH", 10.0).is_err());
     
     // Test spending more than balance
-    assert!(alice.spend_token_balance("USDC", 1000.0).is_err());
-}
\ No newline at end of file
+    assert_eq!(
+        alice.spend_token_balance("USDC", 1000.0),
+        Err(LedgerError::InsufficientBalance {
+            account_id: "alice".to_string(),
+            token: "USDC".to_string(),
+            needed: 1000.0,
+            available: 500.0,
+        })
+    );
+}
+
+#[test]
+fn test_gas_payment_tracking() {
+    let mut alice = chain_lib::User::new("alice".to_string(), 1e17);
+
+    assert!(alice.pay_gas(2e14).is_ok());
+    assert_eq!(alice.get_native_balance(), 1e17 - 2e14);
+    assert_eq!(alice.gas_spent, 2e14);
+
+    // A failed payment is not booked
+    assert!(matches!(alice.pay_gas(1e18), Err(LedgerError::InsufficientNative { .. })));
+    assert_eq!(alice.gas_spent, 2e14);
+}